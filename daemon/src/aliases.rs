@@ -0,0 +1,110 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use regex::Regex;
+use serde_derive::Deserialize;
+
+use crate::digest::format_duration;
+
+// Alias rules file kept next to the screen time csv, shared with the desktop app.
+pub const ALIASES_FILE_NAME: &str = "aliases.json";
+
+// Maps raw application names, such as "code" and "code-oss", to one name.
+// The file looks like {"aliases": {"Code": ["(?i)code", "code-oss"]}}, each pattern must match the whole raw name.
+#[derive(Debug, Default)]
+pub struct AliasRules {
+    rules: Vec<(Regex, String)>,
+}
+
+#[derive(Deserialize)]
+struct AliasesConfig {
+    aliases: HashMap<String, Vec<String>>,
+}
+
+impl AliasRules {
+    //No file means no aliases.
+    pub fn load(aliases_path: &Path) -> Result<AliasRules, Box<dyn Error>> {
+        if !aliases_path.exists() {
+            return Ok(AliasRules::default());
+        }
+        AliasRules::parse(&fs::read_to_string(aliases_path)?)
+    }
+
+    pub fn parse(aliases_json: &str) -> Result<AliasRules, Box<dyn Error>> {
+        let config: AliasesConfig = serde_json::from_str(aliases_json)?;
+        let mut rules = Vec::new();
+        for (name, patterns) in config.aliases {
+            for pattern in patterns {
+                rules.push((Regex::new(&format!("^(?:{})$", pattern))?, name.clone()));
+            }
+        }
+        // Apply the rules in the same order every time
+        rules.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        Ok(AliasRules { rules })
+    }
+
+    pub fn resolve<'a>(&'a self, app_name: &'a str) -> &'a str {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(app_name))
+            .map_or(app_name, |(_, name)| name.as_str())
+    }
+}
+
+// List application names with their totals, longest first, for the NAMES socket request.
+pub fn format_app_totals(app_totals: &HashMap<String, Duration>) -> String {
+    let mut app_totals: Vec<(&String, &Duration)> = app_totals.iter().collect();
+    app_totals.sort_by_key(|(app_name, duration)| (Reverse(**duration), app_name.as_str()));
+    app_totals
+        .iter()
+        .map(|(app_name, duration)| format!("{}: {}", app_name, format_duration(**duration)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_resolve_raw_names() {
+        let alias_rules = AliasRules::parse(
+            r#"{"aliases": {"Code": ["(?i)code", "code-oss"], "Google Chrome": ["(?i)google-chrome"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(alias_rules.resolve("code"), "Code");
+        assert_eq!(alias_rules.resolve("Code"), "Code");
+        assert_eq!(alias_rules.resolve("code-oss"), "Code");
+        assert_eq!(alias_rules.resolve("Google-chrome"), "Google Chrome");
+        //Patterns match the whole name
+        assert_eq!(alias_rules.resolve("vscode"), "vscode");
+    }
+
+    #[test]
+    fn invalid_aliases_are_rejected() {
+        assert!(AliasRules::parse(r#"{"aliases": {"Code": ["("]}}"#).is_err());
+        assert!(AliasRules::parse(r#"{"Code": "code"}"#).is_err());
+    }
+
+    #[test]
+    fn missing_file_has_no_aliases() {
+        let alias_rules = AliasRules::load(Path::new("/does/not/exist/aliases.json")).unwrap();
+        assert_eq!(alias_rules.resolve("code"), "code");
+    }
+
+    #[test]
+    fn format_totals_longest_first() {
+        let mut app_totals = HashMap::new();
+        app_totals.insert("code".to_string(), Duration::from_secs(30 * 60));
+        app_totals.insert("Code".to_string(), Duration::from_secs(90 * 60));
+        app_totals.insert("code-oss".to_string(), Duration::from_secs(30 * 60));
+        assert_eq!(
+            format_app_totals(&app_totals),
+            "Code: 1h 30m\ncode: 30m\ncode-oss: 30m"
+        );
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+use csv::ReaderBuilder;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::{Archive, Builder, Header};
+
+use crate::aliases::ALIASES_FILE_NAME;
+use crate::categories::CATEGORIES_FILE_NAME;
+use crate::csv_writer::{write_atomically, Row};
+use crate::encryption::{Cipher, ENCRYPTED_FILE_MAGIC};
+use crate::focus::FOCUS_SESSIONS_FILE_NAME;
+use crate::goals::GOAL_HISTORY_FILE_NAME;
+use crate::schema::{migrate_csv, schema_version, SCHEMA_VERSION};
+
+// Snapshots are named screen_timed_backup_<local time>.tar.gz, so sorting them by name sorts them by age.
+const BACKUP_FILE_PREFIX: &str = "screen_timed_backup_";
+const BACKUP_FILE_EXTENSION: &str = ".tar.gz";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+// Config and history kept next to the data file, included in snapshots if it exists.
+const CONFIG_FILE_NAMES: [&str; 5] = [
+    ".env",
+    ALIASES_FILE_NAME,
+    CATEGORIES_FILE_NAME,
+    GOAL_HISTORY_FILE_NAME,
+    FOCUS_SESSIONS_FILE_NAME,
+];
+pub const DEFAULT_BACKUP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_BACKUP_RETENTION: usize = 7;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupSchedule {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    // How many snapshots to keep, the oldest are deleted first
+    pub retention: usize,
+}
+
+// Sent from the socket listener to the run() loop, which handles it between writes.
+// The reply is the message for the socket client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackupRequest {
+    Backup,
+    Restore(PathBuf),
+    // Rewrites the data file without its malformed rows
+    Repair,
+}
+
+pub type BackupRequestSender = Sender<(BackupRequest, Sender<String>)>;
+
+pub fn parse_backup_interval(hours: &str) -> Result<Duration, String> {
+    match hours.parse::<u64>() {
+        Ok(hours) if hours > 0 => Ok(Duration::from_secs(hours * 60 * 60)),
+        _ => Err(format!("{} should be a number of hours above 0", hours)),
+    }
+}
+
+pub fn parse_backup_retention(count: &str) -> Result<usize, String> {
+    match count.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("{} should be a number of snapshots above 0", count)),
+    }
+}
+
+// Takes a snapshot once the interval has passed since the newest one, including snapshots from before a restart.
+pub struct BackupScheduler {
+    schedule: BackupSchedule,
+    next_backup: SystemTime,
+}
+
+impl BackupScheduler {
+    pub fn new(schedule: BackupSchedule, now: SystemTime) -> BackupScheduler {
+        let newest_backup = list_backups(&schedule.dir)
+            .ok()
+            .and_then(|backups| backups.last().cloned())
+            .and_then(|newest| fs::metadata(newest).and_then(|meta| meta.modified()).ok());
+        let next_backup = match newest_backup {
+            Some(newest_backup) => newest_backup + schedule.interval,
+            None => now,
+        };
+        BackupScheduler {
+            schedule,
+            next_backup,
+        }
+    }
+
+    pub fn schedule(&self) -> &BackupSchedule {
+        &self.schedule
+    }
+
+    pub fn due(&mut self, now: SystemTime) -> bool {
+        if now < self.next_backup {
+            return false;
+        }
+        self.next_backup = now + self.schedule.interval;
+        true
+    }
+}
+
+// The snapshots in the directory, oldest first.
+pub fn list_backups(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        if file_name.starts_with(BACKUP_FILE_PREFIX) && file_name.ends_with(BACKUP_FILE_EXTENSION) {
+            backups.push(path);
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+// The data file as it is on disk, still encrypted if it is, and the config files next to it.
+fn snapshot_files(csv_name: &String) -> Vec<(String, PathBuf)> {
+    let csv_path = Path::new(csv_name);
+    let mut files = Vec::new();
+    let csv_file_name = csv_path.file_name().unwrap_or_default().to_string_lossy();
+    files.push((csv_file_name.to_string(), csv_path.to_path_buf()));
+    for config_file_name in CONFIG_FILE_NAMES {
+        files.push((
+            config_file_name.to_string(),
+            csv_path.with_file_name(config_file_name),
+        ));
+    }
+    files
+}
+
+//Write a compressed snapshot of the data file and config to the directory, returning its path.
+pub fn create_backup(
+    dir: &Path,
+    csv_name: &String,
+    now: DateTime<Local>,
+) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let mut archive = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for (file_name, path) in snapshot_files(csv_name) {
+        if !path.exists() {
+            continue;
+        }
+        let contents = fs::read(&path)?;
+        let mut header = Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        // The snapshot holds the data and possibly a passphrase, so only the user can read it
+        header.set_mode(0o600);
+        header.set_mtime(now.timestamp().max(0) as u64);
+        header.set_cksum();
+        archive.append_data(&mut header, file_name, contents.as_slice())?;
+    }
+    let compressed = archive.into_inner()?.finish()?;
+    let backup_path = dir.join(format!(
+        "{}{}{}",
+        BACKUP_FILE_PREFIX,
+        now.format(BACKUP_TIME_FORMAT),
+        BACKUP_FILE_EXTENSION
+    ));
+    write_atomically(&backup_path.to_string_lossy().to_string(), &compressed)?;
+    set_owner_only_permissions(&backup_path)?;
+    Ok(backup_path)
+}
+
+#[cfg(unix)]
+fn set_owner_only_permissions(path: &Path) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_owner_only_permissions(_path: &Path) -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
+
+//Delete the oldest snapshots beyond the retention count, returning the deleted paths.
+pub fn prune_backups(dir: &Path, retention: usize) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let backups = list_backups(dir)?;
+    let excess = backups.len().saturating_sub(retention);
+    let pruned: Vec<PathBuf> = backups.into_iter().take(excess).collect();
+    for backup in &pruned {
+        fs::remove_file(backup)?;
+    }
+    Ok(pruned)
+}
+
+// Take a snapshot and delete the ones beyond the retention count.
+pub fn run_backup(schedule: &BackupSchedule, csv_name: &String) -> Result<PathBuf, Box<dyn Error>> {
+    let backup_path = create_backup(&schedule.dir, csv_name, Local::now())?;
+    println!("Backed up to {}", backup_path.display());
+    for pruned in prune_backups(&schedule.dir, schedule.retention)? {
+        println!("Deleted old backup {}", pruned.display());
+    }
+    Ok(backup_path)
+}
+
+// The files of a snapshot by name. Only the data file and known config files are read, never written by their path in the archive.
+fn read_snapshot(
+    snapshot: &Path,
+    csv_name: &String,
+) -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>> {
+    let known_file_names: Vec<String> = snapshot_files(csv_name)
+        .into_iter()
+        .map(|(file_name, _)| file_name)
+        .collect();
+    let mut archive = Archive::new(GzDecoder::new(fs::File::open(snapshot)?));
+    let mut files = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let file_name = entry.path()?.to_string_lossy().to_string();
+        if !known_file_names.contains(&file_name) {
+            return Err(format!(
+                "{} has an unexpected file {}",
+                snapshot.display(),
+                file_name
+            )
+            .into());
+        }
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        files.insert(file_name, contents);
+    }
+    Ok(files)
+}
+
+//Check the data file of a snapshot can be restored, returning its number of rows.
+//It must decrypt with the current key, have a schema this version can read and only rows that parse.
+fn validate_data_file(data_file: &[u8], cipher: Option<&Cipher>) -> Result<usize, Box<dyn Error>> {
+    let is_encrypted = data_file.starts_with(ENCRYPTED_FILE_MAGIC.as_bytes());
+    let csv = match (cipher, is_encrypted) {
+        (Some(cipher), true) => cipher.decrypt_contents(data_file)?,
+        (None, false) => data_file.to_vec(),
+        (Some(_), false) => {
+            return Err("The snapshot is not encrypted, but the data file is".into());
+        }
+        (None, true) => {
+            return Err("The snapshot is encrypted, set the key it was encrypted with".into());
+        }
+    };
+    let version = schema_version(&csv)?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "The snapshot has schema version {}, but this version of screen_timed only supports up to {}",
+            version, SCHEMA_VERSION
+        )
+        .into());
+    }
+    let mut rdr = ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(csv.as_slice());
+    let mut rows = 0;
+    for result in rdr.deserialize::<Row>() {
+        result.map_err(|err| format!("The snapshot has a malformed row: {}", err))?;
+        rows += 1;
+    }
+    Ok(rows)
+}
+
+//Check a snapshot and return its number of rows, without restoring it.
+pub fn validate_backup(
+    snapshot: &Path,
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+) -> Result<usize, Box<dyn Error>> {
+    let files = read_snapshot(snapshot, csv_name)?;
+    let csv_file_name = &snapshot_files(csv_name)[0].0;
+    let data_file = files.get(csv_file_name).ok_or(format!(
+        "{} has no {}",
+        snapshot.display(),
+        csv_file_name
+    ))?;
+    validate_data_file(data_file, cipher)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RestoreSummary {
+    pub rows: usize,
+    // Snapshot of the files that were replaced, to undo the restore
+    pub previous_backup: PathBuf,
+}
+
+pub fn format_restore_summary(summary: &RestoreSummary) -> String {
+    format!(
+        "Restored {} rows, the replaced files are backed up in {}. Restart the daemon to apply restored config",
+        summary.rows,
+        summary.previous_backup.display()
+    )
+}
+
+//Replace the data file and config with a validated snapshot.
+//The current files are backed up next to the snapshot first. Config changes apply once the daemon restarts.
+pub fn restore_backup(
+    snapshot: &Path,
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+) -> Result<RestoreSummary, Box<dyn Error>> {
+    let rows = validate_backup(snapshot, csv_name, cipher)?;
+    let files = read_snapshot(snapshot, csv_name)?;
+    let backup_dir = snapshot.parent().unwrap_or(Path::new("."));
+    let previous_backup = create_backup(backup_dir, csv_name, Local::now())?;
+    println!(
+        "Backed up the current files to {} before restoring",
+        previous_backup.display()
+    );
+    for (file_name, path) in snapshot_files(csv_name) {
+        if let Some(contents) = files.get(&file_name) {
+            write_atomically(&path.to_string_lossy().to_string(), contents)?;
+            println!("Restored {}", path.display());
+        }
+    }
+    // Snapshots from older versions are upgraded like the data file at startup
+    migrate_csv(csv_name, cipher)?;
+    Ok(RestoreSummary {
+        rows,
+        previous_backup,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_writer::{read_valid_rows, write_data_to_csv};
+    use crate::encryption::KeySource;
+    use crate::test_helpers::tests::{setup, CSV_NAME};
+    use chrono::TimeZone;
+    use serial_test::serial;
+
+    const BACKUP_DIR: &str = "backups";
+
+    fn write_app(app_name: &str, seconds: u64) {
+        let mut program_times = HashMap::new();
+        program_times.insert(app_name.to_string(), Duration::from_secs(seconds));
+        write_data_to_csv(
+            &program_times,
+            &CSV_NAME.to_string(),
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
+    }
+
+    fn at_hour(hour: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 16, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn parse_backup_settings() {
+        assert_eq!(
+            parse_backup_interval("12").unwrap(),
+            Duration::from_secs(12 * 60 * 60)
+        );
+        assert!(parse_backup_interval("0").is_err());
+        assert_eq!(parse_backup_retention("3").unwrap(), 3);
+        assert!(parse_backup_retention("none").is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn old_backups_are_pruned() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        write_app("Code", 60);
+        let backup_dir = Path::new(BACKUP_DIR);
+        for hour in 1..=4 {
+            create_backup(backup_dir, &csv_name, at_hour(hour)).unwrap();
+        }
+        let pruned = prune_backups(backup_dir, 2).unwrap();
+        assert_eq!(
+            pruned,
+            vec![
+                backup_dir.join("screen_timed_backup_20240316T010000.tar.gz"),
+                backup_dir.join("screen_timed_backup_20240316T020000.tar.gz"),
+            ]
+        );
+        assert_eq!(list_backups(backup_dir).unwrap().len(), 2);
+    }
+
+    #[test]
+    #[serial]
+    fn restore_replaces_the_data_file_and_config() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        write_app("Code", 60);
+        fs::write(GOAL_HISTORY_FILE_NAME, "{}").unwrap();
+        fs::write(FOCUS_SESSIONS_FILE_NAME, "start,end\n").unwrap();
+        let backup_dir = Path::new(BACKUP_DIR);
+        let snapshot = create_backup(backup_dir, &csv_name, at_hour(1)).unwrap();
+        write_app("Firefox", 30);
+        fs::write(".env", "ALERT_SCREEN=30\n").unwrap();
+        fs::remove_file(GOAL_HISTORY_FILE_NAME).unwrap();
+        fs::remove_file(FOCUS_SESSIONS_FILE_NAME).unwrap();
+
+        let summary = restore_backup(&snapshot, &csv_name, None).unwrap();
+        assert_eq!(summary.rows, 1);
+        let rows = read_valid_rows(&csv_name, None).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].application(), "Code");
+        assert_eq!(fs::read_to_string(".env").unwrap(), "ALERT_SCREEN=45\n");
+        assert_eq!(fs::read_to_string(GOAL_HISTORY_FILE_NAME).unwrap(), "{}");
+        assert_eq!(
+            fs::read_to_string(FOCUS_SESSIONS_FILE_NAME).unwrap(),
+            "start,end\n"
+        );
+        //The replaced files can be restored again
+        assert_eq!(
+            validate_backup(&summary.previous_backup, &csv_name, None).unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn invalid_snapshots_are_not_restored() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        fs::write(
+            &csv_name,
+            "timestamp,application,duration\nyesterday,Code,60\n",
+        )
+        .unwrap();
+        let backup_dir = Path::new(BACKUP_DIR);
+        let malformed_snapshot = create_backup(backup_dir, &csv_name, at_hour(1)).unwrap();
+        write_app("Code", 60);
+        let csv = fs::read(&csv_name).unwrap();
+        assert!(restore_backup(&malformed_snapshot, &csv_name, None).is_err());
+        assert_eq!(fs::read(&csv_name).unwrap(), csv);
+
+        // A plain snapshot is not restored over an encrypted data file
+        let plain_snapshot = create_backup(backup_dir, &csv_name, at_hour(2)).unwrap();
+        fs::write("screen_timed.key", "secret").unwrap();
+        set_owner_only_permissions(Path::new("screen_timed.key")).unwrap();
+        let key_source = KeySource::Keyfile("screen_timed.key".into());
+        fs::remove_file(&csv_name).unwrap();
+        let cipher = Cipher::open(&csv_name, &key_source).unwrap();
+        assert!(validate_backup(&plain_snapshot, &csv_name, Some(&cipher)).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn scheduler_waits_for_the_interval_since_the_newest_backup() {
+        let (_temp_dir, _) = setup();
+        let schedule = BackupSchedule {
+            dir: PathBuf::from(BACKUP_DIR),
+            interval: DEFAULT_BACKUP_INTERVAL,
+            retention: DEFAULT_BACKUP_RETENTION,
+        };
+        let now = SystemTime::now();
+        let mut backup_scheduler = BackupScheduler::new(schedule.clone(), now);
+        assert!(backup_scheduler.due(now));
+        assert!(!backup_scheduler.due(now + Duration::from_secs(60)));
+
+        run_backup(&schedule, &CSV_NAME.to_string()).unwrap();
+        let mut backup_scheduler = BackupScheduler::new(schedule, now);
+        assert!(!backup_scheduler.due(now));
+        assert!(backup_scheduler.due(now + DEFAULT_BACKUP_INTERVAL + Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+// A gap between ticks longer than this means the system was suspended.
+const SUSPEND_GAP: Duration = Duration::from_secs(5);
+
+pub const TWENTY_TWENTY_TWENTY_PRESET: &str = "20-20-20";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakPreset {
+    // Every 20 minutes, look at something 20 feet away for 20 seconds.
+    TwentyTwentyTwenty,
+}
+
+impl BreakPreset {
+    pub fn parse(preset: &str) -> Result<BreakPreset, &'static str> {
+        match preset.trim() {
+            TWENTY_TWENTY_TWENTY_PRESET => Ok(BreakPreset::TwentyTwentyTwenty),
+            _ => Err("Unknown break preset"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakSettings {
+    // Continuous use before a reminder, zero disables reminders
+    pub limit: Duration,
+    // Idle time that counts as a break and resets the continuous use
+    pub break_length: Duration,
+    pub preset: Option<BreakPreset>,
+}
+
+impl BreakSettings {
+    pub fn new(alert_screen_time: u64, break_reset: u64) -> BreakSettings {
+        BreakSettings {
+            limit: Duration::from_secs(alert_screen_time * 60),
+            break_length: Duration::from_secs(break_reset * 60),
+            preset: None,
+        }
+    }
+
+    pub fn from_preset(preset: BreakPreset) -> BreakSettings {
+        match preset {
+            BreakPreset::TwentyTwentyTwenty => BreakSettings {
+                limit: Duration::from_secs(20 * 60),
+                break_length: Duration::from_secs(20),
+                preset: Some(preset),
+            },
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.limit.is_zero()
+    }
+}
+
+// Tracks continuous screen use, driven by the run() loop once per tick.
+// Being idle (paused, no active window, no input or suspended) for the break length resets it.
+pub struct BreakTracker {
+    settings: BreakSettings,
+    continuous_use: Duration,
+    idle: Duration,
+    next_reminder: Duration,
+}
+
+impl BreakTracker {
+    pub fn new(settings: BreakSettings) -> BreakTracker {
+        let next_reminder = settings.limit;
+        BreakTracker {
+            settings,
+            continuous_use: Duration::ZERO,
+            idle: Duration::ZERO,
+            next_reminder,
+        }
+    }
+
+    pub fn settings(&self) -> &BreakSettings {
+        &self.settings
+    }
+
+    //Record the time since the last tick, returns the continuous use if a reminder is due.
+    //A tick with no input since before it started is idle, even with a window focused.
+    pub fn tick(
+        &mut self,
+        active: bool,
+        input_idle: Duration,
+        elapsed: Duration,
+    ) -> Option<Duration> {
+        if !self.settings.is_enabled() {
+            return None;
+        }
+        if active && input_idle <= elapsed && elapsed <= SUSPEND_GAP {
+            self.idle = Duration::ZERO;
+            self.continuous_use += elapsed;
+        } else {
+            self.idle += elapsed;
+            if self.idle >= self.settings.break_length {
+                self.reset();
+            }
+        }
+
+        if self.continuous_use >= self.next_reminder {
+            self.next_reminder = self.continuous_use + self.settings.limit;
+            return Some(self.continuous_use);
+        }
+        None
+    }
+
+    //Postpone the next reminder until after another snooze_duration of continuous use.
+    pub fn snooze(&mut self, snooze_duration: Duration) {
+        self.next_reminder = self.continuous_use + snooze_duration;
+    }
+
+    fn reset(&mut self) {
+        self.continuous_use = Duration::ZERO;
+        self.next_reminder = self.settings.limit;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_secs(1);
+
+    fn tick_for(tracker: &mut BreakTracker, active: bool, secs: u64) -> Vec<Duration> {
+        (0..secs)
+            .filter_map(|_| tracker.tick(active, Duration::ZERO, TICK))
+            .collect()
+    }
+
+    #[test]
+    fn reminds_after_continuous_use() {
+        let mut tracker = BreakTracker::new(BreakSettings::new(1, 5));
+        assert!(tick_for(&mut tracker, true, 59).is_empty());
+        assert_eq!(
+            tracker.tick(true, Duration::ZERO, TICK),
+            Some(Duration::from_secs(60))
+        );
+        //Reminds again after another limit of continuous use
+        assert_eq!(
+            tick_for(&mut tracker, true, 60),
+            vec![Duration::from_secs(120)]
+        );
+    }
+
+    #[test]
+    fn short_idle_does_not_reset() {
+        let mut tracker = BreakTracker::new(BreakSettings::new(1, 5));
+        tick_for(&mut tracker, true, 50);
+        tick_for(&mut tracker, false, 4 * 60);
+        assert_eq!(tracker.continuous_use, Duration::from_secs(50));
+        assert_eq!(tick_for(&mut tracker, true, 10).len(), 1);
+    }
+
+    #[test]
+    fn break_resets_continuous_use() {
+        let mut tracker = BreakTracker::new(BreakSettings::new(1, 5));
+        tick_for(&mut tracker, true, 50);
+        tick_for(&mut tracker, false, 5 * 60);
+        assert_eq!(tracker.continuous_use, Duration::ZERO);
+        assert!(tick_for(&mut tracker, true, 59).is_empty());
+        assert_eq!(tick_for(&mut tracker, true, 1).len(), 1);
+    }
+
+    #[test]
+    fn no_input_with_a_window_focused_counts_as_break() {
+        let mut tracker = BreakTracker::new(BreakSettings::new(1, 5));
+        tick_for(&mut tracker, true, 50);
+        // Walked away from a focused window, or left the lock screen up
+        for secs in 2..5 * 60 + 1 {
+            tracker.tick(true, Duration::from_secs(secs), TICK);
+        }
+        assert_eq!(tracker.continuous_use, Duration::from_secs(50));
+        tracker.tick(true, Duration::from_secs(5 * 60 + 1), TICK);
+        assert_eq!(tracker.continuous_use, Duration::ZERO);
+        // Input during the last tick is use again
+        tracker.tick(true, Duration::from_millis(300), TICK);
+        assert_eq!(tracker.continuous_use, TICK);
+    }
+
+    #[test]
+    fn suspend_counts_as_break() {
+        let mut tracker = BreakTracker::new(BreakSettings::new(1, 5));
+        tick_for(&mut tracker, true, 50);
+        assert_eq!(
+            tracker.tick(true, Duration::ZERO, Duration::from_secs(10 * 60)),
+            None
+        );
+        assert_eq!(tracker.continuous_use, Duration::ZERO);
+    }
+
+    #[test]
+    fn snooze_postpones_reminder() {
+        let mut tracker = BreakTracker::new(BreakSettings::new(30, 5));
+        assert_eq!(tick_for(&mut tracker, true, 30 * 60).len(), 1);
+        tracker.snooze(Duration::from_secs(10 * 60));
+        assert!(tick_for(&mut tracker, true, 10 * 60 - 1).is_empty());
+        assert_eq!(
+            tick_for(&mut tracker, true, 1),
+            vec![Duration::from_secs(40 * 60)]
+        );
+    }
+
+    #[test]
+    fn zero_limit_disables_reminders() {
+        let mut tracker = BreakTracker::new(BreakSettings::new(0, 5));
+        assert!(tick_for(&mut tracker, true, 60 * 60).is_empty());
+    }
+
+    #[test]
+    fn twenty_twenty_twenty_preset() {
+        let preset = BreakPreset::parse("20-20-20").unwrap();
+        let mut tracker = BreakTracker::new(BreakSettings::from_preset(preset));
+        assert_eq!(
+            tick_for(&mut tracker, true, 20 * 60),
+            vec![Duration::from_secs(20 * 60)]
+        );
+        tick_for(&mut tracker, false, 20);
+        assert_eq!(tracker.continuous_use, Duration::ZERO);
+        assert!(BreakPreset::parse("20-20").is_err());
+    }
+}
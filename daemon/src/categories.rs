@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use regex::Regex;
+use serde_derive::Deserialize;
+
+// Categories file kept next to the screen time csv, shared with the desktop app.
+pub const CATEGORIES_FILE_NAME: &str = "categories.json";
+
+// Time in windows matched by a title rule, by application and category.
+// It is part of the application's time in program_times, and is written in rows of its own.
+pub type CategorizedTimes = HashMap<(String, String), Duration>;
+
+#[derive(Deserialize)]
+struct CategoryRuleConfig {
+    app: String,
+    #[serde(default)]
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CategoryConfig {
+    name: String,
+    rules: Vec<CategoryRuleConfig>,
+}
+
+#[derive(Deserialize)]
+struct CategoriesConfig {
+    categories: Vec<CategoryConfig>,
+}
+
+#[derive(Debug)]
+struct TitleRule {
+    category: String,
+    app: Regex,
+    title: Regex,
+}
+
+// The rules of the categories file that also match the window title.
+// Titles are never written to the csv, so these are resolved to their category when recording.
+// Rules on the application name alone are left to the desktop app, which applies them when reading.
+#[derive(Debug, Default)]
+pub struct TitleCategoryRules {
+    rules: Vec<TitleRule>,
+}
+
+impl TitleCategoryRules {
+    //No file means no title rules.
+    pub fn load(categories_path: &Path) -> Result<TitleCategoryRules, Box<dyn Error>> {
+        if !categories_path.exists() {
+            return Ok(TitleCategoryRules::default());
+        }
+        TitleCategoryRules::parse(&fs::read_to_string(categories_path)?)
+    }
+
+    pub fn parse(categories_json: &str) -> Result<TitleCategoryRules, Box<dyn Error>> {
+        let config: CategoriesConfig = serde_json::from_str(categories_json)?;
+        let mut rules = Vec::new();
+        for category in config.categories {
+            for rule in category.rules {
+                if let Some(title) = rule.title {
+                    rules.push(TitleRule {
+                        category: category.name.clone(),
+                        app: Regex::new(&rule.app)?,
+                        title: Regex::new(&title)?,
+                    });
+                }
+            }
+        }
+        Ok(TitleCategoryRules { rules })
+    }
+
+    //The category of the first rule matching both the recorded application name and the title.
+    pub fn resolve(&self, app_name: &str, title: &str) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|rule| rule.app.is_match(app_name) && rule.title.is_match(title))
+            .map(|rule| rule.category.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CATEGORIES_JSON: &str = r#"{
+        "categories": [
+            {
+                "name": "Development",
+                "productivity": "productive",
+                "rules": [{ "app": "(?i)^code$" }, { "app": "(?i)firefox", "title": "GitHub|docs\\.rs" }]
+            },
+            {
+                "name": "Entertainment",
+                "productivity": "distracting",
+                "rules": [{ "app": "(?i)firefox|chrome", "title": "(?i)youtube" }]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn titles_resolve_to_the_first_matching_category() {
+        let title_rules = TitleCategoryRules::parse(CATEGORIES_JSON).unwrap();
+        assert_eq!(
+            title_rules.resolve("firefox", "rust-lang/rust - GitHub"),
+            Some("Development")
+        );
+        assert_eq!(
+            title_rules.resolve("Google Chrome", "Lo-fi beats - YouTube"),
+            Some("Entertainment")
+        );
+        // Both the application and the title have to match
+        assert_eq!(title_rules.resolve("Code", "GitHub"), None);
+        assert_eq!(title_rules.resolve("firefox", "Inbox"), None);
+    }
+
+    #[test]
+    fn invalid_title_rules_are_refused() {
+        let categories_json = r#"{"categories": [{"name": "Development", "productivity": "productive",
+            "rules": [{ "app": "(?i)firefox", "title": "(" }]}]}"#;
+        assert!(TitleCategoryRules::parse(categories_json).is_err());
+    }
+}
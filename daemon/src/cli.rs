@@ -0,0 +1,198 @@
+use std::error::Error;
+
+use crate::export::ExportRequest;
+use crate::focus::parse_focus_request;
+use crate::socket::send_message_to_socket;
+
+const USAGE: &str = "Usage: screen_timed [COMMAND]
+
+Runs the daemon when no command is given.
+
+Commands:
+    pause [MINUTES]    Pause tracking, for MINUTES if given, otherwise until resumed
+    resume             Resume tracking
+    digest [weekly]    Show today's summary, or this week's with weekly
+    focus MINUTES APP...
+                       Start a focus session, only the applications given count as focused
+    focus stop         End the focus session early
+    focus status [json]
+                       Show the running focus session
+    focus sessions [json]
+                       List the finished focus sessions
+    goals [json]       Show today's progress on each goal and its streaks
+    names              List the recorded application names with their totals
+    export START END [csv|json|ndjson] [app|day|day-app]
+                       Print the data between the dates, such as 2024-03-01, optionally as totals
+                       per application, per day or per application per day
+    import activitywatch FILE...
+                       Import ActivityWatch bucket exports, skipping hours that are already recorded
+    merge [--host NAME] FILE...
+                       Merge the data files of other machines, naming the machine of files written
+                       before the host column
+    backup             Take a snapshot of the data file and config in BACKUP_DIR now
+    restore SNAPSHOT   Check a snapshot and replace the data file and config with it
+    repair             Move malformed rows of the data file to a quarantine file
+    encrypt            Encrypt the existing data file, with the daemon stopped";
+
+//Convert command line arguments into the socket message for the daemon.
+pub fn build_socket_message(args: &[String]) -> Result<String, Box<dyn Error>> {
+    let command = match args.first() {
+        Some(command) => command.as_str(),
+        None => return Err(USAGE.into()),
+    };
+    match (command, &args[1..]) {
+        ("pause", []) => Ok("PAUSE".to_string()),
+        ("pause", [minutes]) => {
+            let minutes: u64 = minutes
+                .parse()
+                .map_err(|_| format!("Invalid number of minutes: {}", minutes))?;
+            Ok(format!("PAUSE {}", minutes))
+        }
+        ("resume", []) => Ok("RESUME".to_string()),
+        ("names", []) => Ok("NAMES".to_string()),
+        ("focus", [command]) if command == "stop" => Ok("FOCUS STOP".to_string()),
+        ("focus", [command]) if command == "status" => Ok("FOCUS".to_string()),
+        ("focus", [command, format]) if command == "status" && format == "json" => {
+            Ok("FOCUS JSON".to_string())
+        }
+        ("focus", [command]) if command == "sessions" => Ok("FOCUS SESSIONS".to_string()),
+        ("focus", [command, format]) if command == "sessions" && format == "json" => {
+            Ok("FOCUS SESSIONS JSON".to_string())
+        }
+        ("focus", [minutes, apps @ ..]) if !apps.is_empty() => {
+            let focus_request = format!("{} {}", minutes, apps.join(","));
+            parse_focus_request(&focus_request)?;
+            Ok(format!("FOCUS START {}", focus_request))
+        }
+        ("goals", []) => Ok("GOALS".to_string()),
+        ("goals", [format]) if format == "json" => Ok("GOALS JSON".to_string()),
+        ("repair", []) => Ok("REPAIR".to_string()),
+        ("backup", []) => Ok("BACKUP".to_string()),
+        ("restore", [snapshot]) => Ok(format!("RESTORE {}", snapshot)),
+        ("export", options) => {
+            let export_request = options.join(" ");
+            ExportRequest::parse(&export_request)?;
+            Ok(format!("EXPORT {}", export_request))
+        }
+        ("digest", []) => Ok("DIGEST".to_string()),
+        ("digest", [period]) if period == "weekly" => Ok("DIGEST WEEKLY".to_string()),
+        _ => Err(USAGE.into()),
+    }
+}
+
+pub fn run_command(args: &[String], socket_addr: &str) -> Result<String, Box<dyn Error>> {
+    let message = build_socket_message(args)?;
+    let response = send_message_to_socket(socket_addr, &message)
+        .map_err(|err| format!("Error sending message to the daemon: {}", err))?;
+    if response.trim() == "Failure" {
+        return Err(format!("The daemon failed to handle: {}", message).into());
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn build_pause_and_resume_messages() {
+        assert_eq!(build_socket_message(&to_args(&["pause"])).unwrap(), "PAUSE");
+        assert_eq!(
+            build_socket_message(&to_args(&["pause", "15"])).unwrap(),
+            "PAUSE 15"
+        );
+        assert_eq!(
+            build_socket_message(&to_args(&["resume"])).unwrap(),
+            "RESUME"
+        );
+    }
+
+    #[test]
+    fn build_digest_messages() {
+        assert_eq!(
+            build_socket_message(&to_args(&["digest"])).unwrap(),
+            "DIGEST"
+        );
+        assert_eq!(
+            build_socket_message(&to_args(&["digest", "weekly"])).unwrap(),
+            "DIGEST WEEKLY"
+        );
+        assert!(build_socket_message(&to_args(&["digest", "monthly"])).is_err());
+        assert_eq!(build_socket_message(&to_args(&["names"])).unwrap(), "NAMES");
+        assert_eq!(build_socket_message(&to_args(&["goals"])).unwrap(), "GOALS");
+        assert_eq!(
+            build_socket_message(&to_args(&["goals", "json"])).unwrap(),
+            "GOALS JSON"
+        );
+        assert_eq!(
+            build_socket_message(&to_args(&["repair"])).unwrap(),
+            "REPAIR"
+        );
+    }
+
+    #[test]
+    fn build_focus_messages() {
+        assert_eq!(
+            build_socket_message(&to_args(&["focus", "45", "Code", "Windows Terminal"])).unwrap(),
+            "FOCUS START 45 Code,Windows Terminal"
+        );
+        assert!(build_socket_message(&to_args(&["focus", "45"])).is_err());
+        assert!(build_socket_message(&to_args(&["focus", "soon", "Code"])).is_err());
+        assert_eq!(
+            build_socket_message(&to_args(&["focus", "stop"])).unwrap(),
+            "FOCUS STOP"
+        );
+        assert_eq!(
+            build_socket_message(&to_args(&["focus", "status", "json"])).unwrap(),
+            "FOCUS JSON"
+        );
+        assert_eq!(
+            build_socket_message(&to_args(&["focus", "sessions"])).unwrap(),
+            "FOCUS SESSIONS"
+        );
+    }
+
+    #[test]
+    fn build_export_messages() {
+        assert_eq!(
+            build_socket_message(&to_args(&["export", "2024-03-01", "2024-03-31"])).unwrap(),
+            "EXPORT 2024-03-01 2024-03-31"
+        );
+        assert_eq!(
+            build_socket_message(&to_args(&[
+                "export",
+                "2024-03-01",
+                "2024-03-31",
+                "json",
+                "day"
+            ]))
+            .unwrap(),
+            "EXPORT 2024-03-01 2024-03-31 json day"
+        );
+        assert!(build_socket_message(&to_args(&["export", "March"])).is_err());
+    }
+
+    #[test]
+    fn build_backup_and_restore_messages() {
+        assert_eq!(
+            build_socket_message(&to_args(&["backup"])).unwrap(),
+            "BACKUP"
+        );
+        assert_eq!(
+            build_socket_message(&to_args(&["restore", "/backups/snapshot.tar.gz"])).unwrap(),
+            "RESTORE /backups/snapshot.tar.gz"
+        );
+        assert!(build_socket_message(&to_args(&["restore"])).is_err());
+    }
+
+    #[test]
+    fn invalid_commands_are_rejected() {
+        assert!(build_socket_message(&to_args(&["pause", "later"])).is_err());
+        assert!(build_socket_message(&to_args(&["resume", "now"])).is_err());
+        assert!(build_socket_message(&to_args(&["unknown"])).is_err());
+    }
+}
@@ -1,10 +1,53 @@
+use std::fmt::Display;
+use std::path::PathBuf;
 use std::string::String;
+use std::time::Duration;
 
-use crate::{notification::exit_with_error_notification, ALERT_SCREEN_ENV_VAR};
+use crate::backup::{
+    parse_backup_interval, parse_backup_retention, BackupSchedule, DEFAULT_BACKUP_INTERVAL,
+    DEFAULT_BACKUP_RETENTION,
+};
+use crate::breaks::{BreakPreset, BreakSettings};
+use crate::csv_writer::{parse_flush_interval, DEFAULT_FLUSH_INTERVAL};
+use crate::digest::{parse_digest_time, parse_weekday, DigestSchedule};
+use crate::encryption::KeySource;
+use crate::error::DaemonError;
+use crate::goals::{parse_goals, Goal};
+use crate::host::local_host_id;
+use crate::limits::{parse_daily_limits, parse_day_start_hour, parse_limit_thresholds, DailyLimit};
+use crate::notification::{DESKTOP_NOTIFIER, LOG_NOTIFIER};
+use crate::privacy::{parse_private_apps, parse_private_titles, PrivacyAction, PrivacyRules};
+use crate::suppression::{QuietHours, SuppressionRules};
+use crate::{
+    ALERT_SCREEN_ENV_VAR, ALIAS_AT_WRITE_ENV_VAR, BACKUP_DIR_ENV_VAR, BACKUP_INTERVAL_ENV_VAR,
+    BACKUP_RETENTION_ENV_VAR, BREAK_PRESET_ENV_VAR, BREAK_RESET_ENV_VAR, DAILY_LIMITS_ENV_VAR,
+    DAY_START_HOUR_ENV_VAR, DIGEST_TIME_ENV_VAR, ENCRYPTION_KEYFILE_ENV_VAR,
+    ENCRYPTION_PASSPHRASE_ENV_VAR, FLUSH_INTERVAL_ENV_VAR, GOALS_ENV_VAR, LIMIT_THRESHOLDS_ENV_VAR,
+    NOTIFIER_ENV_VAR, PRIVACY_ACTION_ENV_VAR, PRIVATE_APPS_ENV_VAR, PRIVATE_TITLES_ENV_VAR,
+    QUIET_HOURS_ENV_VAR, SUPPRESS_FULLSCREEN_ENV_VAR, WEEKLY_DIGEST_DAY_ENV_VAR,
+};
+
+const DEFAULT_LIMIT_THRESHOLDS: [u64; 2] = [80, 100];
+const DEFAULT_BREAK_RESET: u64 = 5;
 
 #[derive(Debug)]
 pub struct Config {
     alert_screen_time: u64,
+    break_reset: u64,
+    break_preset: Option<BreakPreset>,
+    daily_limits: Vec<DailyLimit>,
+    limit_thresholds: Vec<u64>,
+    goals: Vec<Goal>,
+    // Hour days start at for daily limits, digests and exports, 0 for midnight
+    day_start_hour: u32,
+    flush_interval: Duration,
+    notifier: String,
+    suppression_rules: SuppressionRules,
+    digest_schedule: Option<DigestSchedule>,
+    alias_at_write: bool,
+    privacy_rules: PrivacyRules,
+    key_source: Option<KeySource>,
+    backup_schedule: Option<BackupSchedule>,
 }
 
 impl Config {
@@ -17,49 +60,313 @@ impl Config {
             }
         };
 
-        Ok(Config { alert_screen_time })
+        Ok(Config {
+            alert_screen_time,
+            break_reset: DEFAULT_BREAK_RESET,
+            break_preset: None,
+            daily_limits: Vec::new(),
+            limit_thresholds: DEFAULT_LIMIT_THRESHOLDS.to_vec(),
+            goals: Vec::new(),
+            day_start_hour: 0,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            notifier: DESKTOP_NOTIFIER.to_string(),
+            suppression_rules: SuppressionRules::default(),
+            digest_schedule: None,
+            alias_at_write: false,
+            privacy_rules: PrivacyRules::default(),
+            key_source: None,
+            backup_schedule: None,
+        })
     }
 
     pub fn get_alert_screen_time(&self) -> u64 {
         self.alert_screen_time
     }
 
+    pub fn get_break_settings(&self) -> BreakSettings {
+        match self.break_preset {
+            Some(preset) => BreakSettings::from_preset(preset),
+            None => BreakSettings::new(self.alert_screen_time, self.break_reset),
+        }
+    }
+
+    pub fn get_daily_limits(&self) -> &Vec<DailyLimit> {
+        &self.daily_limits
+    }
+
+    pub fn get_goals(&self) -> &Vec<Goal> {
+        &self.goals
+    }
+
+    pub fn get_limit_thresholds(&self) -> &Vec<u64> {
+        &self.limit_thresholds
+    }
+
+    pub fn get_day_start_hour(&self) -> u32 {
+        self.day_start_hour
+    }
+
+    pub fn get_flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    pub fn get_notifier(&self) -> &str {
+        &self.notifier
+    }
+
+    pub fn get_suppression_rules(&self) -> &SuppressionRules {
+        &self.suppression_rules
+    }
+
+    pub fn get_digest_schedule(&self) -> Option<DigestSchedule> {
+        self.digest_schedule
+    }
+
+    pub fn get_alias_at_write(&self) -> bool {
+        self.alias_at_write
+    }
+
+    pub fn get_privacy_rules(&self) -> &PrivacyRules {
+        &self.privacy_rules
+    }
+
+    pub fn get_key_source(&self) -> Option<&KeySource> {
+        self.key_source.as_ref()
+    }
+
+    pub fn get_backup_schedule(&self) -> Option<&BackupSchedule> {
+        self.backup_schedule.as_ref()
+    }
+
     pub fn print_out_config(&self) {
+        println!("Host: {}.", local_host_id());
         println!("Alert Screen Time: {}.", self.get_alert_screen_time());
+        println!("Break Reset: {}.", self.break_reset);
+        if let Some(preset) = self.break_preset {
+            println!("Break Preset: {:?}.", preset);
+        }
+        for limit in self.get_daily_limits() {
+            println!("Daily Limit: {}: {} minutes.", limit.name, limit.minutes);
+        }
+        println!("Limit Thresholds: {:?}.", self.get_limit_thresholds());
+        for goal in self.get_goals() {
+            println!(
+                "Goal: {}: {:?} {} minutes.",
+                goal.name, goal.kind, goal.minutes
+            );
+        }
+        println!("Day Start Hour: {}.", self.day_start_hour);
+        println!(
+            "Flush Interval: {} minutes.",
+            self.flush_interval.as_secs() / 60
+        );
+        println!("Notifier: {}.", self.get_notifier());
+        if let Some(quiet_hours) = self.suppression_rules.quiet_hours {
+            println!(
+                "Quiet Hours: {}-{}.",
+                quiet_hours.start.format("%H:%M"),
+                quiet_hours.end.format("%H:%M")
+            );
+        }
+        println!(
+            "Suppress Fullscreen: {}.",
+            self.suppression_rules.suppress_fullscreen
+        );
+        println!("Alias At Write: {}.", self.alias_at_write);
+        // Only the number of rules, the names themselves are private
+        println!("Private Apps: {}.", self.privacy_rules.apps.len());
+        println!("Private Titles: {}.", self.privacy_rules.title.is_some());
+        println!("Privacy Action: {:?}.", self.privacy_rules.action);
+        match &self.key_source {
+            Some(key_source) => println!("Encryption: {:?}.", key_source),
+            None => println!("Encryption: None."),
+        }
+        if let Some(backup_schedule) = &self.backup_schedule {
+            println!("Backup Dir: {}.", backup_schedule.dir.display());
+            println!(
+                "Backup Interval: {} hours.",
+                backup_schedule.interval.as_secs() / 60 / 60
+            );
+            println!("Backup Retention: {}.", backup_schedule.retention);
+        }
+        if let Some(digest_schedule) = self.digest_schedule {
+            println!("Digest Time: {}.", digest_schedule.time.format("%H:%M"));
+            if let Some(weekly_day) = digest_schedule.weekly_day {
+                println!("Weekly Digest Day: {}.", weekly_day);
+            }
+        }
     }
 }
 
-pub fn new_config() -> Config {
+// Parse an optional env var, returning None if it is not set.
+fn parse_optional_var<T, E: Display>(
+    env_var: &str,
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> Result<Option<T>, DaemonError> {
+    match dotenvy::var(env_var) {
+        Ok(value) => match parse(value.trim()) {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(err) => Err(DaemonError::Config(format!(
+                "Error parsing {}: {}",
+                env_var, err
+            ))),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_notifier(notifier: &str) -> Result<String, String> {
+    match notifier {
+        DESKTOP_NOTIFIER | LOG_NOTIFIER => Ok(notifier.to_string()),
+        _ => Err(format!(
+            "should be {} or {}",
+            DESKTOP_NOTIFIER, LOG_NOTIFIER
+        )),
+    }
+}
+
+// The data file is only encrypted if a keyfile or a passphrase is set, not both.
+fn parse_key_source() -> Result<Option<KeySource>, DaemonError> {
+    let keyfile = parse_optional_var(ENCRYPTION_KEYFILE_ENV_VAR, |keyfile| {
+        Ok::<KeySource, String>(KeySource::Keyfile(keyfile.into()))
+    })?;
+    let passphrase = parse_optional_var(
+        ENCRYPTION_PASSPHRASE_ENV_VAR,
+        |passphrase| match passphrase.is_empty() {
+            true => Err("the passphrase is empty"),
+            false => Ok(KeySource::Passphrase(passphrase.to_string())),
+        },
+    )?;
+    match (keyfile, passphrase) {
+        (Some(_), Some(_)) => Err(DaemonError::Config(format!(
+            "Only one of {} and {} should be set",
+            ENCRYPTION_KEYFILE_ENV_VAR, ENCRYPTION_PASSPHRASE_ENV_VAR
+        ))),
+        (keyfile, passphrase) => Ok(keyfile.or(passphrase)),
+    }
+}
+
+// Backups are only taken if a directory is set.
+fn parse_backup_schedule() -> Result<Option<BackupSchedule>, DaemonError> {
+    let interval = parse_optional_var(BACKUP_INTERVAL_ENV_VAR, parse_backup_interval)?;
+    let retention = parse_optional_var(BACKUP_RETENTION_ENV_VAR, parse_backup_retention)?;
+    let dir = parse_optional_var(BACKUP_DIR_ENV_VAR, |dir| match dir.is_empty() {
+        true => Err("the directory is empty"),
+        false => Ok(PathBuf::from(dir)),
+    })?;
+    match dir {
+        Some(dir) => Ok(Some(BackupSchedule {
+            dir,
+            interval: interval.unwrap_or(DEFAULT_BACKUP_INTERVAL),
+            retention: retention.unwrap_or(DEFAULT_BACKUP_RETENTION),
+        })),
+        None if interval.is_some() || retention.is_some() => Err(DaemonError::Config(format!(
+            "{} and {} need {} to be set",
+            BACKUP_INTERVAL_ENV_VAR, BACKUP_RETENTION_ENV_VAR, BACKUP_DIR_ENV_VAR
+        ))),
+        None => Ok(None),
+    }
+}
+
+pub fn new_config() -> Result<Config, DaemonError> {
     if let Err(err) = dotenvy::dotenv() {
-        exit_with_error_notification(format!("Error loading .env file: {}", err).as_str());
+        return Err(DaemonError::Config(format!(
+            "Error loading .env file: {}",
+            err
+        )));
     }
 
     let alert_screen_env_str: String = match dotenvy::var(ALERT_SCREEN_ENV_VAR) {
         Ok(alert_screen_env) => alert_screen_env,
         Err(err) => {
-            exit_with_error_notification(
-                format!("Error getting ALERT_SCREEN_ENV_VAR: {}", err).as_str(),
-            );
+            return Err(DaemonError::Config(format!(
+                "Error getting ALERT_SCREEN_ENV_VAR: {}",
+                err
+            )));
         }
     };
 
-    let config = match Config::build(&alert_screen_env_str) {
+    let mut config = match Config::build(&alert_screen_env_str) {
         Ok(config) => config,
         Err(err) => {
-            exit_with_error_notification(
-                format!("Error parsing ALERT_SCREEN_ENV_VAR: {}", err).as_str(),
-            );
+            return Err(DaemonError::Config(format!(
+                "Error parsing ALERT_SCREEN_ENV_VAR: {}",
+                err
+            )));
         }
     };
 
+    if let Some(break_reset) = parse_optional_var(BREAK_RESET_ENV_VAR, str::parse::<u64>)? {
+        config.break_reset = break_reset;
+    }
+    config.break_preset = parse_optional_var(BREAK_PRESET_ENV_VAR, BreakPreset::parse)?;
+    // Daily limits are optional
+    if let Some(daily_limits) = parse_optional_var(DAILY_LIMITS_ENV_VAR, parse_daily_limits)? {
+        config.daily_limits = daily_limits;
+    }
+    if let Some(goals) = parse_optional_var(GOALS_ENV_VAR, parse_goals)? {
+        config.goals = goals;
+    }
+    if let Some(limit_thresholds) =
+        parse_optional_var(LIMIT_THRESHOLDS_ENV_VAR, parse_limit_thresholds)?
+    {
+        config.limit_thresholds = limit_thresholds;
+    }
+    if let Some(day_start_hour) = parse_optional_var(DAY_START_HOUR_ENV_VAR, parse_day_start_hour)?
+    {
+        config.day_start_hour = day_start_hour;
+    }
+    if let Some(flush_interval) = parse_optional_var(FLUSH_INTERVAL_ENV_VAR, parse_flush_interval)?
+    {
+        config.flush_interval = flush_interval;
+    }
+    if let Some(notifier) = parse_optional_var(NOTIFIER_ENV_VAR, parse_notifier)? {
+        config.notifier = notifier;
+    }
+    config.suppression_rules.quiet_hours =
+        parse_optional_var(QUIET_HOURS_ENV_VAR, QuietHours::parse)?;
+    if let Some(suppress_fullscreen) =
+        parse_optional_var(SUPPRESS_FULLSCREEN_ENV_VAR, str::parse::<bool>)?
+    {
+        config.suppression_rules.suppress_fullscreen = suppress_fullscreen;
+    }
+
+    if let Some(alias_at_write) = parse_optional_var(ALIAS_AT_WRITE_ENV_VAR, str::parse::<bool>)? {
+        config.alias_at_write = alias_at_write;
+    }
+    if let Some(private_apps) = parse_optional_var(PRIVATE_APPS_ENV_VAR, parse_private_apps)? {
+        config.privacy_rules.apps = private_apps;
+    }
+    config.privacy_rules.title = parse_optional_var(PRIVATE_TITLES_ENV_VAR, parse_private_titles)?;
+    if let Some(action) = parse_optional_var(PRIVACY_ACTION_ENV_VAR, PrivacyAction::parse)? {
+        config.privacy_rules.action = action;
+    }
+    config.key_source = parse_key_source()?;
+    // Digests are only sent if a time is set
+    let weekly_day = parse_optional_var(WEEKLY_DIGEST_DAY_ENV_VAR, parse_weekday)?;
+    config.digest_schedule = match parse_optional_var(DIGEST_TIME_ENV_VAR, parse_digest_time)? {
+        Some(time) => Some(DigestSchedule { time, weekly_day }),
+        None if weekly_day.is_some() => {
+            return Err(DaemonError::Config(format!(
+                "{} needs {} to be set",
+                WEEKLY_DIGEST_DAY_ENV_VAR, DIGEST_TIME_ENV_VAR
+            )));
+        }
+        None => None,
+    };
+
+    config.backup_schedule = parse_backup_schedule()?;
+
     config.print_out_config();
-    config
+    Ok(config)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_helpers::tests::setup;
     use rand::prelude::*;
+    use serial_test::serial;
 
     fn generate_random_number() -> u64 {
         rand::thread_rng().gen()
@@ -83,8 +390,50 @@ mod tests {
     }
 
     #[test]
+    fn invalid_notifier_is_rejected() {
+        assert_eq!(parse_notifier("log").unwrap(), LOG_NOTIFIER);
+        assert!(parse_notifier("email").is_err());
+    }
+
+    #[test]
+    fn break_settings_use_alert_screen_time() {
+        let mut config = Config::build("45").unwrap();
+        let break_settings = config.get_break_settings();
+        assert_eq!(break_settings.limit.as_secs(), 45 * 60);
+        assert_eq!(
+            break_settings.break_length.as_secs(),
+            DEFAULT_BREAK_RESET * 60
+        );
+
+        config.break_preset = Some(BreakPreset::TwentyTwentyTwenty);
+        let break_settings = config.get_break_settings();
+        assert_eq!(break_settings.limit.as_secs(), 20 * 60);
+        assert_eq!(break_settings.break_length.as_secs(), 20);
+    }
+
+    #[test]
+    fn build_config_has_no_daily_limits() {
+        let config = Config::build("45").unwrap();
+        assert!(config.get_daily_limits().is_empty());
+        assert_eq!(config.get_limit_thresholds(), &vec![80, 100]);
+        assert!(config.get_goals().is_empty());
+        assert_eq!(config.get_day_start_hour(), 0);
+        assert_eq!(config.get_flush_interval(), DEFAULT_FLUSH_INTERVAL);
+        assert_eq!(config.get_notifier(), DESKTOP_NOTIFIER);
+        assert!(!config.get_suppression_rules().is_enabled());
+        assert_eq!(config.get_digest_schedule(), None);
+        assert!(!config.get_alias_at_write());
+        assert!(config.get_privacy_rules().apps.is_empty());
+        assert_eq!(config.get_privacy_rules().action, PrivacyAction::Label);
+        assert_eq!(config.get_key_source(), None);
+        assert_eq!(config.get_backup_schedule(), None);
+    }
+
+    #[test]
+    #[serial]
     fn env_file_is_read_correctly() {
-        let config = new_config();
+        let (_temp_dir, _) = setup();
+        let config = new_config().unwrap();
         assert_eq!(config.alert_screen_time, 45);
     }
 }
@@ -1,11 +1,14 @@
+use chrono::{DateTime, Local};
+use csv::Reader;
 use csv::ReaderBuilder;
 use csv::WriterBuilder;
 use serde_derive::{Deserialize, Serialize};
 use serde_with::serde_as;
 use serde_with::TimestampSeconds;
 use std::env;
-use std::fs::{copy, remove_file, rename, File, OpenOptions};
-use std::io::Seek;
+use std::fs::{self, rename, File, OpenOptions};
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
 use std::path::PathBuf;
 use std::time::UNIX_EPOCH;
 use std::{
@@ -14,7 +17,12 @@ use std::{
     time::{self, SystemTime},
 };
 
-use crate::notification::exit_with_error_notification;
+use crate::categories::CategorizedTimes;
+use crate::encryption::Cipher;
+use crate::error::DaemonError;
+use crate::host::local_host_id;
+use crate::pause::PAUSED_APPLICATION;
+use crate::schema::schema_version_line;
 
 #[serde_as]
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -24,14 +32,72 @@ pub struct Row {
     application: String,
     //How long in seconds the application was active
     duration: u64,
+    //Machine the row was recorded on, empty before the host column existed
+    #[serde(default)]
+    host: String,
+    //Seconds east of UTC of the local time the row was written in, empty before the column existed
+    #[serde(default)]
+    utc_offset: Option<i32>,
+    //Category of the title rule the windows matched, empty if the application name decides it
+    #[serde(default)]
+    category: String,
 }
-pub fn get_curr_path_to_csv(csv_path: &String) -> String {
+
+impl Row {
+    //A row recorded on this machine at the timestamp.
+    pub fn new(timestamp: SystemTime, application: &str, duration: u64) -> Row {
+        Row {
+            timestamp,
+            application: application.to_string(),
+            duration,
+            host: local_host_id().to_string(),
+            // The offset at the timestamp rather than now, which differs across a DST change
+            utc_offset: Some(
+                DateTime::<Local>::from(timestamp)
+                    .offset()
+                    .local_minus_utc(),
+            ),
+            category: String::new(),
+        }
+    }
+
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    pub fn application(&self) -> &str {
+        &self.application
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn set_host(&mut self, host: &str) {
+        self.host = host.to_string();
+    }
+
+    pub fn utc_offset(&self) -> Option<i32> {
+        self.utc_offset
+    }
+
+    pub fn set_category(&mut self, category: &str) {
+        self.category = category.to_string();
+    }
+}
+
+pub fn get_curr_path_to_csv(csv_path: &String) -> Result<String, DaemonError> {
     let current_path: PathBuf = match env::current_dir() {
         Ok(path) => path,
         Err(err) => {
-            exit_with_error_notification(
-                format!("Exiting: Error getting current path: {}", err).as_str(),
-            );
+            return Err(DaemonError::CsvRead(format!(
+                "Error getting current path: {}",
+                err
+            )));
         }
     };
     let current_path_str = match current_path.to_str() {
@@ -41,48 +107,143 @@ pub fn get_curr_path_to_csv(csv_path: &String) -> String {
             full_path
         }
         None => {
-            exit_with_error_notification("Error getting the current path!");
+            return Err(DaemonError::CsvRead(
+                "Error getting the current path!".to_string(),
+            ));
         }
     };
-    current_path_str
+    Ok(current_path_str)
+}
+
+// Reader over the csv, decrypting it first if the data is encrypted.
+fn csv_reader(
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+) -> Result<Reader<Box<dyn Read>>, Box<dyn Error>> {
+    let source: Box<dyn Read> = match cipher {
+        Some(cipher) => Box::new(Cursor::new(cipher.decrypt_file(csv_name)?)),
+        None => Box::new(File::open(csv_name)?),
+    };
+    // Skip the schema version line
+    Ok(ReaderBuilder::new().comment(Some(b'#')).from_reader(source))
+}
+
+// How often program_times is written to the csv. Each row covers at most this long, so the views can tell when the time was spent.
+pub const DEFAULT_FLUSH_INTERVAL: time::Duration = time::Duration::from_secs(5 * 60);
+
+pub fn parse_flush_interval(minutes: &str) -> Result<time::Duration, String> {
+    match minutes.parse::<u64>() {
+        Ok(minutes) if minutes > 0 => Ok(time::Duration::from_secs(minutes * 60)),
+        _ => Err(format!("{} should be a number of minutes above 0", minutes)),
+    }
 }
 
 pub fn write_data_to_csv(
     program_times: &HashMap<String, time::Duration>,
     csv_name: &String,
+    cipher: Option<&Cipher>,
+    timestamp: SystemTime,
+) -> Result<(), Box<dyn Error>> {
+    write_categorized_data_to_csv(
+        program_times,
+        &CategorizedTimes::new(),
+        csv_name,
+        cipher,
+        timestamp,
+    )
+}
+
+//The time matched by title rules is written in a row per category, the rest of each application's time in one row.
+pub fn write_categorized_data_to_csv(
+    program_times: &HashMap<String, time::Duration>,
+    categorized_times: &CategorizedTimes,
+    csv_name: &String,
+    cipher: Option<&Cipher>,
     timestamp: SystemTime,
 ) -> Result<(), Box<dyn Error>> {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .append(true)
-        .open(csv_name)?;
-    let needs_headers = file.seek(std::io::SeekFrom::End(0))? == 0;
+    let mut rows = Vec::new();
+    for (program_name, duration) in program_times {
+        let categorized: time::Duration = categorized_times
+            .iter()
+            .filter(|((app_name, _), _)| app_name == program_name)
+            .map(|(_, categorized)| *categorized)
+            .sum();
+        if categorized.is_zero() || *duration > categorized {
+            let uncategorized = duration.saturating_sub(categorized);
+            rows.push(Row::new(timestamp, program_name, uncategorized.as_secs()));
+        }
+    }
+    for ((program_name, category), duration) in categorized_times {
+        let mut row = Row::new(timestamp, program_name, duration.as_secs());
+        row.set_category(category);
+        rows.push(row);
+    }
+    append_rows(&rows, csv_name, cipher)
+}
+
+//Append rows to the csv, writing the schema version and headers first if it is new.
+pub fn append_rows(
+    rows: &[Row],
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+) -> Result<(), Box<dyn Error>> {
+    let needs_headers = !Path::new(csv_name).exists() || fs::metadata(csv_name)?.len() == 0;
+    // A new file starts with its schema version
+    let csv = match needs_headers {
+        true => schema_version_line().into_bytes(),
+        false => Vec::new(),
+    };
     let mut wtr = WriterBuilder::new()
         .has_headers(needs_headers)
-        .from_writer(file);
-    for (program_name, duration) in program_times {
-        wtr.serialize(Row {
-            timestamp,
-            application: program_name.to_string(),
-            duration: duration.as_secs(),
-        })?;
+        .from_writer(csv);
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+    let csv = wtr.into_inner()?;
+    match cipher {
+        Some(cipher) => cipher.append(csv_name, &csv),
+        None => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(csv_name)?;
+            file.write_all(&csv)?;
+            Ok(())
+        }
     }
-    wtr.flush()?;
-    Ok(())
 }
 
-//Removes one month of the oldest data
-pub fn remove_old_data(months: u32, csv_name: &String) -> Result<(), Box<dyn Error>> {
-    let backup_screen_csv_name = format!("backup_{}", csv_name);
-    copy(csv_name, &backup_screen_csv_name)?;
-    let new_screen_csv_name = format!("new_{}", csv_name);
+//Total time per application for the rows timestamped within [start, end).
+pub fn read_app_totals(
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+    start: SystemTime,
+    end: SystemTime,
+) -> Result<HashMap<String, time::Duration>, Box<dyn Error>> {
+    let mut app_totals: HashMap<String, time::Duration> = HashMap::new();
+    if !Path::new(csv_name).exists() {
+        return Ok(app_totals);
+    }
+    for record in read_valid_rows(csv_name, cipher)? {
+        let in_range = record.timestamp >= start && record.timestamp < end;
+        if in_range && record.application != PAUSED_APPLICATION {
+            *app_totals.entry(record.application).or_default() +=
+                time::Duration::from_secs(record.duration);
+        }
+    }
+    Ok(app_totals)
+}
 
-    File::create(&new_screen_csv_name)?;
-    let mut rdr = ReaderBuilder::new().from_path(&backup_screen_csv_name)?;
+//Removes one month of the oldest data
+pub fn remove_old_data(
+    months: u32,
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+) -> Result<(), Box<dyn Error>> {
+    let mut rdr = csv_reader(csv_name, cipher)?;
     let mut wtr = WriterBuilder::new()
         .has_headers(true)
-        .from_path(&new_screen_csv_name)?;
+        .from_writer(schema_version_line().into_bytes());
 
     let mut rdr_iter = rdr.deserialize();
     let first_result_iter = rdr_iter.next();
@@ -95,31 +256,88 @@ pub fn remove_old_data(months: u32, csv_name: &String) -> Result<(), Box<dyn Err
     let end_timestamp =
         first_timestamp + time::Duration::from_secs((60 * 60 * 24 * 30 * months).into());
     for result in rdr_iter {
-        let record: Row = result?;
+        let record: Row = result.map_err(|err| {
+            format!(
+                "{}, run `screen_timed repair` to quarantine malformed rows first",
+                err
+            )
+        })?;
         let timestamp = record.timestamp.duration_since(UNIX_EPOCH)?;
         let should_delete = timestamp >= first_timestamp && timestamp <= end_timestamp;
         if !should_delete {
             wtr.serialize(record)?;
         }
     }
-    wtr.flush()?;
+    let new_csv = wtr.into_inner()?;
     //replace old csv with new csv
-    rename(new_screen_csv_name, csv_name)?;
-    remove_file(backup_screen_csv_name)?;
+    match cipher {
+        Some(cipher) => cipher.write_file(csv_name, &new_csv)?,
+        None => write_atomically(csv_name, &new_csv)?,
+    }
     println!("Successfully removed {} months old data", months);
     Ok(())
 }
+
+// Prefix of the temporary file a rewrite is written to before it replaces the original.
+pub const REWRITE_FILE_PREFIX: &str = "new_";
+
+//Replace the file without ever leaving it half written: write a synced temporary file and rename it over the original.
+pub fn write_atomically(file_name: &String, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(file_name);
+    let file_stem = path
+        .file_name()
+        .ok_or(format!("{} is not a file", file_name))?
+        .to_string_lossy();
+    let temp_path = path.with_file_name(format!("{}{}", REWRITE_FILE_PREFIX, file_stem));
+    let mut temp_file = File::create(&temp_path)?;
+    temp_file.write_all(contents)?;
+    temp_file.sync_all()?;
+    rename(&temp_path, path)?;
+    // Sync the directory so that the rename itself survives a crash
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+//Read every row that parses, a malformed row is logged and skipped instead of failing the read.
+pub fn read_valid_rows(
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut rdr = csv_reader(csv_name, cipher)?;
+    let mut rows = Vec::new();
+    let mut malformed_rows = 0;
+    for result in rdr.deserialize() {
+        match result {
+            Ok(row) => rows.push(row),
+            Err(_) => malformed_rows += 1,
+        }
+    }
+    if malformed_rows > 0 {
+        eprintln!(
+            "Skipped {} malformed rows in {}, run `screen_timed repair` to quarantine them",
+            malformed_rows, csv_name
+        );
+    }
+    Ok(rows)
+}
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
     use super::*;
+    use crate::encryption::KeySource;
     use crate::test_helpers::tests::{setup, CSV_NAME};
     use serial_test::serial;
 
     //helper to read csv
     fn read_csv(csv_path: &String) -> Result<Vec<Row>, Box<dyn Error>> {
-        let mut rdr = ReaderBuilder::new().from_path(csv_path)?;
+        let mut rdr = ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .from_path(csv_path)?;
         let mut records: Vec<Row> = Vec::new();
         for result in rdr.deserialize() {
             let record: Row = result?;
@@ -128,6 +346,13 @@ mod tests {
         Ok(records)
     }
 
+    #[test]
+    fn parse_flush_intervals() {
+        assert_eq!(parse_flush_interval("1"), Ok(Duration::from_secs(60)));
+        assert!(parse_flush_interval("0").is_err());
+        assert!(parse_flush_interval("5m").is_err());
+    }
+
     #[test]
     #[serial]
     fn test_get_curr_path_to_csv() {
@@ -135,7 +360,7 @@ mod tests {
         let (_temp_dir, actual_path_to_csv) = setup();
         let csv_name = CSV_NAME.to_string();
         println!("In test_get_curr_path_to_csv");
-        let path_to_csv = get_curr_path_to_csv(&csv_name);
+        let path_to_csv = get_curr_path_to_csv(&csv_name).unwrap();
         println!("path_to_csv: {}", path_to_csv);
         println!("expected_path: {}", actual_path_to_csv);
         assert_eq!(path_to_csv, actual_path_to_csv);
@@ -147,7 +372,13 @@ mod tests {
         let (_temp_dir, actual_path_to_csv) = setup();
         let mut program_times: HashMap<String, time::Duration> = HashMap::new();
         program_times.insert("Application".to_string(), time::Duration::from_secs(0));
-        write_data_to_csv(&program_times, &CSV_NAME.to_string(), SystemTime::now()).unwrap();
+        write_data_to_csv(
+            &program_times,
+            &CSV_NAME.to_string(),
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
         let rows_vector = read_csv(&actual_path_to_csv).unwrap();
         println!("rows_vector[0]: {:?}", rows_vector[0]);
         assert_eq!(rows_vector.len(), 1);
@@ -162,7 +393,13 @@ mod tests {
         let mut program_times: HashMap<String, time::Duration> = HashMap::new();
         program_times.insert("Application".to_string(), time::Duration::from_secs(0));
         program_times.insert("Test".to_string(), time::Duration::from_secs(10));
-        write_data_to_csv(&program_times, &CSV_NAME.to_string(), SystemTime::now()).unwrap();
+        write_data_to_csv(
+            &program_times,
+            &CSV_NAME.to_string(),
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
         let rows_vector = read_csv(&actual_path_to_csv).unwrap();
 
         println!("rows_vector: {:?}", rows_vector);
@@ -179,6 +416,92 @@ mod tests {
         }
     }
 
+    #[test]
+    #[serial]
+    fn categorized_time_is_written_in_rows_of_its_own() {
+        let (_temp_dir, actual_path_to_csv) = setup();
+        let mut program_times: HashMap<String, time::Duration> = HashMap::new();
+        program_times.insert("Firefox".to_string(), time::Duration::from_secs(100));
+        program_times.insert("YouTube".to_string(), time::Duration::from_secs(30));
+        let mut categorized_times = CategorizedTimes::new();
+        categorized_times.insert(
+            ("Firefox".to_string(), "Development".to_string()),
+            time::Duration::from_secs(60),
+        );
+        categorized_times.insert(
+            ("YouTube".to_string(), "Entertainment".to_string()),
+            time::Duration::from_secs(30),
+        );
+        write_categorized_data_to_csv(
+            &program_times,
+            &categorized_times,
+            &CSV_NAME.to_string(),
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
+
+        let mut rows: Vec<(String, u64, String)> = read_csv(&actual_path_to_csv)
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.application, row.duration, row.category))
+            .collect();
+        rows.sort();
+        assert_eq!(
+            rows,
+            vec![
+                ("Firefox".to_string(), 40, "".to_string()),
+                ("Firefox".to_string(), 60, "Development".to_string()),
+                ("YouTube".to_string(), 30, "Entertainment".to_string()),
+            ]
+        );
+        // Each application's total is the same as before
+        let app_totals = read_app_totals(
+            &CSV_NAME.to_string(),
+            None,
+            UNIX_EPOCH,
+            SystemTime::now() + Duration::from_secs(60),
+        )
+        .unwrap();
+        assert_eq!(app_totals["Firefox"], Duration::from_secs(100));
+    }
+
+    #[test]
+    #[serial]
+    fn test_read_app_totals() {
+        let (_temp_dir, _) = setup();
+        let now = SystemTime::now();
+        let one_day = Duration::from_secs(60 * 60 * 24);
+        let yesterday = now.checked_sub(one_day).unwrap();
+
+        let mut program_times: HashMap<String, time::Duration> = HashMap::new();
+        program_times.insert("Application".to_string(), time::Duration::from_secs(5));
+        write_data_to_csv(&program_times, &CSV_NAME.to_string(), None, yesterday).unwrap();
+        program_times.insert("Test".to_string(), time::Duration::from_secs(10));
+        program_times.insert(
+            PAUSED_APPLICATION.to_string(),
+            time::Duration::from_secs(60),
+        );
+        write_data_to_csv(&program_times, &CSV_NAME.to_string(), None, now).unwrap();
+        write_data_to_csv(&program_times, &CSV_NAME.to_string(), None, now).unwrap();
+
+        let start = now.checked_sub(Duration::from_secs(60)).unwrap();
+        let end = now + Duration::from_secs(60);
+        let app_totals = read_app_totals(&CSV_NAME.to_string(), None, start, end).unwrap();
+        assert_eq!(app_totals.len(), 2);
+        assert_eq!(app_totals["Application"], Duration::from_secs(10));
+        assert_eq!(app_totals["Test"], Duration::from_secs(20));
+    }
+
+    #[test]
+    #[serial]
+    fn test_read_app_totals_without_csv() {
+        let (_temp_dir, _) = setup();
+        let app_totals =
+            read_app_totals(&CSV_NAME.to_string(), None, UNIX_EPOCH, SystemTime::now()).unwrap();
+        assert!(app_totals.is_empty());
+    }
+
     #[test]
     #[serial]
     fn test_remove_all_data() {
@@ -186,12 +509,18 @@ mod tests {
         let mut program_times: HashMap<String, time::Duration> = HashMap::new();
         program_times.insert("Application".to_string(), time::Duration::from_secs(0));
         program_times.insert("Test".to_string(), time::Duration::from_secs(10));
-        write_data_to_csv(&program_times, &CSV_NAME.to_string(), SystemTime::now()).unwrap();
+        write_data_to_csv(
+            &program_times,
+            &CSV_NAME.to_string(),
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
         let mut rows_vector = read_csv(&actual_path_to_csv).unwrap();
         assert_eq!(rows_vector.len(), 2);
 
         //Remove the oldest month of data, which deletes everything
-        remove_old_data(1, &CSV_NAME.to_string()).unwrap();
+        remove_old_data(1, &CSV_NAME.to_string(), None).unwrap();
         rows_vector = read_csv(&actual_path_to_csv).unwrap();
 
         println!("rows_vector after removal: {:?}", rows_vector);
@@ -207,15 +536,21 @@ mod tests {
 
         let mut program_times: HashMap<String, time::Duration> = HashMap::new();
         program_times.insert("Application".to_string(), time::Duration::from_secs(0));
-        write_data_to_csv(&program_times, &CSV_NAME.to_string(), two_months_ago).unwrap();
+        write_data_to_csv(&program_times, &CSV_NAME.to_string(), None, two_months_ago).unwrap();
         program_times.clear();
         program_times.insert("Test".to_string(), time::Duration::from_secs(10));
-        write_data_to_csv(&program_times, &CSV_NAME.to_string(), SystemTime::now()).unwrap();
+        write_data_to_csv(
+            &program_times,
+            &CSV_NAME.to_string(),
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
 
         let mut rows_vector = read_csv(&actual_path_to_csv).unwrap();
         assert_eq!(rows_vector.len(), 2);
 
-        remove_old_data(1, &CSV_NAME.to_string()).unwrap();
+        remove_old_data(1, &CSV_NAME.to_string(), None).unwrap();
         rows_vector = read_csv(&actual_path_to_csv).unwrap();
 
         println!("rows_vector after removal: {:?}", rows_vector);
@@ -223,4 +558,33 @@ mod tests {
         assert_eq!(rows_vector[0].application, "Test");
         assert_eq!(rows_vector[0].duration, 10);
     }
+
+    #[test]
+    #[serial]
+    fn test_encrypted_data_round_trip() {
+        let (_temp_dir, actual_path_to_csv) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let key_source = KeySource::Passphrase("passphrase".to_string());
+        let cipher = Cipher::open(&csv_name, &key_source).unwrap();
+        let two_months_ago = SystemTime::now()
+            .checked_sub(Duration::from_secs(60 * 60 * 24 * 60))
+            .unwrap();
+        let mut program_times: HashMap<String, time::Duration> = HashMap::new();
+        program_times.insert("Old".to_string(), time::Duration::from_secs(5));
+        write_data_to_csv(&program_times, &csv_name, Some(&cipher), two_months_ago).unwrap();
+        let mut program_times: HashMap<String, time::Duration> = HashMap::new();
+        program_times.insert("Code".to_string(), time::Duration::from_secs(10));
+        write_data_to_csv(&program_times, &csv_name, Some(&cipher), SystemTime::now()).unwrap();
+
+        assert!(read_csv(&actual_path_to_csv).is_err());
+        //Timestamps are rounded to the second
+        let end = SystemTime::now() + Duration::from_secs(60);
+        let app_totals = read_app_totals(&csv_name, Some(&cipher), UNIX_EPOCH, end).unwrap();
+        assert_eq!(app_totals.len(), 2);
+
+        remove_old_data(1, &csv_name, Some(&cipher)).unwrap();
+        let app_totals = read_app_totals(&csv_name, Some(&cipher), UNIX_EPOCH, end).unwrap();
+        assert_eq!(app_totals.len(), 1);
+        assert_eq!(app_totals["Code"], time::Duration::from_secs(10));
+    }
 }
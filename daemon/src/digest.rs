@@ -0,0 +1,313 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+use crate::csv_writer::read_app_totals;
+use crate::encryption::Cipher;
+use crate::limits::start_of_day;
+use crate::pause::PAUSED_APPLICATION;
+
+const TOP_APPS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DigestPeriod {
+    Daily,
+    Weekly,
+}
+
+impl DigestPeriod {
+    fn days(&self) -> u64 {
+        match self {
+            DigestPeriod::Daily => 1,
+            DigestPeriod::Weekly => 7,
+        }
+    }
+}
+
+// When to send the digests, the weekly recap is sent at the same time on weekly_day.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DigestSchedule {
+    pub time: NaiveTime,
+    pub weekly_day: Option<Weekday>,
+}
+
+pub fn parse_digest_time(digest_time: &str) -> Result<NaiveTime, &'static str> {
+    NaiveTime::parse_from_str(digest_time, "%H:%M")
+        .map_err(|_| "Digest time should be a time such as 21:00")
+}
+
+pub fn parse_weekday(weekday: &str) -> Result<Weekday, &'static str> {
+    weekday
+        .parse::<Weekday>()
+        .map_err(|_| "Weekly digest day should be a day such as Sunday")
+}
+
+// Decides when digests are due, so that each is sent once per day.
+pub struct DigestScheduler {
+    schedule: DigestSchedule,
+    last_sent: NaiveDate,
+}
+
+impl DigestScheduler {
+    //If the daemon starts after the digest time, the first digest is sent the next day.
+    pub fn new(schedule: DigestSchedule, now: NaiveDateTime) -> DigestScheduler {
+        let last_sent = if now.time() >= schedule.time {
+            now.date()
+        } else {
+            now.date() - Days::new(1)
+        };
+        DigestScheduler {
+            schedule,
+            last_sent,
+        }
+    }
+
+    pub fn due(&mut self, now: NaiveDateTime) -> Vec<DigestPeriod> {
+        if now.date() <= self.last_sent || now.time() < self.schedule.time {
+            return Vec::new();
+        }
+        self.last_sent = now.date();
+        let mut due = vec![DigestPeriod::Daily];
+        if self.schedule.weekly_day == Some(now.weekday()) {
+            due.push(DigestPeriod::Weekly);
+        }
+        due
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Digest {
+    pub period: DigestPeriod,
+    pub total: Duration,
+    // Total of the period before, i.e. yesterday or the week before
+    pub previous_total: Duration,
+    pub top_apps: Vec<(String, Duration)>,
+}
+
+// Build the digest of the period ending today, from the csv and the unflushed program_times.
+pub fn build_digest(
+    period: DigestPeriod,
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+    today: NaiveDate,
+    day_start_hour: u32,
+    program_times: &HashMap<String, Duration>,
+) -> Result<Digest, Box<dyn Error>> {
+    let days = Days::new(period.days());
+    let start = today - Days::new(period.days() - 1);
+    let mut app_totals = read_app_totals(
+        csv_name,
+        cipher,
+        start_of_day(start, day_start_hour),
+        start_of_day(today + Days::new(1), day_start_hour),
+    )?;
+    for (program_name, duration) in program_times {
+        if program_name != PAUSED_APPLICATION {
+            *app_totals.entry(program_name.to_string()).or_default() += *duration;
+        }
+    }
+    let previous_totals = read_app_totals(
+        csv_name,
+        cipher,
+        start_of_day(start - days, day_start_hour),
+        start_of_day(start, day_start_hour),
+    )?;
+
+    let mut top_apps: Vec<(String, Duration)> = app_totals.into_iter().collect();
+    top_apps.sort_by_key(|(app_name, duration)| (Reverse(*duration), app_name.clone()));
+    let total = top_apps.iter().map(|(_, duration)| *duration).sum();
+    top_apps.truncate(TOP_APPS);
+    Ok(Digest {
+        period,
+        total,
+        previous_total: previous_totals.values().sum(),
+        top_apps,
+    })
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{}m", minutes),
+        (hours, minutes) => format!("{}h {}m", hours, minutes),
+    }
+}
+
+pub fn format_digest(digest: &Digest) -> String {
+    let (period, previous_period) = match digest.period {
+        DigestPeriod::Daily => ("Today", "yesterday"),
+        DigestPeriod::Weekly => ("This week", "last week"),
+    };
+    let change = if digest.total >= digest.previous_total {
+        format!(
+            "{} more than {}",
+            format_duration(digest.total - digest.previous_total),
+            previous_period
+        )
+    } else {
+        format!(
+            "{} less than {}",
+            format_duration(digest.previous_total - digest.total),
+            previous_period
+        )
+    };
+    let mut digest_text = format!(
+        "{}: {} screen time, {}",
+        period,
+        format_duration(digest.total),
+        change
+    );
+    if !digest.top_apps.is_empty() {
+        let top_apps: Vec<String> = digest
+            .top_apps
+            .iter()
+            .map(|(app_name, duration)| format!("{} {}", app_name, format_duration(*duration)))
+            .collect();
+        digest_text.push_str(&format!("\nTop apps: {}", top_apps.join(", ")));
+    }
+    digest_text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_writer::write_data_to_csv;
+    use crate::test_helpers::tests::{minutes, setup, write_day, CSV_NAME};
+    use serial_test::serial;
+
+    fn at(date: NaiveDate, hour: u32) -> NaiveDateTime {
+        date.and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn parse_digest_settings() {
+        assert_eq!(
+            parse_digest_time("21:30").unwrap(),
+            NaiveTime::from_hms_opt(21, 30, 0).unwrap()
+        );
+        assert!(parse_digest_time("9pm").is_err());
+        assert_eq!(parse_weekday("Sunday").unwrap(), Weekday::Sun);
+        assert_eq!(parse_weekday("fri").unwrap(), Weekday::Fri);
+        assert!(parse_weekday("Someday").is_err());
+    }
+
+    #[test]
+    fn digests_are_due_once_a_day() {
+        let schedule = DigestSchedule {
+            time: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+            weekly_day: Some(Weekday::Sun),
+        };
+        //A Saturday
+        let saturday = NaiveDate::from_ymd_opt(2024, 3, 16).unwrap();
+        let mut scheduler = DigestScheduler::new(schedule, at(saturday, 9));
+        assert!(scheduler.due(at(saturday, 20)).is_empty());
+        assert_eq!(scheduler.due(at(saturday, 21)), vec![DigestPeriod::Daily]);
+        assert!(scheduler.due(at(saturday, 22)).is_empty());
+
+        let sunday = saturday.succ_opt().unwrap();
+        assert!(scheduler.due(at(sunday, 9)).is_empty());
+        assert_eq!(
+            scheduler.due(at(sunday, 21)),
+            vec![DigestPeriod::Daily, DigestPeriod::Weekly]
+        );
+    }
+
+    #[test]
+    fn starting_after_the_digest_time_waits_for_the_next_day() {
+        let schedule = DigestSchedule {
+            time: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+            weekly_day: None,
+        };
+        let day = NaiveDate::from_ymd_opt(2024, 3, 16).unwrap();
+        let mut scheduler = DigestScheduler::new(schedule, at(day, 22));
+        assert!(scheduler.due(at(day, 23)).is_empty());
+        assert_eq!(
+            scheduler.due(at(day.succ_opt().unwrap(), 21)),
+            vec![DigestPeriod::Daily]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn daily_digest_includes_unflushed_time() {
+        let (_temp_dir, _) = setup();
+        let today = NaiveDate::from_ymd_opt(2024, 3, 16).unwrap();
+        write_day(today.pred_opt().unwrap(), &[("Code", 120)]);
+        write_day(
+            today,
+            &[("Code", 60), ("Firefox", 30), ("Slack", 20), ("Paused", 50)],
+        );
+        let mut program_times = HashMap::new();
+        program_times.insert("Code".to_string(), minutes(30));
+        program_times.insert("Terminal".to_string(), minutes(10));
+
+        let digest = build_digest(
+            DigestPeriod::Daily,
+            &CSV_NAME.to_string(),
+            None,
+            today,
+            0,
+            &program_times,
+        )
+        .unwrap();
+        assert_eq!(digest.total, minutes(150));
+        assert_eq!(digest.previous_total, minutes(120));
+        assert_eq!(
+            format_digest(&digest),
+            "Today: 2h 30m screen time, 30m more than yesterday\nTop apps: Code 1h 30m, Firefox 30m, Slack 20m"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn late_night_counts_towards_the_day_before() {
+        let (_temp_dir, _) = setup();
+        let today = NaiveDate::from_ymd_opt(2024, 3, 16).unwrap();
+        write_day(today, &[("Code", 60)]);
+        let mut program_times = HashMap::new();
+        program_times.insert("Code".to_string(), minutes(30));
+        let two_am = start_of_day(today.succ_opt().unwrap(), 0) + Duration::from_secs(2 * 60 * 60);
+        write_data_to_csv(&program_times, &CSV_NAME.to_string(), None, two_am).unwrap();
+
+        let digest = |day_start_hour| {
+            build_digest(
+                DigestPeriod::Daily,
+                &CSV_NAME.to_string(),
+                None,
+                today,
+                day_start_hour,
+                &HashMap::new(),
+            )
+            .unwrap()
+        };
+        assert_eq!(digest(0).total, minutes(60));
+        assert_eq!(digest(4).total, minutes(90));
+    }
+
+    #[test]
+    #[serial]
+    fn weekly_digest_compares_with_the_week_before() {
+        let (_temp_dir, _) = setup();
+        let today = NaiveDate::from_ymd_opt(2024, 3, 17).unwrap();
+        write_day(today - Days::new(7), &[("Code", 300)]);
+        write_day(today - Days::new(6), &[("Code", 60)]);
+        write_day(today, &[("Firefox", 90)]);
+
+        let digest = build_digest(
+            DigestPeriod::Weekly,
+            &CSV_NAME.to_string(),
+            None,
+            today,
+            0,
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            format_digest(&digest),
+            "This week: 2h 30m screen time, 2h 30m less than last week\nTop apps: Firefox 1h 30m, Code 1h 0m"
+        );
+    }
+}
@@ -0,0 +1,391 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::csv_writer::write_atomically;
+
+// First line of an encrypted data file, followed by the salt of the key.
+pub const ENCRYPTED_FILE_MAGIC: &str = "#screen_timed-encrypted-v1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+// Where the secret the key is derived from comes from.
+#[derive(Clone, PartialEq)]
+pub enum KeySource {
+    // A file only readable by the user (0600)
+    Keyfile(PathBuf),
+    Passphrase(String),
+}
+
+// Never print the passphrase.
+impl fmt::Debug for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeySource::Keyfile(path) => write!(f, "Keyfile({})", path.display()),
+            KeySource::Passphrase(_) => write!(f, "Passphrase"),
+        }
+    }
+}
+
+impl KeySource {
+    fn secret(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            KeySource::Keyfile(path) => {
+                check_keyfile_permissions(path)?;
+                let secret = fs::read(path)?;
+                let secret = secret.trim_ascii().to_vec();
+                if secret.is_empty() {
+                    return Err(format!("Keyfile {} is empty", path.display()).into());
+                }
+                Ok(secret)
+            }
+            KeySource::Passphrase(passphrase) => Ok(passphrase.as_bytes().to_vec()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn check_keyfile_permissions(path: &Path) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "Keyfile {} should only be readable by you (chmod 600), it has mode {:o}",
+            path.display(),
+            mode & 0o777
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_keyfile_permissions(_path: &Path) -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
+
+// Encrypts the data file one appended record at a time.
+// The file is the magic line with the salt, then one base64 line of nonce and ciphertext per record.
+// Each record holds csv lines, the first record also holds the csv headers.
+#[derive(Clone)]
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+    salt: [u8; SALT_LEN],
+}
+
+impl Cipher {
+    //Uses the salt of the existing data file, or a new one if there is no data yet.
+    pub fn open(csv_name: &String, key_source: &KeySource) -> Result<Cipher, Box<dyn Error>> {
+        let salt = match read_salt(csv_name)? {
+            Some(salt) => salt,
+            None => {
+                if Path::new(csv_name).exists() && fs::metadata(csv_name)?.len() > 0 {
+                    return Err(format!(
+                        "{} is not encrypted, run `screen_timed encrypt` to encrypt it",
+                        csv_name
+                    )
+                    .into());
+                }
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                salt
+            }
+        };
+        Cipher::new(key_source, salt)
+    }
+
+    fn new(key_source: &KeySource, salt: [u8; SALT_LEN]) -> Result<Cipher, Box<dyn Error>> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(&key_source.secret()?, &salt, &mut key)
+            .map_err(|err| format!("Error deriving key: {}", err))?;
+        Ok(Cipher {
+            aead: XChaCha20Poly1305::new(&key.into()),
+            salt,
+        })
+    }
+
+    fn header_line(&self) -> String {
+        format!("{} {}\n", ENCRYPTED_FILE_MAGIC, STANDARD.encode(self.salt))
+    }
+
+    fn encrypt_record(&self, plaintext: &[u8]) -> Result<String, Box<dyn Error>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .aead
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| "Error encrypting record")?;
+        let mut record = nonce.to_vec();
+        record.extend(ciphertext);
+        Ok(STANDARD.encode(record))
+    }
+
+    fn decrypt_record(&self, record: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let record = STANDARD.decode(record.trim())?;
+        if record.len() < NONCE_LEN {
+            return Err("Encrypted record is too short".into());
+        }
+        let (nonce, ciphertext) = record.split_at(NONCE_LEN);
+        let plaintext = self
+            .aead
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Error decrypting record, the key is wrong or the data was modified")?;
+        Ok(plaintext)
+    }
+
+    //Append csv lines to the data file as one encrypted record.
+    pub fn append(&self, csv_name: &String, csv: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(csv_name)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(self.header_line().as_bytes())?;
+        }
+        file.write_all(format!("{}\n", self.encrypt_record(csv)?).as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+
+    // The header line and the csv as one encrypted record.
+    fn file_contents(&self, csv: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(format!("{}{}\n", self.header_line(), self.encrypt_record(csv)?).into_bytes())
+    }
+
+    //Replace the data file with the csv as one encrypted record.
+    pub fn write_file(&self, csv_name: &String, csv: &[u8]) -> Result<(), Box<dyn Error>> {
+        write_atomically(csv_name, &self.file_contents(csv)?)
+    }
+
+    //Decrypt the data file back into csv.
+    pub fn decrypt_file(&self, csv_name: &String) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.decrypt_all(BufReader::new(File::open(csv_name)?), csv_name)
+    }
+
+    //Decrypt the contents of a data file that is not on disk, such as one in a backup.
+    pub fn decrypt_contents(&self, contents: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.decrypt_all(contents, &"The data".to_string())
+    }
+
+    //Records that cannot be decrypted, such as a line torn by a crash, are skipped like malformed rows.
+    fn decrypt_all(
+        &self,
+        encrypted: impl BufRead,
+        csv_name: &String,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (csv, bad_records) = self.decrypt_lines(encrypted, csv_name)?;
+        if !bad_records.is_empty() {
+            eprintln!(
+                "Skipped {} records of {} that could not be decrypted, run `screen_timed repair` to move them out",
+                bad_records.len(),
+                csv_name
+            );
+        }
+        Ok(csv)
+    }
+
+    //Decrypt the records that can be, returning the lines of those that cannot, for repair.
+    pub fn decrypt_file_lenient(
+        &self,
+        csv_name: &String,
+    ) -> Result<(Vec<u8>, Vec<String>), Box<dyn Error>> {
+        self.decrypt_lines(BufReader::new(File::open(csv_name)?), csv_name)
+    }
+
+    fn decrypt_lines(
+        &self,
+        encrypted: impl BufRead,
+        csv_name: &String,
+    ) -> Result<(Vec<u8>, Vec<String>), Box<dyn Error>> {
+        let mut lines = encrypted.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        if !header.starts_with(ENCRYPTED_FILE_MAGIC) {
+            return Err(format!("{} is not encrypted", csv_name).into());
+        }
+        let mut csv = Vec::new();
+        let mut bad_records = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.decrypt_record(&line) {
+                Ok(plaintext) => csv.extend(plaintext),
+                Err(_) => bad_records.push(line),
+            }
+        }
+        // Nothing decrypting means the key is wrong rather than the data being damaged
+        if csv.is_empty() && !bad_records.is_empty() {
+            return Err(format!("Error decrypting {}, the key is wrong", csv_name).into());
+        }
+        Ok((csv, bad_records))
+    }
+}
+
+// The salt from the first line of an encrypted data file, None if it is not encrypted.
+fn read_salt(csv_name: &String) -> Result<Option<[u8; SALT_LEN]>, Box<dyn Error>> {
+    if !Path::new(csv_name).exists() {
+        return Ok(None);
+    }
+    let mut first_line = String::new();
+    BufReader::new(File::open(csv_name)?).read_line(&mut first_line)?;
+    let salt = match first_line.trim().strip_prefix(ENCRYPTED_FILE_MAGIC) {
+        Some(salt) => STANDARD.decode(salt.trim())?,
+        None => return Ok(None),
+    };
+    match salt.try_into() {
+        Ok(salt) => Ok(Some(salt)),
+        Err(_) => Err(format!("{} has an invalid salt", csv_name).into()),
+    }
+}
+
+pub fn is_encrypted(csv_name: &String) -> Result<bool, Box<dyn Error>> {
+    Ok(read_salt(csv_name)?.is_some())
+}
+
+// Encrypt an existing plain csv in place, checking it decrypts back before replacing it.
+pub fn encrypt_existing_csv(
+    csv_name: &String,
+    key_source: &KeySource,
+) -> Result<(), Box<dyn Error>> {
+    if is_encrypted(csv_name)? {
+        return Err(format!("{} is already encrypted", csv_name).into());
+    }
+    let csv = fs::read(csv_name)?;
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = Cipher::new(key_source, salt)?;
+
+    let contents = cipher.file_contents(&csv)?;
+    let (decrypted, _) = cipher.decrypt_lines(contents.as_slice(), csv_name)?;
+    if decrypted != csv {
+        return Err("Encrypted data did not decrypt back to the csv".into());
+    }
+    write_atomically(csv_name, &contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::tests::{setup, CSV_NAME};
+    use serial_test::serial;
+
+    fn passphrase() -> KeySource {
+        KeySource::Passphrase("correct horse battery staple".to_string())
+    }
+
+    #[test]
+    #[serial]
+    fn appended_records_decrypt_to_csv() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let cipher = Cipher::open(&csv_name, &passphrase()).unwrap();
+        cipher
+            .append(&csv_name, b"timestamp,application,duration\n1,Code,60\n")
+            .unwrap();
+        cipher.append(&csv_name, b"2,Firefox,30\n").unwrap();
+
+        let encrypted = fs::read_to_string(&csv_name).unwrap();
+        assert!(encrypted.starts_with(ENCRYPTED_FILE_MAGIC));
+        assert!(!encrypted.contains("Code"));
+        assert!(is_encrypted(&csv_name).unwrap());
+
+        //Reopening uses the salt from the file
+        let cipher = Cipher::open(&csv_name, &passphrase()).unwrap();
+        assert_eq!(
+            cipher.decrypt_file(&csv_name).unwrap(),
+            b"timestamp,application,duration\n1,Code,60\n2,Firefox,30\n"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn wrong_key_or_modified_data_fails() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let cipher = Cipher::open(&csv_name, &passphrase()).unwrap();
+        cipher.append(&csv_name, b"1,Code,60\n").unwrap();
+
+        let wrong_cipher =
+            Cipher::open(&csv_name, &KeySource::Passphrase("wrong".to_string())).unwrap();
+        assert!(wrong_cipher.decrypt_file(&csv_name).is_err());
+
+        let encrypted = fs::read_to_string(&csv_name).unwrap();
+        let (header, record) = encrypted.split_once('\n').unwrap();
+        let mut record = STANDARD.decode(record.trim()).unwrap();
+        let last = record.len() - 1;
+        record[last] ^= 1;
+        fs::write(
+            &csv_name,
+            format!("{}\n{}\n", header, STANDARD.encode(record)),
+        )
+        .unwrap();
+        assert!(cipher.decrypt_file(&csv_name).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn undecryptable_records_are_skipped() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let cipher = Cipher::open(&csv_name, &passphrase()).unwrap();
+        cipher
+            .append(&csv_name, b"timestamp,application,duration\n1,Code,60\n")
+            .unwrap();
+        cipher.append(&csv_name, b"2,Firefox,30\n").unwrap();
+        // A crash in the middle of the last append leaves it torn
+        let encrypted = fs::read_to_string(&csv_name).unwrap();
+        fs::write(&csv_name, &encrypted[..encrypted.len() - 10]).unwrap();
+
+        assert_eq!(
+            cipher.decrypt_file(&csv_name).unwrap(),
+            b"timestamp,application,duration\n1,Code,60\n"
+        );
+        let wrong_cipher =
+            Cipher::open(&csv_name, &KeySource::Passphrase("wrong".to_string())).unwrap();
+        assert!(wrong_cipher.decrypt_file(&csv_name).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn plain_csv_is_encrypted_in_place() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let csv = b"timestamp,application,duration\n1,Code,60\n";
+        fs::write(&csv_name, csv).unwrap();
+        assert!(Cipher::open(&csv_name, &passphrase()).is_err());
+
+        encrypt_existing_csv(&csv_name, &passphrase()).unwrap();
+        assert!(is_encrypted(&csv_name).unwrap());
+        let cipher = Cipher::open(&csv_name, &passphrase()).unwrap();
+        assert_eq!(cipher.decrypt_file(&csv_name).unwrap(), csv);
+        assert!(encrypt_existing_csv(&csv_name, &passphrase()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial]
+    fn keyfile_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (temp_dir, _) = setup();
+        let keyfile = temp_dir.path().join("screen_timed.key");
+        fs::write(&keyfile, "0123456789abcdef0123456789abcdef\n").unwrap();
+        fs::set_permissions(&keyfile, fs::Permissions::from_mode(0o644)).unwrap();
+        let key_source = KeySource::Keyfile(keyfile.clone());
+        assert!(Cipher::open(&CSV_NAME.to_string(), &key_source).is_err());
+
+        fs::set_permissions(&keyfile, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(Cipher::open(&CSV_NAME.to_string(), &key_source).is_ok());
+    }
+}
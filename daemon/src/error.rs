@@ -0,0 +1,174 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::notification::{error_alert, Notifier};
+
+// Every failure in the daemon. ErrorPolicy decides how each one is handled.
+#[derive(Debug)]
+pub enum DaemonError {
+    // Missing or invalid .env configuration
+    Config(String),
+    // Registering the OS signal handlers
+    Signals(io::Error),
+    // Spawning or joining a thread
+    Thread(String),
+    // Setting up, connecting to or closing the socket
+    Socket { context: String, source: io::Error },
+    // Handling a single socket client
+    SocketClient(String),
+    // Writing program_times to the csv
+    CsvWrite(String),
+    // Reading the csv or the current path to it
+    CsvRead(String),
+    // Taking or restoring a backup of the data file
+    Backup(String),
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DaemonError::Config(err) => write!(f, "Error loading config: {}", err),
+            DaemonError::Signals(err) => write!(f, "Error registering signals: {}", err),
+            DaemonError::Thread(err) => write!(f, "Thread error: {}", err),
+            DaemonError::Socket { context, source } => write!(f, "{}: {}", context, source),
+            DaemonError::SocketClient(err) => write!(f, "Error handling socket client: {}", err),
+            DaemonError::CsvWrite(err) => write!(f, "Error writing to csv: {}", err),
+            DaemonError::CsvRead(err) => write!(f, "Error reading csv: {}", err),
+            DaemonError::Backup(err) => write!(f, "Error with backup: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+impl DaemonError {
+    pub fn socket(context: &str, source: io::Error) -> DaemonError {
+        DaemonError::Socket {
+            context: context.to_string(),
+            source,
+        }
+    }
+
+    pub fn policy(&self) -> ErrorPolicy {
+        match self {
+            DaemonError::Config(_) | DaemonError::Signals(_) | DaemonError::Thread(_) => {
+                ErrorPolicy::Fatal
+            }
+            DaemonError::CsvWrite(_) => ErrorPolicy::Retry,
+            DaemonError::Socket { .. }
+            | DaemonError::SocketClient(_)
+            | DaemonError::CsvRead(_)
+            | DaemonError::Backup(_) => ErrorPolicy::Continue,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorPolicy {
+    // Flush program_times and shut down
+    Fatal,
+    // Keep the data in memory and try again on the next flush
+    Retry,
+    // Log it and carry on tracking
+    Continue,
+}
+
+// Reports errors to the user and applies their policy.
+pub struct ErrorHandler {
+    notifier: Arc<dyn Notifier>,
+    // Whether a retried error has already been reported
+    retrying: bool,
+}
+
+impl ErrorHandler {
+    pub fn new(notifier: Arc<dyn Notifier>) -> ErrorHandler {
+        ErrorHandler {
+            notifier,
+            retrying: false,
+        }
+    }
+
+    //Returns the error back if it is fatal, otherwise tracking can carry on.
+    pub fn handle(&mut self, err: DaemonError) -> Result<(), DaemonError> {
+        match err.policy() {
+            ErrorPolicy::Fatal => {
+                self.report(&err);
+                Err(err)
+            }
+            ErrorPolicy::Retry => {
+                // Only notify on the first failure, not every retry
+                if self.retrying {
+                    eprintln!("{}", err);
+                } else {
+                    self.report(&err);
+                    self.retrying = true;
+                }
+                Ok(())
+            }
+            ErrorPolicy::Continue => {
+                self.report(&err);
+                Ok(())
+            }
+        }
+    }
+
+    //Called when a retried operation succeeds.
+    pub fn succeeded(&mut self) {
+        self.retrying = false;
+    }
+
+    pub fn report(&self, err: &DaemonError) {
+        eprintln!("{}", err);
+        self.notifier.notify(&error_alert(&err.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notification::RecordingNotifier;
+
+    #[test]
+    fn error_policies() {
+        assert_eq!(
+            DaemonError::Config("missing".to_string()).policy(),
+            ErrorPolicy::Fatal
+        );
+        assert_eq!(
+            DaemonError::CsvWrite("disk full".to_string()).policy(),
+            ErrorPolicy::Retry
+        );
+        assert_eq!(
+            DaemonError::SocketClient("bad request".to_string()).policy(),
+            ErrorPolicy::Continue
+        );
+    }
+
+    #[test]
+    fn fatal_errors_are_returned_and_reported() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut error_handler = ErrorHandler::new(notifier.clone());
+        let result = error_handler.handle(DaemonError::Thread("panicked".to_string()));
+        assert!(matches!(result, Err(DaemonError::Thread(_))));
+        assert_eq!(notifier.alerts().len(), 1);
+        assert_eq!(notifier.alerts()[0].body, "Thread error: panicked");
+    }
+
+    #[test]
+    fn retried_errors_are_reported_once() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut error_handler = ErrorHandler::new(notifier.clone());
+        for _ in 0..3 {
+            let result = error_handler.handle(DaemonError::CsvWrite("disk full".to_string()));
+            assert!(result.is_ok());
+        }
+        assert_eq!(notifier.alerts().len(), 1);
+
+        error_handler.succeeded();
+        error_handler
+            .handle(DaemonError::CsvWrite("disk full".to_string()))
+            .unwrap();
+        assert_eq!(notifier.alerts().len(), 2);
+    }
+}
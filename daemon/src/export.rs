@@ -0,0 +1,344 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Days, FixedOffset, Local, NaiveDate};
+use csv::WriterBuilder;
+use serde_derive::Serialize;
+
+use crate::csv_writer::{read_valid_rows, Row};
+use crate::encryption::Cipher;
+use crate::limits::{day_of, start_of_day};
+use crate::pause::PAUSED_APPLICATION;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    // One JSON object per line
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportAggregation {
+    // Every row as recorded
+    Rows,
+    // Total per application over the range
+    App,
+    // Total per day
+    Day,
+    // Total per application per day
+    DayApp,
+}
+
+// What to export, parsed from "EXPORT <start> <end> [csv|json|ndjson] [app|day|day-app]".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportRequest {
+    pub start: NaiveDate,
+    // Inclusive
+    pub end: NaiveDate,
+    pub format: ExportFormat,
+    pub aggregation: ExportAggregation,
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date {}, dates should be such as 2024-03-16", date))
+}
+
+impl ExportRequest {
+    pub fn parse(request: &str) -> Result<ExportRequest, String> {
+        let args: Vec<&str> = request.split_whitespace().collect();
+        let (start, end, options) = match args.as_slice() {
+            [start, end, options @ ..] if options.len() <= 2 => {
+                (parse_date(start)?, parse_date(end)?, options)
+            }
+            _ => return Err("Export needs a start and an end date".to_string()),
+        };
+        if end < start {
+            return Err("The end date is before the start date".to_string());
+        }
+        let mut export_request = ExportRequest {
+            start,
+            end,
+            format: ExportFormat::Csv,
+            aggregation: ExportAggregation::Rows,
+        };
+        for option in options {
+            match *option {
+                "csv" => export_request.format = ExportFormat::Csv,
+                "json" => export_request.format = ExportFormat::Json,
+                "ndjson" => export_request.format = ExportFormat::Ndjson,
+                "app" => export_request.aggregation = ExportAggregation::App,
+                "day" => export_request.aggregation = ExportAggregation::Day,
+                "day-app" => export_request.aggregation = ExportAggregation::DayApp,
+                option => return Err(format!("Unknown export option: {}", option)),
+            }
+        }
+        Ok(export_request)
+    }
+}
+
+// A row, or a total when aggregating. The columns depend on the aggregation.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ExportRecord {
+    //RFC 3339 time the row was written, in the UTC offset it was written in if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    application: Option<String>,
+    //How long in seconds the application was active
+    duration: u64,
+    //The machine the row was recorded on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+}
+
+// Export the data from the csv and the unflushed program_times within the request's dates.
+pub fn export_data(
+    export_request: &ExportRequest,
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+    program_times: &HashMap<String, Duration>,
+    day_start_hour: u32,
+) -> Result<String, Box<dyn Error>> {
+    let start = start_of_day(export_request.start, day_start_hour);
+    let end = start_of_day(export_request.end + Days::new(1), day_start_hour);
+    let in_range = |timestamp: SystemTime| timestamp >= start && timestamp < end;
+    let mut rows: Vec<Row> = read_valid_rows(csv_name, cipher)?
+        .into_iter()
+        .filter(|row| in_range(row.timestamp()))
+        .collect();
+    let now = SystemTime::now();
+    if in_range(now) {
+        for (program_name, duration) in program_times {
+            rows.push(Row::new(now, program_name, duration.as_secs()));
+        }
+    }
+    format_records(
+        export_request.format,
+        &build_records(export_request.aggregation, rows, day_start_hour),
+    )
+}
+
+fn local_date(timestamp: SystemTime, day_start_hour: u32) -> NaiveDate {
+    day_of(DateTime::<Local>::from(timestamp), day_start_hour)
+}
+
+fn format_timestamp(row: &Row) -> String {
+    match row.utc_offset().and_then(FixedOffset::east_opt) {
+        Some(utc_offset) => DateTime::<Local>::from(row.timestamp())
+            .with_timezone(&utc_offset)
+            .to_rfc3339(),
+        None => DateTime::<Local>::from(row.timestamp()).to_rfc3339(),
+    }
+}
+
+// Paused time is only kept in the rows as recorded, not in the totals. Totals combine every host.
+fn build_records(
+    aggregation: ExportAggregation,
+    mut rows: Vec<Row>,
+    day_start_hour: u32,
+) -> Vec<ExportRecord> {
+    let record =
+        |date: Option<NaiveDate>, application: Option<String>, duration: u64| ExportRecord {
+            timestamp: None,
+            date: date.map(|date| date.to_string()),
+            application,
+            duration,
+            host: None,
+        };
+    if aggregation != ExportAggregation::Rows {
+        rows.retain(|row| row.application() != PAUSED_APPLICATION);
+    }
+    match aggregation {
+        ExportAggregation::Rows => {
+            rows.sort_by_key(Row::timestamp);
+            rows.iter()
+                .map(|row| ExportRecord {
+                    timestamp: Some(format_timestamp(row)),
+                    date: None,
+                    application: Some(row.application().to_string()),
+                    duration: row.duration(),
+                    host: Some(row.host().to_string()),
+                })
+                .collect()
+        }
+        ExportAggregation::App => {
+            let mut totals: HashMap<String, u64> = HashMap::new();
+            for row in rows {
+                *totals.entry(row.application().to_string()).or_default() += row.duration();
+            }
+            let mut totals: Vec<(String, u64)> = totals.into_iter().collect();
+            totals.sort_by_key(|(application, duration)| (Reverse(*duration), application.clone()));
+            totals
+                .into_iter()
+                .map(|(application, duration)| record(None, Some(application), duration))
+                .collect()
+        }
+        ExportAggregation::Day => {
+            let mut totals: BTreeMap<NaiveDate, u64> = BTreeMap::new();
+            for row in rows {
+                *totals
+                    .entry(local_date(row.timestamp(), day_start_hour))
+                    .or_default() += row.duration();
+            }
+            totals
+                .into_iter()
+                .map(|(date, duration)| record(Some(date), None, duration))
+                .collect()
+        }
+        ExportAggregation::DayApp => {
+            let mut totals: BTreeMap<(NaiveDate, String), u64> = BTreeMap::new();
+            for row in rows {
+                *totals
+                    .entry((
+                        local_date(row.timestamp(), day_start_hour),
+                        row.application().to_string(),
+                    ))
+                    .or_default() += row.duration();
+            }
+            totals
+                .into_iter()
+                .map(|((date, application), duration)| {
+                    record(Some(date), Some(application), duration)
+                })
+                .collect()
+        }
+    }
+}
+
+fn format_records(
+    format: ExportFormat,
+    records: &[ExportRecord],
+) -> Result<String, Box<dyn Error>> {
+    match format {
+        ExportFormat::Csv => {
+            let mut wtr = WriterBuilder::new().from_writer(Vec::new());
+            for record in records {
+                wtr.serialize(record)?;
+            }
+            Ok(String::from_utf8(wtr.into_inner()?)?)
+        }
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(records)?),
+        ExportFormat::Ndjson => {
+            let mut lines = Vec::new();
+            for record in records {
+                lines.push(serde_json::to_string(record)?);
+            }
+            Ok(lines.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::local_host_id;
+    use crate::schema::schema_version_line;
+    use crate::test_helpers::tests::{setup, write_day, CSV_NAME};
+    use serial_test::serial;
+    use std::fs;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+    }
+
+    fn export(request: &str) -> String {
+        export_data(
+            &ExportRequest::parse(request).unwrap(),
+            &CSV_NAME.to_string(),
+            None,
+            &HashMap::new(),
+            0,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_export_requests() {
+        assert_eq!(
+            ExportRequest::parse("2024-03-01 2024-03-31").unwrap(),
+            ExportRequest {
+                start: date(1),
+                end: date(31),
+                format: ExportFormat::Csv,
+                aggregation: ExportAggregation::Rows,
+            }
+        );
+        let export_request = ExportRequest::parse("2024-03-01 2024-03-01 ndjson day-app").unwrap();
+        assert_eq!(export_request.format, ExportFormat::Ndjson);
+        assert_eq!(export_request.aggregation, ExportAggregation::DayApp);
+        assert!(ExportRequest::parse("2024-03-01").is_err());
+        assert!(ExportRequest::parse("2024-03-02 2024-03-01").is_err());
+        assert!(ExportRequest::parse("2024-03-01 2024-03-02 xml").is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn export_totals_per_app_and_day() {
+        let (_temp_dir, _) = setup();
+        write_day(date(1), &[("Code", 1), ("Paused", 10)]);
+        write_day(date(2), &[("Code", 2), ("Firefox", 4)]);
+        write_day(date(3), &[("Code", 20)]);
+
+        assert_eq!(
+            export("2024-03-01 2024-03-02 app"),
+            "application,duration\nFirefox,240\nCode,180\n"
+        );
+        assert_eq!(
+            export("2024-03-01 2024-03-02 json day"),
+            "[\n  {\n    \"date\": \"2024-03-01\",\n    \"duration\": 60\n  },\n  {\n    \"date\": \"2024-03-02\",\n    \"duration\": 360\n  }\n]"
+        );
+        assert_eq!(
+            export("2024-03-02 2024-03-02 ndjson day-app"),
+            "{\"date\":\"2024-03-02\",\"application\":\"Code\",\"duration\":120}\n{\"date\":\"2024-03-02\",\"application\":\"Firefox\",\"duration\":240}"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn export_rows_as_recorded() {
+        let (_temp_dir, _) = setup();
+        write_day(date(1), &[("Paused", 10)]);
+        let midday =
+            DateTime::<Local>::from(start_of_day(date(1), 0) + Duration::from_secs(12 * 60 * 60));
+        assert_eq!(
+            export("2024-03-01 2024-03-01"),
+            format!(
+                "timestamp,application,duration,host\n{},Paused,600,{}\n",
+                midday.to_rfc3339(),
+                local_host_id()
+            )
+        );
+        assert_eq!(export("2024-03-02 2024-03-02 json"), "[]");
+    }
+
+    #[test]
+    #[serial]
+    fn rows_keep_the_offset_they_were_recorded_in() {
+        let (_temp_dir, _) = setup();
+        let midday =
+            DateTime::<Local>::from(start_of_day(date(1), 0) + Duration::from_secs(12 * 60 * 60));
+        fs::write(
+            CSV_NAME,
+            format!(
+                "{}timestamp,application,duration,host,utc_offset\n{},Code,60,laptop,-18000\n",
+                schema_version_line(),
+                midday.timestamp()
+            ),
+        )
+        .unwrap();
+        let recorded = midday.with_timezone(&FixedOffset::west_opt(5 * 60 * 60).unwrap());
+        assert_eq!(
+            export("2024-03-01 2024-03-01"),
+            format!(
+                "timestamp,application,duration,host\n{},Code,60,laptop\n",
+                recorded.to_rfc3339()
+            )
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use csv::{ReaderBuilder, WriterBuilder};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::digest::format_duration;
+use crate::encryption::{is_encrypted, Cipher};
+use crate::screen_time::FocusedWindow;
+
+// Finished focus sessions, one row each, kept next to the csv.
+pub const FOCUS_SESSIONS_FILE_NAME: &str = "focus_sessions.csv";
+// How long an application that is not allowed can be in focus before it counts as a distraction
+const DISTRACTION_GRACE: Duration = Duration::from_secs(30);
+
+//Parse the arguments of a FOCUS START request, e.g. "45 Code,Alacritty" for 45 minutes.
+pub fn parse_focus_request(request: &str) -> Result<(Duration, Vec<String>), &'static str> {
+    let (minutes, apps) = match request.trim().split_once(' ') {
+        Some((minutes, apps)) => (minutes, apps),
+        None => return Err("A focus session needs minutes and the allowed applications"),
+    };
+    let minutes: u64 = match minutes.parse() {
+        Ok(minutes) if minutes > 0 => minutes,
+        _ => return Err("Focus session minutes should be a positive number"),
+    };
+    let apps: Vec<String> = apps
+        .split(',')
+        .map(|app| app.trim().to_string())
+        .filter(|app| !app.is_empty())
+        .collect();
+    if apps.is_empty() {
+        return Err("A focus session needs at least one allowed application");
+    }
+    Ok((Duration::from_secs(minutes * 60), apps))
+}
+
+// A focus session as it is stored once it is over.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FocusSession {
+    //Unix time in seconds
+    pub started: u64,
+    pub planned_minutes: u64,
+    //Joined with '+', like the applications of a daily limit
+    pub allowed_apps: String,
+    //Seconds in the allowed applications and in the others
+    pub focused: u64,
+    pub distracted: u64,
+    //Times another application stayed in focus for longer than the grace period
+    pub distractions: u32,
+    //False if it was stopped before the planned time
+    pub completed: bool,
+}
+
+// The running session, for the FOCUS socket request.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FocusStatus {
+    pub allowed_apps: Vec<String>,
+    pub planned_minutes: u64,
+    //Seconds
+    pub remaining: u64,
+    pub focused: u64,
+    pub distracted: u64,
+    pub distractions: u32,
+}
+
+#[derive(Debug)]
+struct ActiveSession {
+    started: SystemTime,
+    duration: Duration,
+    allowed_apps: Vec<String>,
+    focused: Duration,
+    distracted: Duration,
+    distractions: u32,
+    // How long other applications have been in focus since the last allowed one
+    off_list: Duration,
+    stopped: bool,
+}
+
+impl ActiveSession {
+    fn allows(&self, app_name: &str) -> bool {
+        self.allowed_apps
+            .iter()
+            .any(|app| app.eq_ignore_ascii_case(app_name))
+    }
+}
+
+// Shared between the socket listener thread (which starts and stops sessions) and the
+// run() loop (which samples the focused window into the session and finishes it).
+#[derive(Debug, Clone, Default)]
+pub struct FocusState {
+    session: Arc<Mutex<Option<ActiveSession>>>,
+}
+
+impl FocusState {
+    // The session is plain data, so it is still usable if a thread panicked while holding it
+    fn session(&self) -> MutexGuard<'_, Option<ActiveSession>> {
+        self.session
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn start(
+        &self,
+        duration: Duration,
+        allowed_apps: Vec<String>,
+        now: SystemTime,
+    ) -> Result<(), &'static str> {
+        let mut session = self.session();
+        if session.is_some() {
+            return Err("A focus session is already running");
+        }
+        *session = Some(ActiveSession {
+            started: now,
+            duration,
+            allowed_apps,
+            focused: Duration::ZERO,
+            distracted: Duration::ZERO,
+            distractions: 0,
+            off_list: Duration::ZERO,
+            stopped: false,
+        });
+        Ok(())
+    }
+
+    //End the session early, the run() loop stores it on its next tick.
+    pub fn stop(&self) -> Result<(), &'static str> {
+        match self.session().as_mut() {
+            Some(session) => {
+                session.stopped = true;
+                Ok(())
+            }
+            None => Err("No focus session is running"),
+        }
+    }
+
+    pub fn status(&self, now: SystemTime) -> Option<FocusStatus> {
+        self.session().as_ref().map(|session| {
+            let elapsed = now.duration_since(session.started).unwrap_or_default();
+            FocusStatus {
+                allowed_apps: session.allowed_apps.clone(),
+                planned_minutes: session.duration.as_secs() / 60,
+                remaining: session.duration.saturating_sub(elapsed).as_secs(),
+                focused: session.focused.as_secs(),
+                distracted: session.distracted.as_secs(),
+                distractions: session.distractions,
+            }
+        })
+    }
+
+    //Count a one second sample of the focused window towards the session.
+    //Returns the name of the application if it has just become a distraction.
+    pub fn sample(&self, focused_window: &FocusedWindow) -> Option<String> {
+        let mut session = self.session();
+        let session = session.as_mut().filter(|session| !session.stopped)?;
+        let app_name = match focused_window {
+            FocusedWindow::None => return None,
+            FocusedWindow::App(app_name) if session.allows(app_name) => {
+                session.focused += Duration::from_secs(1);
+                session.off_list = Duration::ZERO;
+                return None;
+            }
+            FocusedWindow::App(app_name) => app_name.as_str(),
+            FocusedWindow::Private => "A private window",
+        };
+        session.distracted += Duration::from_secs(1);
+        session.off_list += Duration::from_secs(1);
+        if session.off_list != DISTRACTION_GRACE {
+            return None;
+        }
+        session.distractions += 1;
+        Some(app_name.to_string())
+    }
+
+    //Take the session out once its time is up or it was stopped.
+    pub fn finish_if_over(&self, now: SystemTime) -> Option<FocusSession> {
+        let mut session = self.session();
+        let elapsed = now
+            .duration_since(session.as_ref()?.started)
+            .unwrap_or_default();
+        let completed = elapsed >= session.as_ref()?.duration;
+        if !completed && !session.as_ref()?.stopped {
+            return None;
+        }
+        let session = session.take()?;
+        Some(FocusSession {
+            started: session
+                .started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            planned_minutes: session.duration.as_secs() / 60,
+            allowed_apps: session.allowed_apps.join("+"),
+            focused: session.focused.as_secs(),
+            distracted: session.distracted.as_secs(),
+            distractions: session.distractions,
+            completed,
+        })
+    }
+}
+
+fn session_rows(sessions: &[FocusSession], has_headers: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut wtr = WriterBuilder::new()
+        .has_headers(has_headers)
+        .from_writer(Vec::new());
+    for session in sessions {
+        wtr.serialize(session)?;
+    }
+    Ok(wtr.into_inner()?)
+}
+
+//Sessions are encrypted like the data file when it is, as they name the applications used.
+pub fn store_focus_session(
+    sessions_path: &String,
+    session: &FocusSession,
+    cipher: Option<&Cipher>,
+) -> Result<(), Box<dyn Error>> {
+    match cipher {
+        Some(cipher) if is_encrypted(sessions_path)? => cipher.append(
+            sessions_path,
+            &session_rows(std::slice::from_ref(session), false)?,
+        ),
+        // A new history, or one kept before encryption was turned on, is written encrypted as a whole
+        Some(cipher) => {
+            let mut sessions = read_focus_sessions(sessions_path, None)?;
+            sessions.push(session.clone());
+            cipher.write_file(sessions_path, &session_rows(&sessions, true)?)
+        }
+        None => {
+            let is_new = !Path::new(sessions_path).exists();
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(sessions_path)?;
+            let mut wtr = WriterBuilder::new().has_headers(is_new).from_writer(file);
+            wtr.serialize(session)?;
+            wtr.flush()?;
+            Ok(())
+        }
+    }
+}
+
+pub fn read_focus_sessions(
+    sessions_path: &String,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<FocusSession>, Box<dyn Error>> {
+    if !Path::new(sessions_path).exists() {
+        return Ok(Vec::new());
+    }
+    let sessions_csv = match cipher {
+        Some(cipher) if is_encrypted(sessions_path)? => cipher.decrypt_file(sessions_path)?,
+        _ => fs::read(sessions_path)?,
+    };
+    let mut rdr = ReaderBuilder::new().from_reader(sessions_csv.as_slice());
+    let mut sessions = Vec::new();
+    for result in rdr.deserialize() {
+        sessions.push(result?);
+    }
+    Ok(sessions)
+}
+
+// Format the running session for the FOCUS socket request.
+pub fn format_focus_status(focus_status: Option<&FocusStatus>) -> String {
+    match focus_status {
+        Some(focus_status) => format!(
+            "Focusing on {} for {} minutes: {} left, {} focused, {} on other applications, {} distractions",
+            focus_status.allowed_apps.join(", "),
+            focus_status.planned_minutes,
+            format_duration(Duration::from_secs(focus_status.remaining)),
+            format_duration(Duration::from_secs(focus_status.focused)),
+            format_duration(Duration::from_secs(focus_status.distracted)),
+            focus_status.distractions
+        ),
+        None => "No focus session is running".to_string(),
+    }
+}
+
+// Format the stored sessions, one per line, for the FOCUS SESSIONS socket request.
+pub fn format_focus_sessions(sessions: &[FocusSession]) -> String {
+    sessions
+        .iter()
+        .map(|session| {
+            let started =
+                DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(session.started));
+            format!(
+                "{} {} for {} minutes{}: {} focused, {} on other applications, {} distractions",
+                started.format("%Y-%m-%d %H:%M"),
+                session.allowed_apps,
+                session.planned_minutes,
+                if session.completed { "" } else { " (stopped)" },
+                format_duration(Duration::from_secs(session.focused)),
+                format_duration(Duration::from_secs(session.distracted)),
+                session.distractions
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::KeySource;
+    use crate::test_helpers::tests::{setup, CSV_NAME};
+    use serial_test::serial;
+
+    fn app(app_name: &str) -> FocusedWindow {
+        FocusedWindow::App(app_name.to_string())
+    }
+
+    fn sample_for(
+        focus_state: &FocusState,
+        focused_window: &FocusedWindow,
+        secs: u64,
+    ) -> Vec<String> {
+        (0..secs)
+            .filter_map(|_| focus_state.sample(focused_window))
+            .collect()
+    }
+
+    #[test]
+    fn parse_focus_requests() {
+        assert_eq!(
+            parse_focus_request("45 Code, Alacritty").unwrap(),
+            (
+                Duration::from_secs(45 * 60),
+                vec!["Code".to_string(), "Alacritty".to_string()]
+            )
+        );
+        assert!(parse_focus_request("45").is_err());
+        assert!(parse_focus_request("0 Code").is_err());
+        assert!(parse_focus_request("soon Code").is_err());
+        assert!(parse_focus_request("45 ,").is_err());
+    }
+
+    #[test]
+    fn distractions_are_counted_after_the_grace_period() {
+        let focus_state = FocusState::default();
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        focus_state
+            .start(
+                Duration::from_secs(45 * 60),
+                vec!["Code".to_string()],
+                started,
+            )
+            .unwrap();
+        assert!(focus_state
+            .start(Duration::from_secs(60), vec!["Code".to_string()], started)
+            .is_err());
+
+        assert!(sample_for(&focus_state, &app("code"), 60).is_empty());
+        // A quick look is time on other applications, not a distraction
+        assert!(sample_for(&focus_state, &app("Firefox"), 10).is_empty());
+        assert!(sample_for(&focus_state, &FocusedWindow::None, 5).is_empty());
+        assert!(sample_for(&focus_state, &app("Code"), 1).is_empty());
+        assert_eq!(
+            sample_for(&focus_state, &app("Firefox"), 40),
+            vec!["Firefox".to_string()]
+        );
+
+        let focus_status = focus_state
+            .status(started + Duration::from_secs(15 * 60))
+            .unwrap();
+        assert_eq!(
+            (
+                focus_status.focused,
+                focus_status.distracted,
+                focus_status.distractions
+            ),
+            (61, 50, 1)
+        );
+        assert_eq!(focus_status.remaining, 30 * 60);
+        assert_eq!(
+            format_focus_status(Some(&focus_status)),
+            "Focusing on Code for 45 minutes: 30m left, 1m focused, 0m on other applications, 1 distractions"
+        );
+    }
+
+    #[test]
+    fn sessions_finish_when_their_time_is_up_or_they_are_stopped() {
+        let focus_state = FocusState::default();
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        focus_state
+            .start(
+                Duration::from_secs(25 * 60),
+                vec!["Code".to_string()],
+                started,
+            )
+            .unwrap();
+        assert_eq!(
+            focus_state.finish_if_over(started + Duration::from_secs(60)),
+            None
+        );
+        let session = focus_state
+            .finish_if_over(started + Duration::from_secs(25 * 60))
+            .unwrap();
+        assert!(session.completed);
+        assert_eq!(focus_state.status(started), None);
+
+        assert!(focus_state.stop().is_err());
+        focus_state
+            .start(
+                Duration::from_secs(25 * 60),
+                vec!["Code".to_string()],
+                started,
+            )
+            .unwrap();
+        focus_state.stop().unwrap();
+        // Nothing is counted once it is stopped
+        assert!(sample_for(&focus_state, &app("Code"), 5).is_empty());
+        let session = focus_state
+            .finish_if_over(started + Duration::from_secs(60))
+            .unwrap();
+        assert!(!session.completed);
+        assert_eq!(session.focused, 0);
+    }
+
+    fn session() -> FocusSession {
+        FocusSession {
+            started: 1_700_000_000,
+            planned_minutes: 45,
+            allowed_apps: "Code+Alacritty".to_string(),
+            focused: 2400,
+            distracted: 300,
+            distractions: 2,
+            completed: true,
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn store_and_read_sessions() {
+        let (_temp_dir, _) = setup();
+        let sessions_path = FOCUS_SESSIONS_FILE_NAME.to_string();
+        assert!(read_focus_sessions(&sessions_path, None)
+            .unwrap()
+            .is_empty());
+        store_focus_session(&sessions_path, &session(), None).unwrap();
+        store_focus_session(&sessions_path, &session(), None).unwrap();
+        assert_eq!(
+            read_focus_sessions(&sessions_path, None).unwrap(),
+            vec![session(), session()]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn sessions_are_encrypted_with_the_data_file() {
+        let (_temp_dir, _) = setup();
+        let sessions_path = FOCUS_SESSIONS_FILE_NAME.to_string();
+        // Kept before encryption was turned on
+        store_focus_session(&sessions_path, &session(), None).unwrap();
+        let cipher = Cipher::open(
+            &CSV_NAME.to_string(),
+            &KeySource::Passphrase("passphrase".to_string()),
+        )
+        .unwrap();
+        store_focus_session(&sessions_path, &session(), Some(&cipher)).unwrap();
+        store_focus_session(&sessions_path, &session(), Some(&cipher)).unwrap();
+
+        assert!(is_encrypted(&sessions_path).unwrap());
+        assert!(!fs::read_to_string(&sessions_path)
+            .unwrap()
+            .contains("Alacritty"));
+        assert_eq!(
+            read_focus_sessions(&sessions_path, Some(&cipher)).unwrap(),
+            vec![session(), session(), session()]
+        );
+    }
+}
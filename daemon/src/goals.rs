@@ -0,0 +1,565 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, NaiveDate};
+use serde_derive::Serialize;
+
+use crate::csv_writer::{read_valid_rows, write_atomically};
+use crate::digest::format_duration;
+use crate::encryption::{is_encrypted, Cipher};
+use crate::limits::{day_of, start_of_day};
+use crate::pause::PAUSED_APPLICATION;
+
+// Whether each goal was met on each day, kept next to the csv.
+pub const GOAL_HISTORY_FILE_NAME: &str = "goal_history.json";
+// Percentage of an at most goal used after which its streak is at risk
+const AT_RISK_PERCENTAGE: u64 = 80;
+// An at least goal is at risk once the day has less than this left beyond the time still needed
+const AT_RISK_MARGIN: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GoalKind {
+    // Stay under the minutes, such as less entertainment
+    AtMost,
+    // Reach the minutes, such as deep work in the IDE
+    AtLeast,
+}
+
+// A daily goal for one or more applications.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Goal {
+    pub name: String,
+    pub apps: Vec<String>,
+    pub kind: GoalKind,
+    pub minutes: u64,
+}
+
+impl Goal {
+    fn includes(&self, app_name: &str) -> bool {
+        self.apps
+            .iter()
+            .any(|app| app.eq_ignore_ascii_case(app_name))
+    }
+
+    fn used(&self, app_totals: &HashMap<String, Duration>) -> Duration {
+        app_totals
+            .iter()
+            .filter(|(app_name, _)| self.includes(app_name))
+            .map(|(_, duration)| *duration)
+            .sum()
+    }
+
+    fn is_met(&self, used: Duration) -> bool {
+        let target = Duration::from_secs(self.minutes * 60);
+        match self.kind {
+            GoalKind::AtMost => used <= target,
+            GoalKind::AtLeast => used >= target,
+        }
+    }
+
+    // Outcomes are kept under the whole goal, so changing its minutes starts a new streak
+    fn key(&self) -> String {
+        let operator = match self.kind {
+            GoalKind::AtMost => "<=",
+            GoalKind::AtLeast => ">=",
+        };
+        format!("{}{}{}", self.name, operator, self.minutes)
+    }
+}
+
+// Parse goals such as "YouTube+Netflix<=120,Code>=180", in minutes per day.
+// Applications joined with '+' count towards one goal, named after the whole group.
+pub fn parse_goals(goals: &str) -> Result<Vec<Goal>, &'static str> {
+    let mut parsed_goals = Vec::new();
+    for goal_str in goals.split(',').map(str::trim) {
+        if goal_str.is_empty() {
+            continue;
+        }
+        let (name, kind, minutes) = match goal_str.find(['<', '>']) {
+            Some(index) => {
+                let kind = match &goal_str[index..index + 1] {
+                    "<" => GoalKind::AtMost,
+                    _ => GoalKind::AtLeast,
+                };
+                let minutes = goal_str[index + 1..].trim_start_matches('=');
+                (goal_str[..index].trim(), kind, minutes.trim())
+            }
+            None => {
+                return Err("Goals should look like Application<=minutes or Application>=minutes")
+            }
+        };
+        let minutes: u64 = match minutes.parse() {
+            Ok(minutes) if minutes > 0 => minutes,
+            _ => return Err("Goal minutes should be a positive number"),
+        };
+        let apps: Vec<String> = name
+            .split('+')
+            .map(|app| app.trim().to_string())
+            .filter(|app| !app.is_empty())
+            .collect();
+        if apps.is_empty() {
+            return Err("Goal is missing an application name");
+        }
+        parsed_goals.push(Goal {
+            name: name.to_string(),
+            apps,
+            kind,
+            minutes,
+        });
+    }
+    Ok(parsed_goals)
+}
+
+// Today's progress on a goal and its streaks, for the GOALS socket request.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GoalStatus {
+    pub name: String,
+    pub kind: GoalKind,
+    pub minutes: u64,
+    //Seconds used today
+    pub used_today: u64,
+    pub met_today: bool,
+    //Days in a row the goal was met, up to yesterday
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    //The goal may not be met today
+    pub at_risk: bool,
+}
+
+// The current streak ends yesterday, today can still change.
+fn streaks(outcomes: &BTreeMap<NaiveDate, bool>, today: NaiveDate) -> (u32, u32) {
+    let mut current = 0;
+    let mut day = today.pred_opt();
+    while let Some(previous_day) = day {
+        if outcomes.get(&previous_day) != Some(&true) {
+            break;
+        }
+        current += 1;
+        day = previous_day.pred_opt();
+    }
+    let mut longest = 0;
+    let mut run = 0;
+    let mut last_met: Option<NaiveDate> = None;
+    for (day, met) in outcomes {
+        if !met {
+            run = 0;
+            continue;
+        }
+        run = match last_met {
+            Some(last_met) if last_met.succ_opt() == Some(*day) && run > 0 => run + 1,
+            _ => 1,
+        };
+        last_met = Some(*day);
+        longest = longest.max(run);
+    }
+    (current, longest)
+}
+
+// Evaluates each finished day against the goals and keeps the outcomes.
+pub struct GoalTracker {
+    goals: Vec<Goal>,
+    day_start_hour: u32,
+    history_path: String,
+    // Set if the data file is encrypted, the history is then encrypted too as it names the applications
+    cipher: Option<Cipher>,
+    // Goal key -> day -> whether it was met
+    history: BTreeMap<String, BTreeMap<NaiveDate, bool>>,
+    // The day the days before were last evaluated on
+    evaluated_day: Option<NaiveDate>,
+    // Goals whose at risk alert has been sent on notified_day
+    notified: HashSet<String>,
+    notified_day: Option<NaiveDate>,
+}
+
+impl GoalTracker {
+    pub fn new(
+        goals: Vec<Goal>,
+        day_start_hour: u32,
+        history_path: &String,
+        cipher: Option<&Cipher>,
+    ) -> Result<GoalTracker, Box<dyn Error>> {
+        let mut history = BTreeMap::new();
+        if Path::new(history_path).exists() {
+            // A history kept before encryption was turned on is still plain until it is saved
+            let stored = match cipher {
+                Some(cipher) if is_encrypted(history_path)? => cipher.decrypt_file(history_path)?,
+                _ => fs::read(history_path)?,
+            };
+            let stored: BTreeMap<String, BTreeMap<String, bool>> = serde_json::from_slice(&stored)?;
+            for (key, outcomes) in stored {
+                let mut parsed_outcomes = BTreeMap::new();
+                for (day, met) in outcomes {
+                    parsed_outcomes.insert(day.parse::<NaiveDate>()?, met);
+                }
+                history.insert(key, parsed_outcomes);
+            }
+        }
+        Ok(GoalTracker {
+            goals,
+            day_start_hour,
+            history_path: history_path.to_string(),
+            cipher: cipher.cloned(),
+            history,
+            evaluated_day: None,
+            notified: HashSet::new(),
+            notified_day: None,
+        })
+    }
+
+    pub fn has_goals(&self) -> bool {
+        !self.goals.is_empty()
+    }
+
+    fn last_evaluated(&self, goal: &Goal) -> Option<NaiveDate> {
+        self.history
+            .get(&goal.key())
+            .and_then(|outcomes| outcomes.keys().next_back().copied())
+    }
+
+    //Record the outcome of the days before today that have not been evaluated yet.
+    //A goal without outcomes is evaluated from the first day in the csv.
+    pub fn evaluate(&mut self, today: NaiveDate, csv_name: &String) -> Result<(), Box<dyn Error>> {
+        // Once a day, so that a failing read is not retried every second
+        if self.evaluated_day == Some(today) {
+            return Ok(());
+        }
+        self.evaluated_day = Some(today);
+        let yesterday = match today.pred_opt() {
+            Some(yesterday) => yesterday,
+            None => return Ok(()),
+        };
+        if self
+            .goals
+            .iter()
+            .all(|goal| self.last_evaluated(goal) >= Some(yesterday))
+        {
+            return Ok(());
+        }
+        let rows = if Path::new(csv_name).exists() {
+            read_valid_rows(csv_name, self.cipher.as_ref())?
+        } else {
+            Vec::new()
+        };
+        let mut day_totals: HashMap<NaiveDate, HashMap<String, Duration>> = HashMap::new();
+        for row in &rows {
+            if row.application() == PAUSED_APPLICATION {
+                continue;
+            }
+            let day = day_of(
+                DateTime::<Local>::from(row.timestamp()),
+                self.day_start_hour,
+            );
+            if day < today {
+                *day_totals
+                    .entry(day)
+                    .or_default()
+                    .entry(row.application().to_string())
+                    .or_default() += Duration::from_secs(row.duration());
+            }
+        }
+        let first_day = day_totals.keys().min().copied();
+        for goal in &self.goals {
+            let from = match self.last_evaluated(goal) {
+                Some(last_evaluated) => last_evaluated.succ_opt(),
+                None => first_day,
+            };
+            let outcomes = self.history.entry(goal.key()).or_default();
+            let mut day = from;
+            while let Some(evaluated_day) = day.filter(|day| *day < today) {
+                let used = day_totals
+                    .get(&evaluated_day)
+                    .map(|app_totals| goal.used(app_totals))
+                    .unwrap_or_default();
+                outcomes.insert(evaluated_day, goal.is_met(used));
+                day = evaluated_day.succ_opt();
+            }
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let stored: BTreeMap<&String, BTreeMap<String, bool>> = self
+            .history
+            .iter()
+            .map(|(key, outcomes)| {
+                let outcomes = outcomes
+                    .iter()
+                    .map(|(day, met)| (day.to_string(), *met))
+                    .collect();
+                (key, outcomes)
+            })
+            .collect();
+        let stored = serde_json::to_string_pretty(&stored)?;
+        match &self.cipher {
+            Some(cipher) => cipher.write_file(&self.history_path, stored.as_bytes()),
+            None => write_atomically(&self.history_path, stored.as_bytes()),
+        }
+    }
+
+    //app_totals is today's usage so far.
+    pub fn status(
+        &self,
+        app_totals: &HashMap<String, Duration>,
+        now: DateTime<Local>,
+    ) -> Vec<GoalStatus> {
+        let today = day_of(now, self.day_start_hour);
+        let time_left = today
+            .succ_opt()
+            .map(|tomorrow| start_of_day(tomorrow, self.day_start_hour))
+            .and_then(|end_of_today| end_of_today.duration_since(SystemTime::from(now)).ok())
+            .unwrap_or_default();
+        self.goals
+            .iter()
+            .map(|goal| {
+                let used = goal.used(app_totals);
+                let target = Duration::from_secs(goal.minutes * 60);
+                let met_today = goal.is_met(used);
+                let at_risk = match goal.kind {
+                    GoalKind::AtMost => {
+                        met_today && used.as_secs() * 100 >= target.as_secs() * AT_RISK_PERCENTAGE
+                    }
+                    GoalKind::AtLeast => !met_today && time_left < target - used + AT_RISK_MARGIN,
+                };
+                let (current_streak, longest_streak) = match self.history.get(&goal.key()) {
+                    Some(outcomes) => streaks(outcomes, today),
+                    None => (0, 0),
+                };
+                GoalStatus {
+                    name: goal.name.clone(),
+                    kind: goal.kind,
+                    minutes: goal.minutes,
+                    used_today: used.as_secs(),
+                    met_today,
+                    current_streak,
+                    longest_streak,
+                    at_risk,
+                }
+            })
+            .collect()
+    }
+
+    //Returns the goals whose streak is newly at risk today, each once a day.
+    pub fn check_at_risk(&mut self, statuses: &[GoalStatus], today: NaiveDate) -> Vec<GoalStatus> {
+        if self.notified_day != Some(today) {
+            self.notified_day = Some(today);
+            self.notified.clear();
+        }
+        let mut at_risk = Vec::new();
+        for status in statuses {
+            // Only a streak that has started can be lost
+            if status.at_risk
+                && status.current_streak > 0
+                && self.notified.insert(status.name.clone())
+            {
+                at_risk.push(status.clone());
+            }
+        }
+        at_risk
+    }
+}
+
+// Format each goal's progress and streaks, one per line, for the GOALS socket request.
+pub fn format_goal_status(statuses: &[GoalStatus]) -> String {
+    statuses
+        .iter()
+        .map(|status| {
+            format!(
+                "{}: {} {} minutes, {} today, {} day streak (longest {}){}",
+                status.name,
+                match status.kind {
+                    GoalKind::AtMost => "at most",
+                    GoalKind::AtLeast => "at least",
+                },
+                status.minutes,
+                format_duration(Duration::from_secs(status.used_today)),
+                status.current_streak,
+                status.longest_streak,
+                if status.at_risk { " - at risk" } else { "" }
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{encrypt_existing_csv, KeySource};
+    use crate::test_helpers::tests::{minutes, setup, write_day, CSV_NAME};
+    use chrono::{Days, TimeDelta, TimeZone};
+    use serial_test::serial;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+    }
+
+    fn at(day: NaiveDate, hour: u32) -> DateTime<Local> {
+        Local
+            .from_local_datetime(&day.and_hms_opt(hour, 0, 0).unwrap())
+            .earliest()
+            .unwrap()
+    }
+
+    fn tracker_with(cipher: Option<&Cipher>) -> GoalTracker {
+        GoalTracker::new(
+            parse_goals("YouTube+Netflix<=120,Code>=180").unwrap(),
+            0,
+            &GOAL_HISTORY_FILE_NAME.to_string(),
+            cipher,
+        )
+        .unwrap()
+    }
+
+    fn tracker() -> GoalTracker {
+        tracker_with(None)
+    }
+
+    #[test]
+    fn parse_valid_and_invalid_goals() {
+        let goals = parse_goals("YouTube+Netflix<=120, Code>180,").unwrap();
+        assert_eq!(
+            goals,
+            vec![
+                Goal {
+                    name: "YouTube+Netflix".to_string(),
+                    apps: vec!["YouTube".to_string(), "Netflix".to_string()],
+                    kind: GoalKind::AtMost,
+                    minutes: 120
+                },
+                Goal {
+                    name: "Code".to_string(),
+                    apps: vec!["Code".to_string()],
+                    kind: GoalKind::AtLeast,
+                    minutes: 180
+                },
+            ]
+        );
+        assert!(parse_goals("").unwrap().is_empty());
+        assert!(parse_goals("Code=180").is_err());
+        assert!(parse_goals("Code>=0").is_err());
+        assert!(parse_goals(">=60").is_err());
+    }
+
+    #[test]
+    fn streaks_count_consecutive_met_days() {
+        let outcomes: BTreeMap<NaiveDate, bool> = [
+            (date(1), true),
+            (date(2), true),
+            (date(3), true),
+            (date(4), false),
+            (date(5), true),
+            (date(6), true),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(streaks(&outcomes, date(7)), (2, 3));
+        // Yesterday was not evaluated
+        assert_eq!(streaks(&outcomes, date(8)), (0, 3));
+    }
+
+    #[test]
+    #[serial]
+    fn evaluate_finished_days_and_keep_the_outcomes() {
+        let (_temp_dir, _) = setup();
+        write_day(date(1), &[("YouTube", 60), ("Code", 200)]);
+        write_day(date(2), &[("Netflix", 90), ("youtube", 40), ("Code", 190)]);
+        // Nothing is used on the 3rd
+        write_day(date(4), &[("Code", 10)]);
+
+        let mut goal_tracker = tracker();
+        goal_tracker
+            .evaluate(date(4), &CSV_NAME.to_string())
+            .unwrap();
+        let statuses = goal_tracker.status(&HashMap::new(), at(date(4), 9));
+        assert_eq!(
+            (statuses[0].current_streak, statuses[0].longest_streak),
+            (1, 1)
+        );
+        assert_eq!(
+            (statuses[1].current_streak, statuses[1].longest_streak),
+            (0, 2)
+        );
+
+        // Today is evaluated once it is over, from the outcomes kept in the file
+        let mut goal_tracker = tracker();
+        goal_tracker
+            .evaluate(date(4) + Days::new(1), &CSV_NAME.to_string())
+            .unwrap();
+        let statuses = goal_tracker.status(&HashMap::new(), at(date(5), 9));
+        assert_eq!(
+            (statuses[0].current_streak, statuses[0].longest_streak),
+            (2, 2)
+        );
+        assert_eq!(
+            (statuses[1].current_streak, statuses[1].longest_streak),
+            (0, 2)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn streaks_at_risk_notify_once_per_day() {
+        let (_temp_dir, _) = setup();
+        write_day(date(1), &[("YouTube", 30), ("Code", 200)]);
+        let mut goal_tracker = tracker();
+        goal_tracker
+            .evaluate(date(2), &CSV_NAME.to_string())
+            .unwrap();
+
+        let app_totals: HashMap<String, Duration> = [
+            ("YouTube".to_string(), minutes(100)),
+            ("Code".to_string(), minutes(60)),
+        ]
+        .into_iter()
+        .collect();
+        // Two hours of Code still to go, with more than three hours left in the day
+        let statuses = goal_tracker.status(&app_totals, at(date(2), 20));
+        assert!(statuses[0].at_risk);
+        assert!(!statuses[1].at_risk);
+        let statuses = goal_tracker.status(&app_totals, at(date(2), 21) + TimeDelta::minutes(30));
+        assert!(statuses[1].at_risk);
+
+        let at_risk = goal_tracker.check_at_risk(&statuses, date(2));
+        assert_eq!(at_risk.len(), 2);
+        assert!(goal_tracker.check_at_risk(&statuses, date(2)).is_empty());
+        assert_eq!(
+            format_goal_status(&statuses),
+            "YouTube+Netflix: at most 120 minutes, 1h 40m today, 1 day streak (longest 1) - at risk\nCode: at least 180 minutes, 1h 0m today, 1 day streak (longest 1) - at risk"
+        );
+
+        // Over the limit, the streak is already lost
+        let app_totals: HashMap<String, Duration> = [("Netflix".to_string(), minutes(130))]
+            .into_iter()
+            .collect();
+        assert!(!goal_tracker.status(&app_totals, at(date(2), 20))[0].at_risk);
+    }
+
+    #[test]
+    #[serial]
+    fn history_is_encrypted_with_the_data_file() {
+        let (_temp_dir, _) = setup();
+        write_day(date(1), &[("Code", 200)]);
+        write_day(date(2), &[("Code", 190)]);
+        // Kept before encryption was turned on
+        tracker().evaluate(date(3), &CSV_NAME.to_string()).unwrap();
+        let key_source = KeySource::Passphrase("passphrase".to_string());
+        encrypt_existing_csv(&CSV_NAME.to_string(), &key_source).unwrap();
+        let cipher = Cipher::open(&CSV_NAME.to_string(), &key_source).unwrap();
+
+        tracker_with(Some(&cipher))
+            .evaluate(date(4), &CSV_NAME.to_string())
+            .unwrap();
+        let history_path = GOAL_HISTORY_FILE_NAME.to_string();
+        assert!(is_encrypted(&history_path).unwrap());
+        assert!(!fs::read_to_string(&history_path).unwrap().contains("Code"));
+        let statuses = tracker_with(Some(&cipher)).status(&HashMap::new(), at(date(4), 9));
+        assert_eq!(
+            (statuses[1].current_streak, statuses[1].longest_streak),
+            (0, 2)
+        );
+    }
+}
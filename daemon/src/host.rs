@@ -0,0 +1,29 @@
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+
+use crate::HOST_ID_ENV_VAR;
+
+// Used if the hostname cannot be found.
+const UNKNOWN_HOST: &str = "unknown";
+
+//The id written to every row recorded on this machine: HOST_ID if set, otherwise the hostname.
+pub fn local_host_id() -> &'static str {
+    static HOST_ID: OnceLock<String> = OnceLock::new();
+    HOST_ID.get_or_init(|| match dotenvy::var(HOST_ID_ENV_VAR) {
+        Ok(host_id) if !host_id.trim().is_empty() => host_id.trim().to_string(),
+        _ => system_hostname(),
+    })
+}
+
+fn system_hostname() -> String {
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .or_else(|_| env::var("HOSTNAME"))
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .unwrap_or_default();
+    match hostname.trim() {
+        "" => UNKNOWN_HOST.to_string(),
+        hostname => hostname.to_string(),
+    }
+}
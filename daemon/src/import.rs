@@ -0,0 +1,359 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::DateTime;
+use serde_derive::Deserialize;
+
+use crate::aliases::AliasRules;
+use crate::csv_writer::{read_valid_rows, write_data_to_csv};
+use crate::encryption::Cipher;
+use crate::privacy::PrivacyRules;
+
+const WINDOW_BUCKET_TYPE: &str = "currentwindow";
+const AFK_BUCKET_TYPE: &str = "afkstatus";
+const AFK_STATUS: &str = "afk";
+const HOUR_MS: i64 = 60 * 60 * 1000;
+
+// An ActivityWatch export, either of every bucket or of a single one.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ActivityWatchExport {
+    Buckets { buckets: HashMap<String, Bucket> },
+    Bucket(Bucket),
+}
+
+#[derive(Deserialize)]
+struct Bucket {
+    #[serde(rename = "type")]
+    bucket_type: String,
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    //RFC 3339
+    timestamp: String,
+    //Seconds
+    duration: f64,
+    #[serde(default)]
+    data: EventData,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct EventData {
+    app: Option<String>,
+    title: Option<String>,
+    status: Option<String>,
+}
+
+struct WindowEvent {
+    start_ms: i64,
+    end_ms: i64,
+    app: String,
+    title: String,
+}
+
+// The window events and idle periods of one or more ActivityWatch exports.
+#[derive(Default)]
+pub struct ActivityWatchHistory {
+    windows: Vec<WindowEvent>,
+    // Periods the user was away, in milliseconds since the epoch
+    afk: Vec<(i64, i64)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImportSummary {
+    pub rows: usize,
+    pub seconds: u64,
+    // Hours that already had data, which were not imported
+    pub skipped_hours: usize,
+}
+
+fn event_interval(event: &Event) -> Result<(i64, i64), Box<dyn Error>> {
+    let start_ms = DateTime::parse_from_rfc3339(&event.timestamp)
+        .map_err(|err| format!("Invalid timestamp {}: {}", event.timestamp, err))?
+        .timestamp_millis();
+    Ok((
+        start_ms,
+        start_ms + (event.duration.max(0.0) * 1000.0) as i64,
+    ))
+}
+
+impl ActivityWatchHistory {
+    //Add the currentwindow and afkstatus buckets of an export, other buckets are ignored.
+    pub fn add_export(&mut self, export_json: &str) -> Result<(), Box<dyn Error>> {
+        let buckets = match serde_json::from_str(export_json)? {
+            ActivityWatchExport::Buckets { buckets } => buckets.into_values().collect(),
+            ActivityWatchExport::Bucket(bucket) => vec![bucket],
+        };
+        for bucket in buckets {
+            for event in bucket.events {
+                let (start_ms, end_ms) = event_interval(&event)?;
+                match (bucket.bucket_type.as_str(), event.data) {
+                    (
+                        WINDOW_BUCKET_TYPE,
+                        EventData {
+                            app: Some(app),
+                            title,
+                            ..
+                        },
+                    ) => self.windows.push(WindowEvent {
+                        start_ms,
+                        end_ms,
+                        app,
+                        title: title.unwrap_or_default(),
+                    }),
+                    (
+                        AFK_BUCKET_TYPE,
+                        EventData {
+                            status: Some(status),
+                            ..
+                        },
+                    ) if status == AFK_STATUS => self.afk.push((start_ms, end_ms)),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    //Active time per application per hour, in milliseconds, without the time the user was away.
+    //Private windows are labelled or skipped and aliases applied, as when recording.
+    fn active_time_per_hour(
+        &self,
+        privacy_rules: &PrivacyRules,
+        alias_rules: &AliasRules,
+    ) -> BTreeMap<i64, HashMap<String, i64>> {
+        let mut afk = self.afk.clone();
+        afk.sort();
+        let mut hours: BTreeMap<i64, HashMap<String, i64>> = BTreeMap::new();
+        for window in &self.windows {
+            let app_name = match privacy_rules.apply(&window.app, &window.title) {
+                Some(app_name) => alias_rules.resolve(app_name),
+                None => continue,
+            };
+            for (start_ms, end_ms) in subtract_intervals((window.start_ms, window.end_ms), &afk) {
+                // Split at the hours so that each part is counted on the right day
+                let mut part_start = start_ms;
+                while part_start < end_ms {
+                    let hour_start = part_start.div_euclid(HOUR_MS) * HOUR_MS;
+                    let part_end = end_ms.min(hour_start + HOUR_MS);
+                    *hours
+                        .entry(hour_start)
+                        .or_default()
+                        .entry(app_name.to_string())
+                        .or_default() += part_end - part_start;
+                    part_start = part_end;
+                }
+            }
+        }
+        hours
+    }
+}
+
+// The parts of the interval not covered by the sorted intervals.
+fn subtract_intervals(interval: (i64, i64), sorted_intervals: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let (mut start, end) = interval;
+    let mut parts = Vec::new();
+    for (covered_start, covered_end) in sorted_intervals {
+        if *covered_end <= start || *covered_start >= end {
+            continue;
+        }
+        if *covered_start > start {
+            parts.push((start, *covered_start));
+        }
+        start = start.max(*covered_end);
+        if start >= end {
+            return parts;
+        }
+    }
+    parts.push((start, end));
+    parts
+}
+
+// Hours already recorded in the csv. A row's duration was recorded in the hours before its timestamp.
+fn recorded_hours(
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+) -> Result<HashSet<i64>, Box<dyn Error>> {
+    let mut hours = HashSet::new();
+    if !Path::new(csv_name).exists() {
+        return Ok(hours);
+    }
+    for row in read_valid_rows(csv_name, cipher)? {
+        let end_ms = row.timestamp().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let start_ms = end_ms - row.duration() as i64 * 1000;
+        let mut hour_start = start_ms.div_euclid(HOUR_MS) * HOUR_MS;
+        while hour_start <= end_ms {
+            hours.insert(hour_start);
+            hour_start += HOUR_MS;
+        }
+    }
+    Ok(hours)
+}
+
+//Append the history as one row per application per hour, timestamped at the last second of the hour like a flush at its end.
+//Hours that already have data are skipped, so the same export can be imported twice.
+pub fn import_activitywatch(
+    history: &ActivityWatchHistory,
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+    privacy_rules: &PrivacyRules,
+    alias_rules: &AliasRules,
+) -> Result<ImportSummary, Box<dyn Error>> {
+    let recorded_hours = recorded_hours(csv_name, cipher)?;
+    let mut summary = ImportSummary {
+        rows: 0,
+        seconds: 0,
+        skipped_hours: 0,
+    };
+    for (hour_start, app_times) in history.active_time_per_hour(privacy_rules, alias_rules) {
+        if recorded_hours.contains(&hour_start) {
+            summary.skipped_hours += 1;
+            continue;
+        }
+        let program_times: HashMap<String, Duration> = app_times
+            .into_iter()
+            .map(|(app_name, ms)| (app_name, Duration::from_secs((ms / 1000) as u64)))
+            .filter(|(_, duration)| !duration.is_zero())
+            .collect();
+        if program_times.is_empty() {
+            continue;
+        }
+        let timestamp = UNIX_EPOCH + Duration::from_millis((hour_start + HOUR_MS - 1000) as u64);
+        write_data_to_csv(&program_times, csv_name, cipher, timestamp)?;
+        summary.rows += program_times.len();
+        summary.seconds += program_times.values().map(Duration::as_secs).sum::<u64>();
+    }
+    Ok(summary)
+}
+
+//Read ActivityWatch export files, such as the ones from Settings > Export all buckets as JSON.
+pub fn read_activitywatch_exports(
+    export_paths: &[String],
+) -> Result<ActivityWatchHistory, Box<dyn Error>> {
+    let mut history = ActivityWatchHistory::default();
+    for export_path in export_paths {
+        let export_json = fs::read_to_string(export_path)
+            .map_err(|err| format!("Error reading {}: {}", export_path, err))?;
+        history
+            .add_export(&export_json)
+            .map_err(|err| format!("Error parsing {}: {}", export_path, err))?;
+    }
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_writer::read_app_totals;
+    use crate::privacy::PrivacyAction;
+    use crate::test_helpers::tests::{setup, CSV_NAME};
+    use serial_test::serial;
+    use std::time::SystemTime;
+
+    // 10:30 to 11:30 in Code with a 10 minute break at 10:50, then Firefox until 11:40
+    const EXPORT: &str = r#"{"buckets": {
+        "aw-watcher-window_laptop": {"type": "currentwindow", "events": [
+            {"timestamp": "2024-03-16T10:30:00+00:00", "duration": 3600, "data": {"app": "Code", "title": "main.rs"}},
+            {"timestamp": "2024-03-16T11:30:00.000000+00:00", "duration": 600.4, "data": {"app": "Firefox", "title": "Bank - Private Browsing"}}
+        ]},
+        "aw-watcher-afk_laptop": {"type": "afkstatus", "events": [
+            {"timestamp": "2024-03-16T10:50:00+00:00", "duration": 600, "data": {"status": "afk"}},
+            {"timestamp": "2024-03-16T11:00:00+00:00", "duration": 2400, "data": {"status": "not-afk"}}
+        ]},
+        "aw-watcher-input_laptop": {"type": "os.hid.input", "events": []}
+    }}"#;
+
+    fn history() -> ActivityWatchHistory {
+        let mut history = ActivityWatchHistory::default();
+        history.add_export(EXPORT).unwrap();
+        history
+    }
+
+    fn at(time: &str) -> i64 {
+        DateTime::parse_from_rfc3339(&format!("2024-03-16T{}:00+00:00", time))
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn away_time_is_not_counted_and_hours_are_split() {
+        let hours =
+            history().active_time_per_hour(&PrivacyRules::default(), &AliasRules::default());
+        let minutes = |ms: i64| ms / 60_000;
+        assert_eq!(hours.len(), 2);
+        assert_eq!(minutes(hours[&at("10:00")]["Code"]), 20);
+        assert_eq!(minutes(hours[&at("11:00")]["Code"]), 30);
+        assert_eq!(minutes(hours[&at("11:00")]["Firefox"]), 10);
+    }
+
+    #[test]
+    fn private_windows_are_not_imported_by_name() {
+        let privacy_rules = PrivacyRules {
+            apps: Vec::new(),
+            title: Some(regex::Regex::new("Private Browsing").unwrap()),
+            action: PrivacyAction::Skip,
+        };
+        let hours = history().active_time_per_hour(&privacy_rules, &AliasRules::default());
+        assert!(!hours[&at("11:00")].contains_key("Firefox"));
+    }
+
+    #[test]
+    fn subtract_covered_intervals() {
+        assert_eq!(
+            subtract_intervals((0, 10), &[(2, 4), (6, 8)]),
+            vec![(0, 2), (4, 6), (8, 10)]
+        );
+        assert_eq!(subtract_intervals((0, 10), &[(0, 10)]), vec![]);
+        assert_eq!(
+            subtract_intervals((5, 10), &[(0, 6), (20, 30)]),
+            vec![(6, 10)]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn importing_twice_does_not_duplicate() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let import = || {
+            import_activitywatch(
+                &history(),
+                &csv_name,
+                None,
+                &PrivacyRules::default(),
+                &AliasRules::default(),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            import(),
+            ImportSummary {
+                rows: 3,
+                seconds: 60 * 60,
+                skipped_hours: 0,
+            }
+        );
+        assert_eq!(
+            import(),
+            ImportSummary {
+                rows: 0,
+                seconds: 0,
+                skipped_hours: 2,
+            }
+        );
+        let app_totals = read_app_totals(&csv_name, None, UNIX_EPOCH, SystemTime::now()).unwrap();
+        assert_eq!(app_totals["Code"], Duration::from_secs(50 * 60));
+        assert_eq!(app_totals["Firefox"], Duration::from_secs(10 * 60));
+    }
+}
@@ -1,25 +1,72 @@
-use config::new_config;
-use csv_writer::write_data_to_csv;
-use notification::{exit_with_error_notification, screen_time_notification};
-use screen_time::update_current_app;
+use aliases::{AliasRules, ALIASES_FILE_NAME};
+use backup::{
+    format_restore_summary, restore_backup, run_backup, validate_backup, BackupRequest,
+    BackupScheduler,
+};
+use breaks::BreakTracker;
+use categories::{CategorizedTimes, TitleCategoryRules, CATEGORIES_FILE_NAME};
+use chrono::{Local, NaiveDate};
+use config::{new_config, Config};
+use csv_writer::write_categorized_data_to_csv;
+use digest::{build_digest, format_duration, DigestScheduler};
+use encryption::{encrypt_existing_csv, Cipher};
+use error::{DaemonError, ErrorHandler};
+use focus::{store_focus_session, FocusState, FOCUS_SESSIONS_FILE_NAME};
+use goals::{GoalStatus, GoalTracker, GOAL_HISTORY_FILE_NAME};
+use import::{import_activitywatch, read_activitywatch_exports};
+use limits::{day_of, start_of_day, LimitTracker, LimitUsage};
+use merge::merge_data_files;
+use notification::{
+    daily_limit_alert, digest_alert, distraction_alert, focus_session_alert, goal_at_risk_alert,
+    new_notifier, screen_time_alert, DesktopNotifier, NotificationAction, Notifier,
+};
+use pause::{PauseState, PAUSED_APPLICATION};
+use repair::{recover_interrupted_rewrite, repair_csv, QUARANTINE_FILE_PREFIX};
+use schema::migrate_csv;
+use screen_time::{input_idle_time, update_current_app};
 use signals::register_os_signals;
-use socket::send_terminating_mssg;
+use socket::{send_message_to_socket, send_terminating_mssg, SocketState};
 use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
+use std::path::Path;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
-use std::thread;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::SystemTime;
 use std::time::{self};
-use threads::{create_alert_screen_thread, create_socket_listener_thread};
+use std::time::{Duration, Instant};
+use suppression::SuppressingNotifier;
+use threads::create_socket_listener_thread;
 
+mod aliases;
+mod backup;
+mod breaks;
+mod categories;
+mod cli;
 mod config;
 mod csv_writer;
+mod digest;
+mod encryption;
+mod error;
+mod export;
+mod focus;
+mod goals;
+mod host;
+mod import;
+mod limits;
+mod merge;
 mod notification;
+mod pause;
+mod privacy;
+mod repair;
+mod schema;
 mod screen_time;
 mod signals;
 mod socket;
+mod suppression;
 mod test_helpers;
 mod threads;
 
@@ -28,90 +75,939 @@ mod windows;
 
 const SOCKET_ADDR: &str = "[::1]:12345";
 const ALERT_SCREEN_ENV_VAR: &str = "ALERT_SCREEN";
+const ALIAS_AT_WRITE_ENV_VAR: &str = "ALIAS_AT_WRITE";
+const PRIVATE_APPS_ENV_VAR: &str = "PRIVATE_APPS";
+const ENCRYPTION_KEYFILE_ENV_VAR: &str = "ENCRYPTION_KEYFILE";
+const HOST_ID_ENV_VAR: &str = "HOST_ID";
+const BACKUP_DIR_ENV_VAR: &str = "BACKUP_DIR";
+const BACKUP_INTERVAL_ENV_VAR: &str = "BACKUP_INTERVAL";
+const BACKUP_RETENTION_ENV_VAR: &str = "BACKUP_RETENTION";
+const ENCRYPTION_PASSPHRASE_ENV_VAR: &str = "ENCRYPTION_PASSPHRASE";
+const PRIVATE_TITLES_ENV_VAR: &str = "PRIVATE_TITLES";
+const PRIVACY_ACTION_ENV_VAR: &str = "PRIVACY_ACTION";
+const BREAK_RESET_ENV_VAR: &str = "BREAK_RESET";
+const BREAK_PRESET_ENV_VAR: &str = "BREAK_PRESET";
+const DAILY_LIMITS_ENV_VAR: &str = "DAILY_LIMITS";
+const DAY_START_HOUR_ENV_VAR: &str = "DAY_START_HOUR";
+const FLUSH_INTERVAL_ENV_VAR: &str = "FLUSH_INTERVAL";
+const GOALS_ENV_VAR: &str = "GOALS";
+const LIMIT_THRESHOLDS_ENV_VAR: &str = "LIMIT_THRESHOLDS";
+const NOTIFIER_ENV_VAR: &str = "NOTIFIER";
+const QUIET_HOURS_ENV_VAR: &str = "QUIET_HOURS";
+const DIGEST_TIME_ENV_VAR: &str = "DIGEST_TIME";
+const WEEKLY_DIGEST_DAY_ENV_VAR: &str = "WEEKLY_DIGEST_DAY";
+const SUPPRESS_FULLSCREEN_ENV_VAR: &str = "SUPPRESS_FULLSCREEN";
 const SCREEN_DATA_CSV_PATH: &str = "screen_time_data.csv";
+const SNOOZE_DURATION: Duration = Duration::from_secs(10 * 60);
+const SHUTDOWN_FLUSH_ATTEMPTS: u32 = 3;
 
-pub fn run() -> Result<(), Box<dyn Error>> {
-    let env_config = new_config();
-    let alert_screen_time = env_config.get_alert_screen_time();
-
-    if let Err(err) = create_alert_screen_thread(alert_screen_time) {
-        exit_with_error_notification(
-            format!("Error creating alert screen thread: {}", err).as_str(),
-        );
-    }
+pub fn run() -> Result<(), DaemonError> {
+    let env_config = new_config().map_err(report_startup_error)?;
+    // Aliases are applied when reading, unless they are also applied before writing
+    let alias_rules = if env_config.get_alias_at_write() {
+        AliasRules::load(Path::new(ALIASES_FILE_NAME))
+            .map_err(|err| {
+                DaemonError::Config(format!("Error loading {}: {}", ALIASES_FILE_NAME, err))
+            })
+            .map_err(report_startup_error)?
+    } else {
+        AliasRules::default()
+    };
+    // Category rules on window titles are resolved when recording, as titles are not written
+    let title_category_rules = TitleCategoryRules::load(Path::new(CATEGORIES_FILE_NAME))
+        .map_err(|err| {
+            DaemonError::Config(format!("Error loading {}: {}", CATEGORIES_FILE_NAME, err))
+        })
+        .map_err(report_startup_error)?;
+    // Finish or undo a rewrite of the csv cut short by a crash
+    recover_interrupted_rewrite(&SCREEN_DATA_CSV_PATH.to_string())
+        .map_err(|err| DaemonError::CsvRead(format!("Error recovering csv: {}", err)))
+        .map_err(report_startup_error)?;
+    let cipher = match env_config.get_key_source() {
+        Some(key_source) => Some(
+            Cipher::open(&SCREEN_DATA_CSV_PATH.to_string(), key_source)
+                .map_err(|err| {
+                    DaemonError::Config(format!("Error opening encrypted data: {}", err))
+                })
+                .map_err(report_startup_error)?,
+        ),
+        None => None,
+    };
+    migrate_csv(&SCREEN_DATA_CSV_PATH.to_string(), cipher.as_ref())
+        .map_err(|err| DaemonError::CsvRead(format!("Error migrating csv: {}", err)))
+        .map_err(report_startup_error)?;
+    let mut goal_tracker = GoalTracker::new(
+        env_config.get_goals().clone(),
+        env_config.get_day_start_hour(),
+        &GOAL_HISTORY_FILE_NAME.to_string(),
+        cipher.as_ref(),
+    )
+    .map_err(|err| {
+        DaemonError::Config(format!("Error loading {}: {}", GOAL_HISTORY_FILE_NAME, err))
+    })
+    .map_err(report_startup_error)?;
+    let mut break_tracker = BreakTracker::new(env_config.get_break_settings());
+    let alert_screen_time = break_tracker.settings().limit.as_secs() / 60;
+    // Actions chosen on break reminders
+    let (action_sender, action_receiver) = mpsc::channel::<NotificationAction>();
+    // Alerts are held back during quiet hours or while a fullscreen window is focused
+    let notifier = Arc::new(SuppressingNotifier::new(
+        new_notifier(env_config.get_notifier(), action_sender),
+        env_config.get_suppression_rules().clone(),
+    ));
+    let mut error_handler = ErrorHandler::new(notifier.clone());
+    // Backups and restores asked for over the socket
+    let (backup_sender, backup_receiver) = mpsc::channel::<(BackupRequest, Sender<String>)>();
+    let socket_state = SocketState {
+        alert_screen_time,
+        day_start_hour: env_config.get_day_start_hour(),
+        cipher: cipher.clone(),
+        key_source: env_config.get_key_source().cloned(),
+        backup_requests: Some(backup_sender),
+        ..Default::default()
+    };
     // When true, update csv
-    let update_csv = Arc::new(AtomicBool::new(false));
-    let child_update_csv = Arc::clone(&update_csv);
+    let update_csv = Arc::clone(&socket_state.update_csv);
+    let pause_state = socket_state.pause_state.clone();
+    let focus_state = socket_state.focus_state.clone();
+    let limit_usage = Arc::clone(&socket_state.limit_usage);
+    let goal_status = Arc::clone(&socket_state.goal_status);
     let program_finished = Arc::new(AtomicBool::new(false));
-    register_os_signals(&program_finished);
+    register_os_signals(&program_finished).or_else(|err| error_handler.handle(err))?;
 
     let child_program_finished = Arc::clone(&program_finished);
 
     let socket_addr: &str = "[::1]:12345";
 
-    let socket_listener_thread = match create_socket_listener_thread(
+    // Tracking carries on without the socket if it cannot be set up
+    let mut socket_listener_thread = match create_socket_listener_thread(
         Arc::clone(&child_program_finished),
-        Arc::clone(&child_update_csv),
-        alert_screen_time,
+        socket_state.clone(),
         socket_addr.to_string(),
     ) {
-        Ok(socket_listener_thread) => socket_listener_thread,
+        Ok(listener_thread) => Some(listener_thread),
         Err(err) => {
-            exit_with_error_notification(
-                format!("Error creating socket screen thread: {}", err).as_str(),
-            );
+            error_handler.handle(err)?;
+            None
         }
     };
     let mut program_times: HashMap<String, time::Duration> = HashMap::new();
-
-    // 1, 0 ->  1 - run screen_time_daemon
-    // 0, 1 ->  1 -  break
-    // 0, 0 -> 0 - break
-    // 1, 1 -> 1 - update
-    while !program_finished.load(Ordering::Relaxed) || update_csv.load(Ordering::Relaxed) {
-        if program_finished.load(Ordering::Relaxed) {
-            break;
+    let mut categorized_times = CategorizedTimes::new();
+    let mut limit_tracker = LimitTracker::new(
+        env_config.get_daily_limits().clone(),
+        env_config.get_limit_thresholds().clone(),
+        env_config.get_day_start_hour(),
+        Local::now(),
+    );
+    // Goals need today's usage as well
+    let tracks_usage = limit_tracker.has_limits() || goal_tracker.has_goals();
+    if tracks_usage {
+        if let Err(err) =
+            limit_tracker.load_stored_usage(&SCREEN_DATA_CSV_PATH.to_string(), cipher.as_ref())
+        {
+            let err = DaemonError::CsvRead(format!("Error reading today's usage: {}", err));
+            error_handler.handle(err)?;
         }
-        if update_csv.load(Ordering::Relaxed) {
-            println!("Updating csv...");
-            if let Err(err) = write_data_to_csv(
-                &program_times,
-                &SCREEN_DATA_CSV_PATH.to_string(),
-                SystemTime::now(),
+    }
+    let mut digest_scheduler = env_config
+        .get_digest_schedule()
+        .map(|schedule| DigestScheduler::new(schedule, Local::now().naive_local()));
+    let mut backup_scheduler = env_config
+        .get_backup_schedule()
+        .map(|schedule| BackupScheduler::new(schedule.clone(), SystemTime::now()));
+    // When the current pause started, if tracking is paused
+    let mut pause_started: Option<Instant> = None;
+    // Wall clock time of the last tick, so time spent suspended is noticed
+    let mut last_tick = SystemTime::now();
+    let mut last_flush = Instant::now();
+
+    // Stops with an error if the policy decides that it is fatal
+    let tracking_result: Result<(), DaemonError> = 'tracking: {
+        // 1, 0 ->  1 - run screen_time_daemon
+        // 0, 1 ->  1 -  break
+        // 0, 0 -> 0 - break
+        // 1, 1 -> 1 - update
+        while !program_finished.load(Ordering::Relaxed) || update_csv.load(Ordering::Relaxed) {
+            if program_finished.load(Ordering::Relaxed) {
+                println!("Signal received!");
+                break;
+            }
+            // Flushing regularly keeps each row to a short period, for the timeline and heatmap
+            let flush_due = last_flush.elapsed() >= env_config.get_flush_interval();
+            if flush_due {
+                last_flush = Instant::now();
+            }
+            if update_csv.load(Ordering::Relaxed) || (flush_due && !program_times.is_empty()) {
+                println!("Updating csv...");
+                if let Err(err) = flush_program_times(
+                    &mut program_times,
+                    &mut categorized_times,
+                    &mut limit_tracker,
+                    &mut error_handler,
+                    cipher.as_ref(),
+                    SystemTime::now(),
+                ) {
+                    break 'tracking Err(err);
+                }
+                update_csv.store(false, Ordering::Relaxed);
+            }
+            // The socket listener only stops early if it failed
+            if socket_listener_thread
+                .as_ref()
+                .is_some_and(|listener_thread| listener_thread.is_finished())
+            {
+                if let Some(listener_thread) = socket_listener_thread.take() {
+                    if let Err(err) = join_socket_listener_thread(listener_thread)
+                        .or_else(|err| error_handler.handle(err))
+                    {
+                        break 'tracking Err(err);
+                    }
+                }
+            }
+            if let Some(digest_scheduler) = &mut digest_scheduler {
+                if let Err(err) = send_due_digests(
+                    digest_scheduler,
+                    &program_times,
+                    cipher.as_ref(),
+                    env_config.get_day_start_hour(),
+                    notifier.as_ref(),
+                    &mut error_handler,
+                ) {
+                    break 'tracking Err(err);
+                }
+            }
+            if let Err(err) = run_backups(
+                &backup_receiver,
+                backup_scheduler.as_mut(),
+                &mut limit_tracker,
+                tracks_usage,
+                cipher.as_ref(),
+                &mut error_handler,
             ) {
-                exit_with_error_notification(format!("Error writing to csv: {}", err).as_str());
+                break 'tracking Err(err);
+            }
+            share_program_times(&socket_state, &program_times);
+            finish_focus_session(
+                &focus_state,
+                notifier.as_ref(),
+                &error_handler,
+                cipher.as_ref(),
+            );
+
+            thread::sleep(time::Duration::from_secs(1));
+            let elapsed = last_tick.elapsed().unwrap_or(time::Duration::from_secs(1));
+            last_tick = SystemTime::now();
+            handle_notification_actions(&action_receiver, &mut break_tracker, &pause_state);
+            notifier.release_deferred();
+            if pause_state.is_paused() {
+                // Paused time counts towards a break
+                break_tracker.tick(false, Duration::ZERO, elapsed);
+                if pause_started.is_none() {
+                    println!("Pausing tracking...");
+                    // Flush so that the rows before the pause are timestamped before it
+                    if let Err(err) = flush_program_times(
+                        &mut program_times,
+                        &mut categorized_times,
+                        &mut limit_tracker,
+                        &mut error_handler,
+                        cipher.as_ref(),
+                        SystemTime::now(),
+                    ) {
+                        break 'tracking Err(err);
+                    }
+                    pause_started = Some(Instant::now());
+                }
+                continue;
+            }
+            if let Some(started) = pause_started.take() {
+                println!("Resuming tracking...");
+                record_paused_period(&mut program_times, started.elapsed());
+                if let Err(err) = flush_program_times(
+                    &mut program_times,
+                    &mut categorized_times,
+                    &mut limit_tracker,
+                    &mut error_handler,
+                    cipher.as_ref(),
+                    SystemTime::now(),
+                ) {
+                    break 'tracking Err(err);
+                }
+            }
+            let focused_window = update_current_app(
+                &mut program_times,
+                &mut categorized_times,
+                env_config.get_privacy_rules(),
+                &alias_rules,
+                &title_category_rules,
+            );
+            // Only needed for break reminders, and falls back to the focused window without it
+            let input_idle = match break_tracker.settings().is_enabled() {
+                true => input_idle_time().unwrap_or_default(),
+                false => Duration::ZERO,
+            };
+            if let Some(continuous_use) =
+                break_tracker.tick(focused_window.is_active(), input_idle, elapsed)
+            {
+                notifier.notify(&screen_time_alert(
+                    continuous_use,
+                    break_tracker.settings().preset,
+                ));
+            }
+            if let Some(app_name) = focus_state.sample(&focused_window) {
+                notifier.notify(&distraction_alert(&app_name));
+            }
+            if tracks_usage {
+                if let Err(err) = check_daily_limits(
+                    &mut program_times,
+                    &mut categorized_times,
+                    &mut limit_tracker,
+                    &limit_usage,
+                    notifier.as_ref(),
+                    &mut error_handler,
+                    cipher.as_ref(),
+                ) {
+                    break 'tracking Err(err);
+                }
+            }
+            if goal_tracker.has_goals() {
+                if let Err(err) = check_goals(
+                    &mut goal_tracker,
+                    &limit_tracker,
+                    &program_times,
+                    &goal_status,
+                    notifier.as_ref(),
+                    &mut error_handler,
+                ) {
+                    break 'tracking Err(err);
+                }
             }
-            program_times.clear();
-            update_csv.store(false, Ordering::Relaxed);
         }
+        Ok(())
+    };
 
-        thread::sleep(time::Duration::from_secs(1));
-        update_current_app(&mut program_times);
+    if let Some(started) = pause_started.take() {
+        record_paused_period(&mut program_times, started.elapsed());
+    }
+    // A session cut short by shutting down is kept as stopped
+    if focus_state.stop().is_ok() {
+        finish_focus_session(
+            &focus_state,
+            notifier.as_ref(),
+            &error_handler,
+            cipher.as_ref(),
+        );
+    }
+    if socket_listener_thread.is_some() {
+        program_finished.store(true, Ordering::Relaxed);
+        if let Err(err) = send_terminating_mssg(SOCKET_ADDR.to_string()) {
+            error_handler.handle(err)?;
+        }
     }
-
-    println!("Signal received!");
-    send_terminating_mssg(SOCKET_ADDR.to_string());
 
     for (program_name, duration) in &program_times {
         println!("{}: {}", program_name, duration.as_secs());
     }
-    match write_data_to_csv(
-        &program_times,
+    // Always flush, even when stopping because of a fatal error
+    let flush_result = flush_on_shutdown(&program_times, &categorized_times, cipher.as_ref());
+    match &flush_result {
+        Ok(()) => println!("Finished writing to csv."),
+        Err(err) => error_handler.report(err),
+    }
+    //Wait for socket listener thread to finish
+    if let Some(listener_thread) = socket_listener_thread.take() {
+        if let Err(err) = join_socket_listener_thread(listener_thread) {
+            error_handler.report(&err);
+        }
+    }
+    tracking_result?;
+    flush_result?;
+    println!("Successfully exiting...");
+
+    Ok(())
+}
+
+// Errors before the configured notifier exists are shown as desktop notifications.
+fn report_startup_error(err: DaemonError) -> DaemonError {
+    ErrorHandler::new(Arc::new(DesktopNotifier::new(None))).report(&err);
+    err
+}
+
+// Apply the actions chosen on break reminders since the last tick.
+fn handle_notification_actions(
+    action_receiver: &Receiver<NotificationAction>,
+    break_tracker: &mut BreakTracker,
+    pause_state: &PauseState,
+) {
+    while let Ok(action) = action_receiver.try_recv() {
+        match action {
+            NotificationAction::Snooze => break_tracker.snooze(SNOOZE_DURATION),
+            // Pausing for the break length counts as a break and resets the continuous use
+            NotificationAction::StartBreak => {
+                pause_state.pause(Some(break_tracker.settings().break_length))
+            }
+            NotificationAction::PauseTracking => pause_state.pause(None),
+            NotificationAction::Dismiss => {}
+        }
+    }
+}
+
+// Write program_times to the csv and clear it.
+// If writing fails, program_times is kept so the write is retried on the next flush.
+fn flush_program_times(
+    program_times: &mut HashMap<String, time::Duration>,
+    categorized_times: &mut CategorizedTimes,
+    limit_tracker: &mut LimitTracker,
+    error_handler: &mut ErrorHandler,
+    cipher: Option<&Cipher>,
+    timestamp: SystemTime,
+) -> Result<(), DaemonError> {
+    match write_categorized_data_to_csv(
+        program_times,
+        categorized_times,
         &SCREEN_DATA_CSV_PATH.to_string(),
-        SystemTime::now(),
+        cipher,
+        timestamp,
     ) {
         Ok(()) => {
-            println!("Finished writing to csv.");
+            error_handler.succeeded();
+            limit_tracker.record_flushed(program_times);
+            program_times.clear();
+            categorized_times.clear();
+            Ok(())
         }
-        Err(err) => {
-            exit_with_error_notification(format!("Error writing to csv: {}", err).as_str());
+        Err(err) => error_handler.handle(DaemonError::CsvWrite(err.to_string())),
+    }
+}
+
+// The final write before exiting, retried a few times as the data is lost otherwise.
+fn flush_on_shutdown(
+    program_times: &HashMap<String, time::Duration>,
+    categorized_times: &CategorizedTimes,
+    cipher: Option<&Cipher>,
+) -> Result<(), DaemonError> {
+    let mut attempt = 1;
+    loop {
+        match write_categorized_data_to_csv(
+            program_times,
+            categorized_times,
+            &SCREEN_DATA_CSV_PATH.to_string(),
+            cipher,
+            SystemTime::now(),
+        ) {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt >= SHUTDOWN_FLUSH_ATTEMPTS => {
+                return Err(DaemonError::CsvWrite(err.to_string()));
+            }
+            Err(err) => {
+                eprintln!("Error writing to csv, retrying: {}", err);
+                attempt += 1;
+                thread::sleep(time::Duration::from_secs(1));
+            }
         }
     }
-    //Wait for socket listener thread to finish
-    if let Err(_) = socket_listener_thread.join() {
-        exit_with_error_notification("Error joining socket listener thread");
+}
+
+fn join_socket_listener_thread(
+    listener_thread: JoinHandle<Result<(), DaemonError>>,
+) -> Result<(), DaemonError> {
+    match listener_thread.join() {
+        Ok(result) => result,
+        Err(_) => Err(DaemonError::Thread(
+            "Error joining socket listener thread".to_string(),
+        )),
     }
-    println!("Successfully exiting...");
+}
+
+// Notify about newly crossed daily limit thresholds and share the usage with the socket listener.
+fn check_daily_limits(
+    program_times: &mut HashMap<String, time::Duration>,
+    categorized_times: &mut CategorizedTimes,
+    limit_tracker: &mut LimitTracker,
+    limit_usage: &Arc<Mutex<Vec<LimitUsage>>>,
+    notifier: &dyn Notifier,
+    error_handler: &mut ErrorHandler,
+    cipher: Option<&Cipher>,
+) -> Result<(), DaemonError> {
+    let today: NaiveDate = day_of(Local::now(), limit_tracker.day_start_hour());
+    if today != limit_tracker.day() {
+        // Flush yesterday's usage so that it is stamped on yesterday
+        let end_of_yesterday =
+            start_of_day(today, limit_tracker.day_start_hour()) - Duration::from_secs(1);
+        flush_program_times(
+            program_times,
+            categorized_times,
+            limit_tracker,
+            error_handler,
+            cipher,
+            end_of_yesterday,
+        )?;
+        limit_tracker.roll_over(today);
+    }
+    for (usage, threshold) in limit_tracker.check(program_times) {
+        notifier.notify(&daily_limit_alert(&usage, threshold));
+    }
+    match limit_usage.lock() {
+        Ok(mut limit_usage) => *limit_usage = limit_tracker.usage(program_times),
+        Err(err) => eprintln!("Error locking limit usage: {}", err),
+    }
+    Ok(())
+}
 
+// Evaluate the finished days, notify about streaks at risk and share the goal status with the socket listener.
+fn check_goals(
+    goal_tracker: &mut GoalTracker,
+    limit_tracker: &LimitTracker,
+    program_times: &HashMap<String, time::Duration>,
+    goal_status: &Arc<Mutex<Vec<GoalStatus>>>,
+    notifier: &dyn Notifier,
+    error_handler: &mut ErrorHandler,
+) -> Result<(), DaemonError> {
+    // check_daily_limits has already flushed yesterday's usage
+    let today = limit_tracker.day();
+    if let Err(err) = goal_tracker.evaluate(today, &SCREEN_DATA_CSV_PATH.to_string()) {
+        error_handler.handle(DaemonError::CsvRead(format!(
+            "Error evaluating goals: {}",
+            err
+        )))?;
+    }
+    let statuses = goal_tracker.status(&limit_tracker.app_totals(program_times), Local::now());
+    for status in goal_tracker.check_at_risk(&statuses, today) {
+        notifier.notify(&goal_at_risk_alert(&status));
+    }
+    match goal_status.lock() {
+        Ok(mut goal_status) => *goal_status = statuses,
+        Err(err) => eprintln!("Error locking goal status: {}", err),
+    }
     Ok(())
 }
+
+// Store the focus session and notify about it once its time is up or it was stopped.
+fn finish_focus_session(
+    focus_state: &FocusState,
+    notifier: &dyn Notifier,
+    error_handler: &ErrorHandler,
+    cipher: Option<&Cipher>,
+) {
+    if let Some(session) = focus_state.finish_if_over(SystemTime::now()) {
+        println!("Focus session finished");
+        if let Err(err) =
+            store_focus_session(&FOCUS_SESSIONS_FILE_NAME.to_string(), &session, cipher)
+        {
+            error_handler.report(&DaemonError::CsvWrite(format!(
+                "Error storing focus session: {}",
+                err
+            )));
+        }
+        notifier.notify(&focus_session_alert(&session));
+    }
+}
+
+// Send the daily and weekly digests that are due.
+fn send_due_digests(
+    digest_scheduler: &mut DigestScheduler,
+    program_times: &HashMap<String, time::Duration>,
+    cipher: Option<&Cipher>,
+    day_start_hour: u32,
+    notifier: &dyn Notifier,
+    error_handler: &mut ErrorHandler,
+) -> Result<(), DaemonError> {
+    let now = Local::now();
+    for period in digest_scheduler.due(now.naive_local()) {
+        match build_digest(
+            period,
+            &SCREEN_DATA_CSV_PATH.to_string(),
+            cipher,
+            day_of(now, day_start_hour),
+            day_start_hour,
+            program_times,
+        ) {
+            Ok(digest) => notifier.notify(&digest_alert(&digest)),
+            Err(err) => error_handler.handle(DaemonError::CsvRead(format!(
+                "Error building digest: {}",
+                err
+            )))?,
+        }
+    }
+    Ok(())
+}
+
+// Take the scheduled backup if it is due, then the backups, restores and repairs asked for over the socket.
+// They run here, between writes, so the data file is never written while it is copied or replaced.
+fn run_backups(
+    backup_receiver: &Receiver<(BackupRequest, Sender<String>)>,
+    mut backup_scheduler: Option<&mut BackupScheduler>,
+    limit_tracker: &mut LimitTracker,
+    tracks_usage: bool,
+    cipher: Option<&Cipher>,
+    error_handler: &mut ErrorHandler,
+) -> Result<(), DaemonError> {
+    let csv_name = SCREEN_DATA_CSV_PATH.to_string();
+    if let Some(backup_scheduler) = backup_scheduler.as_mut() {
+        if backup_scheduler.due(SystemTime::now()) {
+            if let Err(err) = run_backup(backup_scheduler.schedule(), &csv_name) {
+                error_handler.handle(DaemonError::Backup(err.to_string()))?;
+            }
+        }
+    }
+    while let Ok((request, reply_sender)) = backup_receiver.try_recv() {
+        let reply = match request {
+            BackupRequest::Backup => match &backup_scheduler {
+                Some(backup_scheduler) => {
+                    match run_backup(backup_scheduler.schedule(), &csv_name) {
+                        Ok(backup_path) => format!("Backed up to {}", backup_path.display()),
+                        Err(err) => {
+                            eprintln!("Error backing up: {}", err);
+                            "Failure".to_string()
+                        }
+                    }
+                }
+                None => {
+                    eprintln!("Set {} to take backups", BACKUP_DIR_ENV_VAR);
+                    "Failure".to_string()
+                }
+            },
+            BackupRequest::Restore(snapshot) => {
+                match restore_backup(&snapshot, &csv_name, cipher) {
+                    Ok(summary) => {
+                        // Today's usage may have changed with the data file
+                        if tracks_usage {
+                            if let Err(err) = limit_tracker.load_stored_usage(&csv_name, cipher) {
+                                error_handler.handle(DaemonError::CsvRead(format!(
+                                    "Error reading today's usage: {}",
+                                    err
+                                )))?;
+                            }
+                        }
+                        format_restore_summary(&summary)
+                    }
+                    Err(err) => {
+                        eprintln!("Error restoring {}: {}", snapshot.display(), err);
+                        "Failure".to_string()
+                    }
+                }
+            }
+            BackupRequest::Repair => match repair_csv(&csv_name, cipher) {
+                Ok(0) => "No malformed rows found".to_string(),
+                Ok(quarantined) => format!(
+                    "Moved {} malformed rows to {}{}",
+                    quarantined, QUARANTINE_FILE_PREFIX, csv_name
+                ),
+                Err(err) => {
+                    eprintln!("Error repairing csv: {}", err);
+                    "Failure".to_string()
+                }
+            },
+        };
+        // The client may have stopped waiting
+        if reply_sender.send(reply).is_err() {
+            eprintln!("The client stopped waiting for the backup reply");
+        }
+    }
+    Ok(())
+}
+
+// Share the time that has not been written to the csv yet with the socket listener.
+fn share_program_times(
+    socket_state: &SocketState,
+    program_times: &HashMap<String, time::Duration>,
+) {
+    match socket_state.program_times.lock() {
+        Ok(mut shared_program_times) => shared_program_times.clone_from(program_times),
+        Err(err) => eprintln!("Error locking program times: {}", err),
+    }
+}
+
+// Record a paused period as an explicit gap in the data.
+fn record_paused_period(
+    program_times: &mut HashMap<String, time::Duration>,
+    paused_duration: Duration,
+) {
+    *program_times
+        .entry(PAUSED_APPLICATION.to_string())
+        .or_default() += paused_duration;
+}
+
+// Run a command line command (e.g. `screen_timed pause 30`) against the running daemon.
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args {
+        [command] if command == "encrypt" => return encrypt_data_file(),
+        [command, source, export_paths @ ..]
+            if command == "import" && source == "activitywatch" =>
+        {
+            return import_activitywatch_history(export_paths);
+        }
+        [command, data_files @ ..] if command == "merge" => return merge_other_hosts(data_files),
+        [command, snapshot] if command == "restore" => return restore_from_backup(snapshot),
+        _ => {}
+    }
+    let response = cli::run_command(args, SOCKET_ADDR)?;
+    println!("{}", response);
+    Ok(())
+}
+
+// Encrypt an existing plain data file with the key set in .env, while the daemon is stopped.
+fn encrypt_data_file() -> Result<(), Box<dyn Error>> {
+    if send_message_to_socket(SOCKET_ADDR, "HEALTH_CHECK").is_ok() {
+        return Err("Stop the daemon before encrypting the data file".into());
+    }
+    let env_config = new_config()?;
+    let key_source = env_config.get_key_source().ok_or(format!(
+        "Set {} or {} in .env to encrypt the data file",
+        ENCRYPTION_KEYFILE_ENV_VAR, ENCRYPTION_PASSPHRASE_ENV_VAR
+    ))?;
+    encrypt_existing_csv(&SCREEN_DATA_CSV_PATH.to_string(), key_source)?;
+    println!("Encrypted {}", SCREEN_DATA_CSV_PATH);
+    Ok(())
+}
+
+// Open the data file for a command run without the daemon, upgrading it to the current schema first.
+fn open_data_file(env_config: &Config) -> Result<Option<Cipher>, Box<dyn Error>> {
+    let csv_name = SCREEN_DATA_CSV_PATH.to_string();
+    recover_interrupted_rewrite(&csv_name)?;
+    let cipher = match env_config.get_key_source() {
+        Some(key_source) => Some(Cipher::open(&csv_name, key_source)?),
+        None => None,
+    };
+    migrate_csv(&csv_name, cipher.as_ref())?;
+    Ok(cipher)
+}
+
+// Restore a snapshot, through the daemon if it is running so that it stops writing meanwhile.
+fn restore_from_backup(snapshot: &str) -> Result<(), Box<dyn Error>> {
+    let snapshot =
+        fs::canonicalize(snapshot).map_err(|err| format!("Error finding {}: {}", snapshot, err))?;
+    let env_config = new_config()?;
+    let csv_name = SCREEN_DATA_CSV_PATH.to_string();
+    let cipher = match env_config.get_key_source() {
+        Some(key_source) => Some(Cipher::open(&csv_name, key_source)?),
+        None => None,
+    };
+    // Checked here too so that the reason a snapshot is invalid is shown
+    let rows = validate_backup(&snapshot, &csv_name, cipher.as_ref())?;
+    println!("{} is valid with {} rows", snapshot.display(), rows);
+    if send_message_to_socket(SOCKET_ADDR, "HEALTH_CHECK").is_ok() {
+        let args = ["restore".to_string(), snapshot.display().to_string()];
+        println!("{}", cli::run_command(&args, SOCKET_ADDR)?);
+        return Ok(());
+    }
+    recover_interrupted_rewrite(&csv_name)?;
+    let summary = restore_backup(&snapshot, &csv_name, cipher.as_ref())?;
+    println!("{}", format_restore_summary(&summary));
+    Ok(())
+}
+
+// Merge the data files of other machines, e.g. `screen_timed merge laptop.csv` or `screen_timed merge --host laptop old_laptop.csv`.
+fn merge_other_hosts(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (default_host, data_files) = match args {
+        [flag, host, data_files @ ..] if flag == "--host" => (Some(host.as_str()), data_files),
+        data_files => (None, data_files),
+    };
+    if data_files.is_empty() {
+        return Err("Give the data files to merge".into());
+    }
+    if send_message_to_socket(SOCKET_ADDR, "HEALTH_CHECK").is_ok() {
+        return Err("Stop the daemon before merging data files".into());
+    }
+    let env_config = new_config()?;
+    let cipher = open_data_file(&env_config)?;
+    let summary = merge_data_files(
+        data_files,
+        default_host,
+        &SCREEN_DATA_CSV_PATH.to_string(),
+        cipher.as_ref(),
+        env_config.get_key_source(),
+    )?;
+    let hosts: Vec<&str> = summary.hosts.iter().map(String::as_str).collect();
+    println!(
+        "Merged {} rows from {}, skipped {} rows that were already recorded",
+        summary.merged_rows,
+        match hosts.is_empty() {
+            true => "no other machines".to_string(),
+            false => hosts.join(", "),
+        },
+        summary.skipped_rows
+    );
+    Ok(())
+}
+
+// Import ActivityWatch exports through the same writer as the daemon, with its privacy and alias rules.
+fn import_activitywatch_history(export_paths: &[String]) -> Result<(), Box<dyn Error>> {
+    if export_paths.is_empty() {
+        return Err("Give the ActivityWatch export files to import".into());
+    }
+    let history = read_activitywatch_exports(export_paths)?;
+    if history.is_empty() {
+        return Err("The exports have no currentwindow events to import".into());
+    }
+    let env_config = new_config()?;
+    let csv_name = SCREEN_DATA_CSV_PATH.to_string();
+    let cipher = open_data_file(&env_config)?;
+    let alias_rules = match env_config.get_alias_at_write() {
+        true => AliasRules::load(Path::new(ALIASES_FILE_NAME))?,
+        false => AliasRules::default(),
+    };
+    let summary = import_activitywatch(
+        &history,
+        &csv_name,
+        cipher.as_ref(),
+        env_config.get_privacy_rules(),
+        &alias_rules,
+    )?;
+    println!(
+        "Imported {} of screen time in {} rows, skipped {} hours that were already recorded",
+        format_duration(Duration::from_secs(summary.seconds)),
+        summary.rows,
+        summary.skipped_hours
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_writer::read_valid_rows;
+    use crate::limits::parse_daily_limits;
+    use crate::notification::{AlertKind, RecordingNotifier};
+    use crate::test_helpers::tests::setup;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn daily_limit_alerts_are_sent_to_the_notifier() {
+        let (_temp_dir, _) = setup();
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut error_handler = ErrorHandler::new(notifier.clone());
+        let limit_usage: Arc<Mutex<Vec<LimitUsage>>> = Arc::new(Mutex::new(Vec::new()));
+        let mut limit_tracker = LimitTracker::new(
+            parse_daily_limits("Slack=10").unwrap(),
+            vec![80, 100],
+            0,
+            Local::now(),
+        );
+        let mut program_times: HashMap<String, time::Duration> = HashMap::new();
+        program_times.insert("Slack".to_string(), Duration::from_secs(9 * 60));
+
+        check_daily_limits(
+            &mut program_times,
+            &mut CategorizedTimes::new(),
+            &mut limit_tracker,
+            &limit_usage,
+            notifier.as_ref(),
+            &mut error_handler,
+            None,
+        )
+        .unwrap();
+        check_daily_limits(
+            &mut program_times,
+            &mut CategorizedTimes::new(),
+            &mut limit_tracker,
+            &limit_usage,
+            notifier.as_ref(),
+            &mut error_handler,
+            None,
+        )
+        .unwrap();
+
+        let alerts = notifier.alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::DailyLimit);
+        assert_eq!(
+            alerts[0].body,
+            "You have used 80% of your daily limit for Slack (9 of 10 minutes)"
+        );
+        assert_eq!(
+            limit_usage.lock().unwrap()[0].used,
+            Duration::from_secs(9 * 60)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn failed_flush_keeps_program_times_for_retry() {
+        let (_temp_dir, _) = setup();
+        // A directory in place of the csv makes writing fail
+        std::fs::create_dir(SCREEN_DATA_CSV_PATH).unwrap();
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut error_handler = ErrorHandler::new(notifier.clone());
+        let mut limit_tracker = LimitTracker::new(Vec::new(), Vec::new(), 0, Local::now());
+        let mut program_times: HashMap<String, time::Duration> = HashMap::new();
+        program_times.insert("Code".to_string(), Duration::from_secs(60));
+
+        for _ in 0..2 {
+            flush_program_times(
+                &mut program_times,
+                &mut CategorizedTimes::new(),
+                &mut limit_tracker,
+                &mut error_handler,
+                None,
+                SystemTime::now(),
+            )
+            .unwrap();
+        }
+        assert_eq!(program_times.len(), 1);
+        assert_eq!(notifier.alerts().len(), 1);
+        assert_eq!(notifier.alerts()[0].kind, AlertKind::Error);
+
+        std::fs::remove_dir(SCREEN_DATA_CSV_PATH).unwrap();
+        flush_program_times(
+            &mut program_times,
+            &mut CategorizedTimes::new(),
+            &mut limit_tracker,
+            &mut error_handler,
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
+        assert!(program_times.is_empty());
+        assert!(std::path::Path::new(SCREEN_DATA_CSV_PATH).exists());
+    }
+
+    #[test]
+    #[serial]
+    fn flushes_during_a_repair_are_kept() {
+        let (_temp_dir, _) = setup();
+        std::fs::write(
+            SCREEN_DATA_CSV_PATH,
+            "timestamp,application,duration\n1,Code,60\n2,Firefox\n",
+        )
+        .unwrap();
+        migrate_csv(&SCREEN_DATA_CSV_PATH.to_string(), None).unwrap();
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut error_handler = ErrorHandler::new(notifier.clone());
+        let mut limit_tracker = LimitTracker::new(Vec::new(), Vec::new(), 0, Local::now());
+        let (backup_sender, backup_receiver) = mpsc::channel();
+        let (reply_sender, reply_receiver) = mpsc::channel();
+        // Asked for over the socket, the repair waits for the run() loop
+        backup_sender
+            .send((BackupRequest::Repair, reply_sender))
+            .unwrap();
+        let mut program_times: HashMap<String, time::Duration> = HashMap::new();
+        program_times.insert("Slack".to_string(), Duration::from_secs(30));
+        flush_program_times(
+            &mut program_times,
+            &mut CategorizedTimes::new(),
+            &mut limit_tracker,
+            &mut error_handler,
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
+
+        run_backups(
+            &backup_receiver,
+            None,
+            &mut limit_tracker,
+            false,
+            None,
+            &mut error_handler,
+        )
+        .unwrap();
+        assert_eq!(
+            reply_receiver.recv().unwrap(),
+            "Moved 1 malformed rows to quarantine_screen_time_data.csv"
+        );
+        let rows = read_valid_rows(&SCREEN_DATA_CSV_PATH.to_string(), None).unwrap();
+        let applications: Vec<&str> = rows.iter().map(|row| row.application()).collect();
+        assert_eq!(applications, vec!["Code", "Slack"]);
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, NaiveDate, TimeDelta, TimeZone};
+
+use crate::csv_writer::read_app_totals;
+use crate::encryption::Cipher;
+
+// A daily time budget shared by one or more applications.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailyLimit {
+    pub name: String,
+    pub apps: Vec<String>,
+    pub minutes: u64,
+}
+
+impl DailyLimit {
+    fn includes(&self, app_name: &str) -> bool {
+        self.apps
+            .iter()
+            .any(|app| app.eq_ignore_ascii_case(app_name))
+    }
+}
+
+// How much of a daily limit has been used today.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitUsage {
+    pub name: String,
+    pub used: Duration,
+    pub minutes: u64,
+}
+
+impl LimitUsage {
+    pub fn percentage(&self) -> u64 {
+        if self.minutes == 0 {
+            return 100;
+        }
+        self.used.as_secs() * 100 / (self.minutes * 60)
+    }
+
+    pub fn is_over_limit(&self) -> bool {
+        self.used >= Duration::from_secs(self.minutes * 60)
+    }
+}
+
+// Parse limits such as "Slack=90,Discord+Telegram=60".
+// Applications joined with '+' share one budget, named after the whole group.
+pub fn parse_daily_limits(daily_limits: &str) -> Result<Vec<DailyLimit>, &'static str> {
+    let mut limits = Vec::new();
+    for limit_str in daily_limits.split(',').map(str::trim) {
+        if limit_str.is_empty() {
+            continue;
+        }
+        let (name, minutes) = match limit_str.rsplit_once('=') {
+            Some((name, minutes)) => (name.trim(), minutes.trim()),
+            None => return Err("Daily limits should look like Application=minutes"),
+        };
+        let minutes: u64 = match minutes.parse() {
+            Ok(minutes) if minutes > 0 => minutes,
+            _ => return Err("Daily limit minutes should be a positive number"),
+        };
+        let apps: Vec<String> = name
+            .split('+')
+            .map(|app| app.trim().to_string())
+            .filter(|app| !app.is_empty())
+            .collect();
+        if apps.is_empty() {
+            return Err("Daily limit is missing an application name");
+        }
+        limits.push(DailyLimit {
+            name: name.to_string(),
+            apps,
+            minutes,
+        });
+    }
+    Ok(limits)
+}
+
+// Parse percentages such as "80,100" at which to notify.
+pub fn parse_limit_thresholds(thresholds: &str) -> Result<Vec<u64>, &'static str> {
+    let mut parsed_thresholds = Vec::new();
+    for threshold in thresholds.split(',').map(str::trim) {
+        match threshold.parse::<u64>() {
+            Ok(threshold) if threshold > 0 => parsed_thresholds.push(threshold),
+            _ => return Err("Limit thresholds should be positive percentages"),
+        }
+    }
+    parsed_thresholds.sort_unstable();
+    parsed_thresholds.dedup();
+    Ok(parsed_thresholds)
+}
+
+pub fn parse_day_start_hour(day_start_hour: &str) -> Result<u32, &'static str> {
+    match day_start_hour.parse::<u32>() {
+        Ok(day_start_hour) if day_start_hour < 24 => Ok(day_start_hour),
+        _ => Err("The day start hour should be from 0 to 23"),
+    }
+}
+
+// Start of the given local day as a SystemTime.
+// Days start at day_start_hour, so time after midnight and before it counts towards the day before.
+pub fn start_of_day(day: NaiveDate, day_start_hour: u32) -> SystemTime {
+    let day_start = day.and_hms_opt(day_start_hour, 0, 0).unwrap_or_default();
+    let start = Local
+        .from_local_datetime(&day_start)
+        .earliest()
+        .or_else(|| {
+            // The clocks skipped the start of the day (DST), so it starts when they changed
+            Local
+                .from_local_datetime(&(day_start + TimeDelta::hours(1)))
+                .earliest()
+        });
+    match start {
+        Some(start) => start.into(),
+        None => day_start.and_utc().into(),
+    }
+}
+
+// The day the local time counts towards, the day before if it is before day_start_hour.
+pub fn day_of(time: DateTime<Local>, day_start_hour: u32) -> NaiveDate {
+    (time.naive_local() - TimeDelta::hours(day_start_hour as i64)).date()
+}
+
+// Evaluates today's usage against the daily limits.
+// Usage is what was already flushed to the csv today plus the in-memory program_times.
+pub struct LimitTracker {
+    limits: Vec<DailyLimit>,
+    thresholds: Vec<u64>,
+    day_start_hour: u32,
+    day: NaiveDate,
+    stored_today: HashMap<String, Duration>,
+    // (limit name, threshold) pairs that have already been notified today
+    notified: HashSet<(String, u64)>,
+}
+
+impl LimitTracker {
+    pub fn new(
+        limits: Vec<DailyLimit>,
+        thresholds: Vec<u64>,
+        day_start_hour: u32,
+        now: DateTime<Local>,
+    ) -> LimitTracker {
+        LimitTracker {
+            limits,
+            thresholds,
+            day_start_hour,
+            day: day_of(now, day_start_hour),
+            stored_today: HashMap::new(),
+            notified: HashSet::new(),
+        }
+    }
+
+    pub fn day(&self) -> NaiveDate {
+        self.day
+    }
+
+    pub fn day_start_hour(&self) -> u32 {
+        self.day_start_hour
+    }
+
+    pub fn has_limits(&self) -> bool {
+        !self.limits.is_empty()
+    }
+
+    //Load the usage already written to the csv today.
+    pub fn load_stored_usage(
+        &mut self,
+        csv_name: &String,
+        cipher: Option<&Cipher>,
+    ) -> Result<(), Box<dyn Error>> {
+        let start = start_of_day(self.day, self.day_start_hour);
+        let end = match self.day.succ_opt() {
+            Some(next_day) => start_of_day(next_day, self.day_start_hour),
+            None => SystemTime::now(),
+        };
+        self.stored_today = read_app_totals(csv_name, cipher, start, end)?;
+        Ok(())
+    }
+
+    //Returns true if the day changed, in which case the usage starts from zero.
+    pub fn roll_over(&mut self, today: NaiveDate) -> bool {
+        if today == self.day {
+            return false;
+        }
+        self.day = today;
+        self.stored_today.clear();
+        self.notified.clear();
+        true
+    }
+
+    //Keep track of usage that has been written to the csv (and cleared from program_times).
+    pub fn record_flushed(&mut self, program_times: &HashMap<String, Duration>) {
+        for (program_name, duration) in program_times {
+            *self
+                .stored_today
+                .entry(program_name.to_string())
+                .or_default() += *duration;
+        }
+    }
+
+    //Today's usage per application, stored and in memory.
+    pub fn app_totals(
+        &self,
+        program_times: &HashMap<String, Duration>,
+    ) -> HashMap<String, Duration> {
+        let mut app_totals = self.stored_today.clone();
+        for (program_name, duration) in program_times {
+            *app_totals.entry(program_name.to_string()).or_default() += *duration;
+        }
+        app_totals
+    }
+
+    pub fn usage(&self, program_times: &HashMap<String, Duration>) -> Vec<LimitUsage> {
+        self.limits
+            .iter()
+            .map(|limit| {
+                let used = self
+                    .stored_today
+                    .iter()
+                    .chain(program_times.iter())
+                    .filter(|(app_name, _)| limit.includes(app_name))
+                    .map(|(_, duration)| *duration)
+                    .sum();
+                LimitUsage {
+                    name: limit.name.clone(),
+                    used,
+                    minutes: limit.minutes,
+                }
+            })
+            .collect()
+    }
+
+    //Returns the usage and highest newly crossed threshold of each limit that needs a notification.
+    pub fn check(&mut self, program_times: &HashMap<String, Duration>) -> Vec<(LimitUsage, u64)> {
+        let mut crossed = Vec::new();
+        for usage in self.usage(program_times) {
+            let percentage = usage.percentage();
+            let new_thresholds: Vec<u64> = self
+                .thresholds
+                .iter()
+                .filter(|threshold| percentage >= **threshold)
+                .filter(|threshold| !self.notified.contains(&(usage.name.clone(), **threshold)))
+                .copied()
+                .collect();
+            for threshold in &new_thresholds {
+                self.notified.insert((usage.name.clone(), *threshold));
+            }
+            if let Some(highest_threshold) = new_thresholds.last() {
+                crossed.push((usage, *highest_threshold));
+            }
+        }
+        crossed
+    }
+}
+
+// Format the usage of each limit, one per line, for the LIMITS socket request.
+pub fn format_limit_usage(limit_usage: &[LimitUsage]) -> String {
+    limit_usage
+        .iter()
+        .map(|usage| {
+            format!(
+                "{}: {}/{} minutes ({}%){}",
+                usage.name,
+                usage.used.as_secs() / 60,
+                usage.minutes,
+                usage.percentage(),
+                if usage.is_over_limit() {
+                    " - over limit"
+                } else {
+                    ""
+                }
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::tests::minutes;
+
+    #[test]
+    fn parse_valid_daily_limits() {
+        let limits = parse_daily_limits("Slack=90, Discord+Telegram=60,").unwrap();
+        assert_eq!(
+            limits,
+            vec![
+                DailyLimit {
+                    name: "Slack".to_string(),
+                    apps: vec!["Slack".to_string()],
+                    minutes: 90
+                },
+                DailyLimit {
+                    name: "Discord+Telegram".to_string(),
+                    apps: vec!["Discord".to_string(), "Telegram".to_string()],
+                    minutes: 60
+                },
+            ]
+        );
+        assert!(parse_daily_limits("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_invalid_daily_limits() {
+        assert!(parse_daily_limits("Slack").is_err());
+        assert!(parse_daily_limits("Slack=0").is_err());
+        assert!(parse_daily_limits("Slack=lots").is_err());
+        assert!(parse_daily_limits("=90").is_err());
+    }
+
+    #[test]
+    fn days_start_at_the_day_start_hour() {
+        assert_eq!(parse_day_start_hour("4"), Ok(4));
+        assert!(parse_day_start_hour("24").is_err());
+        assert!(parse_day_start_hour("4am").is_err());
+
+        let day = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+        let next_day = day.succ_opt().unwrap();
+        let at = |hour| {
+            Local
+                .from_local_datetime(&next_day.and_hms_opt(hour, 0, 0).unwrap())
+                .unwrap()
+        };
+        assert_eq!(day_of(at(3), 4), day);
+        assert_eq!(day_of(at(4), 4), next_day);
+        assert_eq!(day_of(at(3), 0), next_day);
+        assert_eq!(start_of_day(next_day, 4), SystemTime::from(at(4)));
+    }
+
+    #[test]
+    fn parse_thresholds() {
+        assert_eq!(parse_limit_thresholds("100, 80").unwrap(), vec![80, 100]);
+        assert!(parse_limit_thresholds("80,").is_err());
+        assert!(parse_limit_thresholds("0").is_err());
+    }
+
+    #[test]
+    fn usage_includes_stored_and_in_memory_time() {
+        let limits = parse_daily_limits("slack=90").unwrap();
+        let mut tracker = LimitTracker::new(limits, vec![80, 100], 0, Local::now());
+        let mut program_times = HashMap::new();
+        program_times.insert("Slack".to_string(), minutes(30));
+        tracker.record_flushed(&program_times);
+        program_times.insert("Slack".to_string(), minutes(15));
+        program_times.insert("Code".to_string(), minutes(15));
+
+        let usage = tracker.usage(&program_times);
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].used, minutes(45));
+        assert_eq!(usage[0].percentage(), 50);
+        assert!(!usage[0].is_over_limit());
+    }
+
+    #[test]
+    fn thresholds_notify_once_per_day() {
+        let limits = parse_daily_limits("Slack=100").unwrap();
+        let mut tracker = LimitTracker::new(limits, vec![80, 100], 0, Local::now());
+        let mut program_times = HashMap::new();
+
+        program_times.insert("Slack".to_string(), minutes(79));
+        assert!(tracker.check(&program_times).is_empty());
+
+        program_times.insert("Slack".to_string(), minutes(80));
+        let crossed = tracker.check(&program_times);
+        assert_eq!(crossed.len(), 1);
+        assert_eq!(crossed[0].1, 80);
+        assert!(tracker.check(&program_times).is_empty());
+
+        program_times.insert("Slack".to_string(), minutes(101));
+        let crossed = tracker.check(&program_times);
+        assert_eq!(crossed.len(), 1);
+        assert_eq!(crossed[0].1, 100);
+        assert!(crossed[0].0.is_over_limit());
+        assert!(tracker.check(&program_times).is_empty());
+
+        //A new day starts from zero
+        assert!(tracker.roll_over(tracker.day().succ_opt().unwrap()));
+        program_times.insert("Slack".to_string(), minutes(85));
+        assert_eq!(tracker.check(&program_times)[0].1, 80);
+    }
+
+    #[test]
+    fn jumping_past_several_thresholds_notifies_the_highest() {
+        let limits = parse_daily_limits("Slack=10").unwrap();
+        let mut tracker = LimitTracker::new(limits, vec![80, 100], 0, Local::now());
+        let mut program_times = HashMap::new();
+        program_times.insert("Slack".to_string(), minutes(20));
+        let crossed = tracker.check(&program_times);
+        assert_eq!(crossed.len(), 1);
+        assert_eq!(crossed[0].1, 100);
+        assert!(tracker.check(&program_times).is_empty());
+    }
+
+    #[test]
+    fn format_usage() {
+        let usage = vec![
+            LimitUsage {
+                name: "Slack".to_string(),
+                used: minutes(95),
+                minutes: 90,
+            },
+            LimitUsage {
+                name: "Discord".to_string(),
+                used: minutes(30),
+                minutes: 60,
+            },
+        ];
+        assert_eq!(
+            format_limit_usage(&usage),
+            "Slack: 95/90 minutes (105%) - over limit\nDiscord: 30/60 minutes (50%)"
+        );
+    }
+}
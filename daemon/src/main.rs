@@ -1,3 +1,15 @@
+use std::env;
+use std::process::exit;
+
 fn main() {
-    screen_timed::run().unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        if let Err(err) = screen_timed::run() {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    } else if let Err(err) = screen_timed::run_cli(&args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
 }
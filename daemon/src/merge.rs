@@ -0,0 +1,162 @@
+use std::collections::{BTreeSet, HashSet};
+use std::error::Error;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::csv_writer::{append_rows, read_valid_rows, Row};
+use crate::encryption::{is_encrypted, Cipher, KeySource};
+use crate::host::local_host_id;
+
+#[derive(Debug, PartialEq)]
+pub struct MergeSummary {
+    pub merged_rows: usize,
+    // Rows already in the csv, or recorded on this machine
+    pub skipped_rows: usize,
+    pub hosts: BTreeSet<String>,
+}
+
+// Rows are the same if they were written at the same time on the same host for the same application.
+fn row_key(row: &Row) -> Result<(u64, String, u64, String), Box<dyn Error>> {
+    Ok((
+        row.timestamp().duration_since(UNIX_EPOCH)?.as_secs(),
+        row.application().to_string(),
+        row.duration(),
+        row.host().to_string(),
+    ))
+}
+
+//Read another machine's data file, decrypting it with the same key if it is encrypted.
+fn read_other_data_file(
+    data_file: &String,
+    key_source: Option<&KeySource>,
+) -> Result<Vec<Row>, Box<dyn Error>> {
+    if !Path::new(data_file).exists() {
+        return Err(format!("{} does not exist", data_file).into());
+    }
+    if !is_encrypted(data_file)? {
+        return read_valid_rows(data_file, None);
+    }
+    let key_source = key_source.ok_or(format!(
+        "{} is encrypted, set the key it was encrypted with to merge it",
+        data_file
+    ))?;
+    let cipher = Cipher::open(data_file, key_source)?;
+    read_valid_rows(data_file, Some(&cipher))
+}
+
+//Append the rows of other machines' data files to the csv.
+//Rows already in the csv and rows recorded on this machine are skipped, so merging twice does not double count.
+//Rows from before the host column existed are given default_host.
+pub fn merge_data_files(
+    data_files: &[String],
+    default_host: Option<&str>,
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+    key_source: Option<&KeySource>,
+) -> Result<MergeSummary, Box<dyn Error>> {
+    let mut known_rows = HashSet::new();
+    if Path::new(csv_name).exists() {
+        for row in read_valid_rows(csv_name, cipher)? {
+            known_rows.insert(row_key(&row)?);
+        }
+    }
+    let mut summary = MergeSummary {
+        merged_rows: 0,
+        skipped_rows: 0,
+        hosts: BTreeSet::new(),
+    };
+    let mut new_rows = Vec::new();
+    for data_file in data_files {
+        for mut row in read_other_data_file(data_file, key_source)? {
+            if row.host().is_empty() {
+                let default_host = default_host.ok_or(format!(
+                    "{} has no host column, give the name of the machine it is from",
+                    data_file
+                ))?;
+                row.set_host(default_host);
+            }
+            if row.host() == local_host_id() || !known_rows.insert(row_key(&row)?) {
+                summary.skipped_rows += 1;
+                continue;
+            }
+            summary.hosts.insert(row.host().to_string());
+            new_rows.push(row);
+        }
+    }
+    new_rows.sort_by_key(Row::timestamp);
+    append_rows(&new_rows, csv_name, cipher)?;
+    summary.merged_rows = new_rows.len();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_writer::{read_app_totals, write_data_to_csv};
+    use crate::test_helpers::tests::{setup, CSV_NAME};
+    use serial_test::serial;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    const LAPTOP_CSV_NAME: &str = "laptop_screen_time_data.csv";
+
+    fn app_totals() -> HashMap<String, Duration> {
+        read_app_totals(
+            &CSV_NAME.to_string(),
+            None,
+            UNIX_EPOCH,
+            SystemTime::now() + Duration::from_secs(60),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    #[serial]
+    fn merging_twice_does_not_double_count() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let mut program_times = HashMap::new();
+        program_times.insert("Code".to_string(), Duration::from_secs(60));
+        write_data_to_csv(&program_times, &csv_name, None, SystemTime::now()).unwrap();
+        fs::write(
+            LAPTOP_CSV_NAME,
+            format!(
+                "timestamp,application,duration,host\n1,Code,30,laptop\n1,Firefox,20,laptop\n2,Code,60,{}\n",
+                local_host_id()
+            ),
+        )
+        .unwrap();
+        let merge = || {
+            merge_data_files(&[LAPTOP_CSV_NAME.to_string()], None, &csv_name, None, None).unwrap()
+        };
+
+        let summary = merge();
+        assert_eq!(summary.merged_rows, 2);
+        //The row recorded on this machine is already in the csv
+        assert_eq!(summary.skipped_rows, 1);
+        assert_eq!(summary.hosts, BTreeSet::from(["laptop".to_string()]));
+        assert_eq!(merge().merged_rows, 0);
+        assert_eq!(app_totals()["Code"], Duration::from_secs(90));
+        assert_eq!(app_totals()["Firefox"], Duration::from_secs(20));
+    }
+
+    #[test]
+    #[serial]
+    fn files_without_hosts_need_a_default_host() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        fs::write(
+            LAPTOP_CSV_NAME,
+            "timestamp,application,duration\n1,Code,30\n",
+        )
+        .unwrap();
+        let data_files = [LAPTOP_CSV_NAME.to_string()];
+        assert!(merge_data_files(&data_files, None, &csv_name, None, None).is_err());
+
+        let summary = merge_data_files(&data_files, Some("laptop"), &csv_name, None, None).unwrap();
+        assert_eq!(summary.merged_rows, 1);
+        let rows = read_valid_rows(&csv_name, None).unwrap();
+        assert_eq!(rows[0].host(), "laptop");
+    }
+}
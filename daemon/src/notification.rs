@@ -1,35 +1,343 @@
-use std::process::exit;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
 
 use notify_rust::Notification;
+use notify_rust::NotificationHandle;
 use notify_rust::Timeout;
 
-pub fn screen_time_notification(alert_screen_time: u64) {
-    let alert_message = format!(
-        "You have been on the screen for {} minutes",
-        alert_screen_time
-    );
-    if let Err(err) = Notification::new()
-        .summary("Screen Time Alert")
-        .body(alert_message.as_str())
-        .timeout(Timeout::Never)
-        .show()
-    {
-        eprintln!("Error showing notification: {}", err);
-        exit(1);
-    }
-}
-
-// Send error notification to user, then process exit with code 1.
-pub fn exit_with_error_notification(error_message: &str) -> ! {
-    eprintln!("{}", &error_message);
-    if let Err(err) = Notification::new()
-        .summary("Error")
-        .body(error_message)
-        .timeout(Timeout::Never)
-        .show()
-    {
-        eprintln!("Error showing notification: {}", err);
-    }
-
-    exit(1);
+use crate::breaks::BreakPreset;
+use crate::digest::{format_digest, format_duration, Digest, DigestPeriod};
+use crate::focus::FocusSession;
+use crate::goals::{GoalKind, GoalStatus};
+use crate::limits::LimitUsage;
+
+const SNOOZE_ACTION: &str = "snooze";
+const START_BREAK_ACTION: &str = "start_break";
+const PAUSE_TRACKING_ACTION: &str = "pause_tracking";
+
+pub const DESKTOP_NOTIFIER: &str = "desktop";
+pub const LOG_NOTIFIER: &str = "log";
+
+// What the user chose on an actionable notification.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotificationAction {
+    Snooze,
+    StartBreak,
+    PauseTracking,
+    // Closed, or clicked without choosing an action
+    Dismiss,
+}
+
+impl NotificationAction {
+    pub fn from_identifier(identifier: &str) -> NotificationAction {
+        match identifier {
+            SNOOZE_ACTION => NotificationAction::Snooze,
+            START_BREAK_ACTION => NotificationAction::StartBreak,
+            PAUSE_TRACKING_ACTION => NotificationAction::PauseTracking,
+            _ => NotificationAction::Dismiss,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertKind {
+    // Has snooze/break/pause actions
+    BreakReminder,
+    DailyLimit,
+    Goal,
+    Focus,
+    Error,
+    // Several alerts combined into one
+    Summary,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub summary: String,
+    pub body: String,
+}
+
+// Where alerts are sent, injected into the daemon so that tests can record them.
+// Failing to deliver an alert is logged, it never stops tracking.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, alert: &Alert);
+}
+
+// Shows alerts as desktop notifications.
+// Actions chosen on break reminders are sent to the run() loop through action_sender.
+pub struct DesktopNotifier {
+    action_sender: Option<Sender<NotificationAction>>,
+}
+
+impl DesktopNotifier {
+    pub fn new(action_sender: Option<Sender<NotificationAction>>) -> DesktopNotifier {
+        DesktopNotifier { action_sender }
+    }
+}
+
+impl Notifier for DesktopNotifier {
+    fn notify(&self, alert: &Alert) {
+        let mut notification = Notification::new();
+        notification
+            .summary(&alert.summary)
+            .body(&alert.body)
+            .timeout(Timeout::Never);
+        let action_sender = match (&self.action_sender, alert.kind) {
+            (Some(action_sender), AlertKind::BreakReminder) => {
+                notification
+                    .action(SNOOZE_ACTION, "Snooze 10 min")
+                    .action(START_BREAK_ACTION, "Start break")
+                    .action(PAUSE_TRACKING_ACTION, "Pause tracking");
+                Some(action_sender.clone())
+            }
+            _ => None,
+        };
+        match notification.show() {
+            Ok(handle) => {
+                if let Some(action_sender) = action_sender {
+                    wait_for_notification_action(handle, action_sender);
+                }
+            }
+            Err(err) => {
+                eprintln!("Error showing notification: {}", err);
+            }
+        }
+    }
+}
+
+// Only logs alerts, for machines without a notification server.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, alert: &Alert) {
+        eprintln!("{}: {}", alert.summary, alert.body);
+    }
+}
+
+// Keeps alerts in memory so that tests can assert on them.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingNotifier {
+    alerts: std::sync::Mutex<Vec<Alert>>,
+}
+
+#[cfg(test)]
+impl RecordingNotifier {
+    pub fn alerts(&self) -> Vec<Alert> {
+        self.alerts.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Notifier for RecordingNotifier {
+    fn notify(&self, alert: &Alert) {
+        self.alerts.lock().unwrap().push(alert.clone());
+    }
+}
+
+pub fn new_notifier(
+    notifier_kind: &str,
+    action_sender: Sender<NotificationAction>,
+) -> Arc<dyn Notifier> {
+    match notifier_kind {
+        LOG_NOTIFIER => Arc::new(LogNotifier),
+        _ => Arc::new(DesktopNotifier::new(Some(action_sender))),
+    }
+}
+
+// Waiting for an action blocks, so it is done on its own thread.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn wait_for_notification_action(
+    handle: NotificationHandle,
+    action_sender: Sender<NotificationAction>,
+) {
+    let spawn_result = std::thread::Builder::new()
+        .name("notification_action_thread".to_string())
+        .spawn(move || {
+            handle.wait_for_action(|identifier| {
+                let action = NotificationAction::from_identifier(identifier);
+                println!("Notification action: {:?}", action);
+                if let Err(err) = action_sender.send(action) {
+                    eprintln!("Error sending notification action: {}", err);
+                }
+            });
+        });
+    if let Err(err) = spawn_result {
+        eprintln!("Error creating notification action thread: {}", err);
+    }
+}
+
+// Notification actions are only supported by the xdg notification server.
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn wait_for_notification_action(
+    _handle: NotificationHandle,
+    _action_sender: Sender<NotificationAction>,
+) {
+}
+
+pub fn screen_time_alert(continuous_use: Duration, preset: Option<BreakPreset>) -> Alert {
+    let (summary, body) = match preset {
+        Some(BreakPreset::TwentyTwentyTwenty) => (
+            "Eye Break",
+            format!(
+                "You have been on the screen for {} minutes. Look at something 20 feet away for 20 seconds",
+                continuous_use.as_secs() / 60
+            ),
+        ),
+        None => (
+            "Screen Time Alert",
+            format!(
+                "You have been on the screen for {} minutes without a break",
+                continuous_use.as_secs() / 60
+            ),
+        ),
+    };
+    Alert {
+        kind: AlertKind::BreakReminder,
+        summary: summary.to_string(),
+        body,
+    }
+}
+
+pub fn daily_limit_alert(limit_usage: &LimitUsage, threshold: u64) -> Alert {
+    Alert {
+        kind: AlertKind::DailyLimit,
+        summary: "Daily Limit Alert".to_string(),
+        body: format!(
+            "You have used {}% of your daily limit for {} ({} of {} minutes)",
+            threshold,
+            limit_usage.name,
+            limit_usage.used.as_secs() / 60,
+            limit_usage.minutes
+        ),
+    }
+}
+
+pub fn goal_at_risk_alert(goal_status: &GoalStatus) -> Alert {
+    let used = goal_status.used_today / 60;
+    let body = match goal_status.kind {
+        GoalKind::AtMost => format!(
+            "You have used {} of your {} minutes of {} today, stay under them to keep your {} day streak",
+            used, goal_status.minutes, goal_status.name, goal_status.current_streak
+        ),
+        GoalKind::AtLeast => format!(
+            "You have {} minutes of {} left to reach today to keep your {} day streak",
+            goal_status.minutes.saturating_sub(used),
+            goal_status.name,
+            goal_status.current_streak
+        ),
+    };
+    Alert {
+        kind: AlertKind::Goal,
+        summary: "Streak At Risk".to_string(),
+        body,
+    }
+}
+
+pub fn distraction_alert(app_name: &str) -> Alert {
+    Alert {
+        kind: AlertKind::Focus,
+        summary: "Focus Session".to_string(),
+        body: format!("{} is not on the list for this focus session", app_name),
+    }
+}
+
+pub fn focus_session_alert(session: &FocusSession) -> Alert {
+    let summary = if session.completed {
+        "Focus Session Complete"
+    } else {
+        "Focus Session Stopped"
+    };
+    Alert {
+        kind: AlertKind::Focus,
+        summary: summary.to_string(),
+        body: format!(
+            "{} focused and {} on other applications, {} distractions",
+            format_duration(Duration::from_secs(session.focused)),
+            format_duration(Duration::from_secs(session.distracted)),
+            session.distractions
+        ),
+    }
+}
+
+pub fn digest_alert(digest: &Digest) -> Alert {
+    let summary = match digest.period {
+        DigestPeriod::Daily => "Daily Screen Time Summary",
+        DigestPeriod::Weekly => "Weekly Screen Time Recap",
+    };
+    Alert {
+        kind: AlertKind::Summary,
+        summary: summary.to_string(),
+        body: format_digest(digest),
+    }
+}
+
+pub fn error_alert(error_message: &str) -> Alert {
+    Alert {
+        kind: AlertKind::Error,
+        summary: "Error".to_string(),
+        body: error_message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notification_actions_are_parsed() {
+        assert_eq!(
+            NotificationAction::from_identifier("snooze"),
+            NotificationAction::Snooze
+        );
+        assert_eq!(
+            NotificationAction::from_identifier("start_break"),
+            NotificationAction::StartBreak
+        );
+        assert_eq!(
+            NotificationAction::from_identifier("pause_tracking"),
+            NotificationAction::PauseTracking
+        );
+        assert_eq!(
+            NotificationAction::from_identifier("__closed"),
+            NotificationAction::Dismiss
+        );
+        assert_eq!(
+            NotificationAction::from_identifier("default"),
+            NotificationAction::Dismiss
+        );
+    }
+
+    #[test]
+    fn screen_time_alerts() {
+        let alert = screen_time_alert(Duration::from_secs(45 * 60), None);
+        assert_eq!(alert.kind, AlertKind::BreakReminder);
+        assert_eq!(alert.summary, "Screen Time Alert");
+        assert_eq!(
+            alert.body,
+            "You have been on the screen for 45 minutes without a break"
+        );
+
+        let alert = screen_time_alert(
+            Duration::from_secs(20 * 60),
+            Some(BreakPreset::TwentyTwentyTwenty),
+        );
+        assert_eq!(alert.summary, "Eye Break");
+    }
+
+    #[test]
+    fn recording_notifier_records_alerts() {
+        let notifier = RecordingNotifier::default();
+        notifier.notify(&error_alert("Something went wrong"));
+        assert_eq!(
+            notifier.alerts(),
+            vec![Alert {
+                kind: AlertKind::Error,
+                summary: "Error".to_string(),
+                body: "Something went wrong".to_string(),
+            }]
+        );
+    }
 }
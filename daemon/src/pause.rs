@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Application name used for the rows that record a paused period.
+pub const PAUSED_APPLICATION: &str = "Paused";
+
+const NOT_PAUSED: u64 = 0;
+const PAUSED_INDEFINITELY: u64 = u64::MAX;
+
+// Shared between the socket listener thread (which pauses/resumes) and the
+// run() loop (which skips tracking while paused).
+// Stores the unix time in seconds at which the pause ends.
+#[derive(Debug, Clone)]
+pub struct PauseState {
+    paused_until: Arc<AtomicU64>,
+}
+
+impl PauseState {
+    pub fn new() -> PauseState {
+        PauseState {
+            paused_until: Arc::new(AtomicU64::new(NOT_PAUSED)),
+        }
+    }
+
+    //Pause tracking, for the duration if given, otherwise until resumed.
+    pub fn pause(&self, duration: Option<Duration>) {
+        let paused_until = match duration {
+            Some(duration) => now_secs() + duration.as_secs(),
+            None => PAUSED_INDEFINITELY,
+        };
+        self.paused_until.store(paused_until, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused_until.store(NOT_PAUSED, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        match self.paused_until.load(Ordering::Relaxed) {
+            NOT_PAUSED => false,
+            PAUSED_INDEFINITELY => true,
+            paused_until => now_secs() < paused_until,
+        }
+    }
+}
+
+impl Default for PauseState {
+    fn default() -> Self {
+        PauseState::new()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+//Parse the arguments of a PAUSE request, e.g. "PAUSE" or "PAUSE 30" (minutes).
+pub fn parse_pause_request(request: &str) -> Result<Option<Duration>, &'static str> {
+    let minutes_str = match request.trim().strip_prefix("PAUSE") {
+        Some(minutes_str) => minutes_str.trim(),
+        None => return Err("Not a pause request"),
+    };
+    if minutes_str.is_empty() {
+        return Ok(None);
+    }
+    match minutes_str.parse::<u64>() {
+        Ok(0) | Err(_) => Err("Invalid pause duration"),
+        Ok(minutes) => Ok(Some(Duration::from_secs(minutes * 60))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pause_and_resume() {
+        let pause_state = PauseState::new();
+        assert!(!pause_state.is_paused());
+        pause_state.pause(None);
+        assert!(pause_state.is_paused());
+        pause_state.resume();
+        assert!(!pause_state.is_paused());
+    }
+
+    #[test]
+    fn timed_pause_expires() {
+        let pause_state = PauseState::new();
+        pause_state.pause(Some(Duration::from_secs(60)));
+        assert!(pause_state.is_paused());
+        pause_state.pause(Some(Duration::from_secs(0)));
+        assert!(!pause_state.is_paused());
+    }
+
+    #[test]
+    fn pause_state_is_shared_between_clones() {
+        let pause_state = PauseState::new();
+        let child_pause_state = pause_state.clone();
+        child_pause_state.pause(None);
+        assert!(pause_state.is_paused());
+    }
+
+    #[test]
+    fn parse_pause_requests() {
+        assert_eq!(parse_pause_request("PAUSE").unwrap(), None);
+        assert_eq!(
+            parse_pause_request("PAUSE 30").unwrap(),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert!(parse_pause_request("PAUSE 0").is_err());
+        assert!(parse_pause_request("PAUSE soon").is_err());
+        assert!(parse_pause_request("RESUME").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use regex::Regex;
+
+// Application name recorded for windows matching the privacy rules.
+pub const PRIVATE_APPLICATION: &str = "Private";
+
+pub const LABEL_PRIVACY_ACTION: &str = "label";
+pub const SKIP_PRIVACY_ACTION: &str = "skip";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PrivacyAction {
+    // Record the time under PRIVATE_APPLICATION
+    #[default]
+    Label,
+    // Do not record the time at all
+    Skip,
+}
+
+impl PrivacyAction {
+    pub fn parse(action: &str) -> Result<PrivacyAction, &'static str> {
+        match action.trim() {
+            LABEL_PRIVACY_ACTION => Ok(PrivacyAction::Label),
+            SKIP_PRIVACY_ACTION => Ok(PrivacyAction::Skip),
+            _ => Err("Privacy action should be label or skip"),
+        }
+    }
+}
+
+// Windows whose name should never be recorded, applied before anything enters program_times.
+#[derive(Debug, Clone, Default)]
+pub struct PrivacyRules {
+    // Application names, matched case-insensitively
+    pub apps: Vec<String>,
+    // Matched against the window title
+    pub title: Option<Regex>,
+    pub action: PrivacyAction,
+}
+
+// Parse application names such as "KeePassXC,Bitwarden".
+pub fn parse_private_apps(private_apps: &str) -> Result<Vec<String>, &'static str> {
+    Ok(private_apps
+        .split(',')
+        .map(str::trim)
+        .filter(|app| !app.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+pub fn parse_private_titles(private_titles: &str) -> Result<Regex, regex::Error> {
+    Regex::new(private_titles)
+}
+
+impl PrivacyRules {
+    //Returns the name to record the window under, or None if it should not be recorded.
+    pub fn apply<'a>(&self, app_name: &'a str, title: &str) -> Option<&'a str> {
+        let is_private_app = self
+            .apps
+            .iter()
+            .any(|app| app.eq_ignore_ascii_case(app_name));
+        let is_private_title = self
+            .title
+            .as_ref()
+            .is_some_and(|title_regex| title_regex.is_match(title));
+        if !is_private_app && !is_private_title {
+            return Some(app_name);
+        }
+        match self.action {
+            PrivacyAction::Label => Some(PRIVATE_APPLICATION),
+            PrivacyAction::Skip => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn privacy_rules(action: PrivacyAction) -> PrivacyRules {
+        PrivacyRules {
+            apps: parse_private_apps("KeePassXC, Bitwarden,").unwrap(),
+            title: Some(parse_private_titles("(?i)private browsing|incognito").unwrap()),
+            action,
+        }
+    }
+
+    #[test]
+    fn private_windows_are_labelled() {
+        let privacy_rules = privacy_rules(PrivacyAction::Label);
+        assert_eq!(
+            privacy_rules.apply("keepassxc", "Passwords"),
+            Some("Private")
+        );
+        assert_eq!(
+            privacy_rules.apply("firefox", "Mozilla Firefox Private Browsing"),
+            Some("Private")
+        );
+        assert_eq!(privacy_rules.apply("firefox", "GitHub"), Some("firefox"));
+    }
+
+    #[test]
+    fn private_windows_can_be_skipped() {
+        let privacy_rules = privacy_rules(PrivacyAction::Skip);
+        assert_eq!(privacy_rules.apply("Bitwarden", ""), None);
+        assert_eq!(privacy_rules.apply("Code", "main.rs"), Some("Code"));
+    }
+
+    #[test]
+    fn no_rules_records_everything() {
+        let privacy_rules = PrivacyRules::default();
+        assert_eq!(
+            privacy_rules.apply("KeePassXC", "Incognito"),
+            Some("KeePassXC")
+        );
+    }
+
+    #[test]
+    fn parse_privacy_action() {
+        assert_eq!(PrivacyAction::parse("skip").unwrap(), PrivacyAction::Skip);
+        assert_eq!(PrivacyAction::parse("label").unwrap(), PrivacyAction::Label);
+        assert!(PrivacyAction::parse("hide").is_err());
+        assert!(parse_private_titles("(").is_err());
+    }
+}
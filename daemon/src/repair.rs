@@ -0,0 +1,200 @@
+use csv::{ByteRecord, ReaderBuilder, WriterBuilder};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::csv_writer::{write_atomically, Row, REWRITE_FILE_PREFIX};
+use crate::encryption::Cipher;
+use crate::schema::schema_version_line;
+
+// Rows that could not be read are moved to quarantine_<csv name> by the repair command.
+pub const QUARANTINE_FILE_PREFIX: &str = "quarantine_";
+// Copy made before a rewrite by older versions, before rewrites were atomic.
+const BACKUP_FILE_PREFIX: &str = "backup_";
+const CSV_HEADERS: [&str; 3] = ["timestamp", "application", "duration"];
+
+//Clean up after a rewrite that was interrupted by a crash or power loss, run at startup.
+pub fn recover_interrupted_rewrite(csv_name: &String) -> Result<(), Box<dyn Error>> {
+    // The rename never happened, so the csv is still the complete original
+    let rewrite_name = format!("{}{}", REWRITE_FILE_PREFIX, csv_name);
+    if Path::new(&rewrite_name).exists() {
+        println!("Removing {} left by an interrupted rewrite", rewrite_name);
+        fs::remove_file(&rewrite_name)?;
+    }
+    let backup_name = format!("{}{}", BACKUP_FILE_PREFIX, csv_name);
+    if Path::new(&backup_name).exists() {
+        if Path::new(csv_name).exists() {
+            println!("Removing {} left by an interrupted rewrite", backup_name);
+            fs::remove_file(&backup_name)?;
+        } else {
+            println!("Recovering {} from {}", csv_name, backup_name);
+            fs::rename(&backup_name, csv_name)?;
+        }
+    }
+    Ok(())
+}
+
+//Move the rows that cannot be read to the quarantine file, returning how many were moved.
+pub fn repair_csv(csv_name: &String, cipher: Option<&Cipher>) -> Result<usize, Box<dyn Error>> {
+    if !Path::new(csv_name).exists() {
+        return Ok(0);
+    }
+    // Encrypted records that fail to decrypt are quarantined as they are
+    let (csv, bad_records) = match cipher {
+        Some(cipher) => cipher.decrypt_file_lenient(csv_name)?,
+        None => (fs::read(csv_name)?, Vec::new()),
+    };
+
+    let mut rdr = ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(csv.as_slice());
+    let mut headers = ByteRecord::from(CSV_HEADERS.to_vec());
+    let mut good_rows = WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    let mut bad_rows = WriterBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_writer(Vec::new());
+    let mut quarantined = bad_records.len();
+    for (index, record) in rdr.byte_records().enumerate() {
+        let record = record?;
+        if index == 0 && record.iter().any(|field| field == b"timestamp") {
+            headers = record;
+            continue;
+        }
+        match record.deserialize::<Row>(Some(&headers)) {
+            Ok(_) => good_rows.write_byte_record(&record)?,
+            Err(_) => {
+                bad_rows.write_byte_record(&record)?;
+                quarantined += 1;
+            }
+        }
+    }
+    if quarantined == 0 {
+        return Ok(0);
+    }
+
+    // Quarantine first so that no row is lost if the rewrite fails
+    let quarantine_name = format!("{}{}", QUARANTINE_FILE_PREFIX, csv_name);
+    let bad_rows = bad_rows.into_inner()?;
+    match cipher {
+        Some(cipher) => {
+            cipher.append(&quarantine_name, &bad_rows)?;
+            let mut quarantine = OpenOptions::new().append(true).open(&quarantine_name)?;
+            for bad_record in &bad_records {
+                writeln!(quarantine, "{}", bad_record)?;
+            }
+            quarantine.sync_all()?;
+        }
+        None => {
+            let mut quarantine = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&quarantine_name)?;
+            quarantine.write_all(&bad_rows)?;
+            quarantine.sync_all()?;
+        }
+    }
+
+    let mut repaired_csv = WriterBuilder::new().from_writer(schema_version_line().into_bytes());
+    repaired_csv.write_byte_record(&headers)?;
+    let mut repaired_csv = repaired_csv.into_inner()?;
+    repaired_csv.extend(good_rows.into_inner()?);
+    match cipher {
+        Some(cipher) => cipher.write_file(csv_name, &repaired_csv)?,
+        None => write_atomically(csv_name, &repaired_csv)?,
+    }
+    Ok(quarantined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_writer::read_valid_rows;
+    use crate::encryption::KeySource;
+    use crate::test_helpers::tests::{setup, CSV_NAME};
+    use serial_test::serial;
+
+    const DAMAGED_CSV: &str =
+        "timestamp,application,duration\n1,Code,60\n2,Firefox\nnot a time,Slack,30\n3,Code,30\n";
+
+    #[test]
+    #[serial]
+    fn malformed_rows_are_quarantined() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        fs::write(&csv_name, DAMAGED_CSV).unwrap();
+        //Reads skip the malformed rows instead of failing
+        assert_eq!(read_valid_rows(&csv_name, None).unwrap().len(), 2);
+
+        assert_eq!(repair_csv(&csv_name, None).unwrap(), 2);
+        assert_eq!(
+            fs::read_to_string(&csv_name).unwrap(),
+            format!(
+                "{}timestamp,application,duration\n1,Code,60\n3,Code,30\n",
+                schema_version_line()
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{}{}", QUARANTINE_FILE_PREFIX, csv_name)).unwrap(),
+            "2,Firefox\nnot a time,Slack,30\n"
+        );
+        //Nothing left to repair
+        assert_eq!(repair_csv(&csv_name, None).unwrap(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn encrypted_rows_are_quarantined() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let cipher =
+            Cipher::open(&csv_name, &KeySource::Passphrase("passphrase".to_string())).unwrap();
+        cipher.append(&csv_name, DAMAGED_CSV.as_bytes()).unwrap();
+        // A record that no longer decrypts
+        let mut file = OpenOptions::new().append(true).open(&csv_name).unwrap();
+        writeln!(file, "AAAA").unwrap();
+
+        assert_eq!(repair_csv(&csv_name, Some(&cipher)).unwrap(), 3);
+        assert_eq!(
+            cipher.decrypt_file(&csv_name).unwrap(),
+            format!(
+                "{}timestamp,application,duration\n1,Code,60\n3,Code,30\n",
+                schema_version_line()
+            )
+            .into_bytes()
+        );
+        let quarantine_name = format!("{}{}", QUARANTINE_FILE_PREFIX, csv_name);
+        let (quarantined, bad_records) = cipher.decrypt_file_lenient(&quarantine_name).unwrap();
+        assert_eq!(quarantined, b"2,Firefox\nnot a time,Slack,30\n");
+        assert_eq!(bad_records, vec!["AAAA".to_string()]);
+    }
+
+    #[test]
+    #[serial]
+    fn interrupted_rewrites_are_cleaned_up() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let rewrite_name = format!("{}{}", REWRITE_FILE_PREFIX, csv_name);
+        let backup_name = format!("{}{}", BACKUP_FILE_PREFIX, csv_name);
+        fs::write(&csv_name, "timestamp,application,duration\n").unwrap();
+        fs::write(&rewrite_name, "timestamp,appl").unwrap();
+        fs::write(&backup_name, "timestamp,application,duration\n").unwrap();
+        recover_interrupted_rewrite(&csv_name).unwrap();
+        assert!(!Path::new(&rewrite_name).exists());
+        assert!(!Path::new(&backup_name).exists());
+
+        //Only the backup is left if the csv was removed before the rename
+        fs::remove_file(&csv_name).unwrap();
+        fs::write(&backup_name, "timestamp,application,duration\n1,Code,60\n").unwrap();
+        recover_interrupted_rewrite(&csv_name).unwrap();
+        assert_eq!(
+            fs::read_to_string(&csv_name).unwrap(),
+            "timestamp,application,duration\n1,Code,60\n"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use csv::{ReaderBuilder, WriterBuilder};
+
+use crate::csv_writer::write_atomically;
+use crate::encryption::Cipher;
+use crate::host::local_host_id;
+
+// Version of the columns written to the csv, bumped with a new migration whenever they change.
+pub const SCHEMA_VERSION: u32 = 5;
+// First line of the csv. Readers skip lines starting with '#', so older readers ignore it.
+const SCHEMA_VERSION_PREFIX: &str = "#schema_version=";
+// Files written before the version marker existed
+const UNVERSIONED_SCHEMA_VERSION: u32 = 1;
+
+type MigrateFn = fn(&[u8]) -> Result<Vec<u8>, Box<dyn Error>>;
+
+// Upgrades the csv, without its version line, from version - 1 to version.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    migrate: MigrateFn,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 2,
+        description: "add the schema version line",
+        migrate: |csv| Ok(csv.to_vec()),
+    },
+    Migration {
+        version: 3,
+        description: "add the host column, the existing rows were recorded on this machine",
+        migrate: |csv| add_column(csv, "host", local_host_id()),
+    },
+    Migration {
+        version: 4,
+        description: "add the utc_offset column, empty for the existing rows as it is not known",
+        migrate: |csv| add_column(csv, "utc_offset", ""),
+    },
+    Migration {
+        version: 5,
+        description: "add the category column, empty for the existing rows as no title rule was applied to them",
+        migrate: |csv| add_column(csv, "category", ""),
+    },
+];
+
+// Add a column with the same value in every row.
+fn add_column(csv: &[u8], header: &str, value: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(csv);
+    let mut wtr = WriterBuilder::new().flexible(true).from_writer(Vec::new());
+    for (index, record) in rdr.byte_records().enumerate() {
+        let mut record = record?;
+        match index {
+            0 => record.push_field(header.as_bytes()),
+            _ => record.push_field(value.as_bytes()),
+        }
+        wtr.write_byte_record(&record)?;
+    }
+    Ok(wtr.into_inner()?)
+}
+
+pub fn schema_version_line() -> String {
+    format!("{}{}\n", SCHEMA_VERSION_PREFIX, SCHEMA_VERSION)
+}
+
+// Split the csv into its schema version and the rest of the csv.
+fn split_schema_version(csv: &[u8]) -> Result<(u32, &[u8]), Box<dyn Error>> {
+    let Some(rest) = csv.strip_prefix(SCHEMA_VERSION_PREFIX.as_bytes()) else {
+        return Ok((UNVERSIONED_SCHEMA_VERSION, csv));
+    };
+    let line_end = rest
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .unwrap_or(rest.len());
+    let version = std::str::from_utf8(&rest[..line_end])?.trim().parse()?;
+    Ok((version, rest.get(line_end + 1..).unwrap_or_default()))
+}
+
+// The schema version of a csv, which may be newer than SCHEMA_VERSION.
+pub fn schema_version(csv: &[u8]) -> Result<u32, Box<dyn Error>> {
+    Ok(split_schema_version(csv)?.0)
+}
+
+//Upgrade the csv to SCHEMA_VERSION in place, keeping a copy of the old file. Returns the version it was upgraded from.
+pub fn migrate_csv(
+    csv_name: &String,
+    cipher: Option<&Cipher>,
+) -> Result<Option<u32>, Box<dyn Error>> {
+    if !Path::new(csv_name).exists() || fs::metadata(csv_name)?.len() == 0 {
+        return Ok(None);
+    }
+    let csv = match cipher {
+        Some(cipher) => cipher.decrypt_file(csv_name)?,
+        None => fs::read(csv_name)?,
+    };
+    let (version, csv) = split_schema_version(&csv)?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "{} has schema version {}, but this version of screen_timed only supports up to {}",
+            csv_name, version, SCHEMA_VERSION
+        )
+        .into());
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    let backup_name = format!("v{}_backup_{}", version, csv_name);
+    fs::copy(csv_name, &backup_name)?;
+    println!("Backed up {} to {}", csv_name, backup_name);
+    let mut csv = csv.to_vec();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version)
+    {
+        println!(
+            "Migrating {} to schema version {}: {}",
+            csv_name, migration.version, migration.description
+        );
+        csv = (migration.migrate)(&csv)?;
+    }
+    let mut migrated_csv = schema_version_line().into_bytes();
+    migrated_csv.extend(csv);
+    match cipher {
+        Some(cipher) => cipher.write_file(csv_name, &migrated_csv)?,
+        None => write_atomically(csv_name, &migrated_csv)?,
+    }
+    Ok(Some(version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_writer::read_valid_rows;
+    use crate::test_helpers::tests::{setup, CSV_NAME};
+    use serial_test::serial;
+
+    #[test]
+    fn migrations_reach_the_current_version() {
+        let versions: Vec<u32> = MIGRATIONS
+            .iter()
+            .map(|migration| migration.version)
+            .collect();
+        let expected: Vec<u32> = (UNVERSIONED_SCHEMA_VERSION + 1..=SCHEMA_VERSION).collect();
+        assert_eq!(versions, expected);
+    }
+
+    #[test]
+    #[serial]
+    fn unversioned_csv_is_upgraded_with_a_backup() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let csv = "timestamp,application,duration\n1,Code,60\n";
+        fs::write(&csv_name, csv).unwrap();
+
+        assert_eq!(migrate_csv(&csv_name, None).unwrap(), Some(1));
+        assert_eq!(
+            fs::read_to_string(&csv_name).unwrap(),
+            format!(
+                "{}timestamp,application,duration,host,utc_offset,category\n1,Code,60,{},,\n",
+                schema_version_line(),
+                local_host_id()
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(format!("v1_backup_{}", csv_name)).unwrap(),
+            csv
+        );
+        assert_eq!(read_valid_rows(&csv_name, None).unwrap().len(), 1);
+        //Already up to date
+        assert_eq!(migrate_csv(&csv_name, None).unwrap(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn newer_schema_is_refused_but_readable() {
+        let (_temp_dir, _) = setup();
+        let csv_name = CSV_NAME.to_string();
+        let csv = format!(
+            "{}{}\ntimestamp,application,duration,pid\n1,Code,60,4242\n",
+            SCHEMA_VERSION_PREFIX,
+            SCHEMA_VERSION + 1
+        );
+        fs::write(&csv_name, &csv).unwrap();
+
+        assert!(migrate_csv(&csv_name, None).is_err());
+        assert_eq!(fs::read_to_string(&csv_name).unwrap(), csv);
+        //Unknown columns are ignored when reading
+        assert_eq!(read_valid_rows(&csv_name, None).unwrap().len(), 1);
+    }
+}
@@ -2,29 +2,106 @@ use std::{collections::HashMap, time::Duration};
 
 use active_win_pos_rs::get_active_window;
 
-use crate::notification::exit_with_error_notification;
+use crate::aliases::AliasRules;
+use crate::categories::{CategorizedTimes, TitleCategoryRules};
+use crate::privacy::{PrivacyRules, PRIVATE_APPLICATION};
 
-//Get the current active window and update the current app's time.
-pub fn update_current_app(program_times: &mut HashMap<String, Duration>) {
+// The window in focus when it was sampled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FocusedWindow {
+    // No active window
+    None,
+    // Skipped by the privacy rules, still screen use but not recorded
+    Private,
+    // The recorded application name, after aliases
+    App(String),
+}
+
+impl FocusedWindow {
+    pub fn is_active(&self) -> bool {
+        *self != FocusedWindow::None
+    }
+}
+
+// Get the idle time from xprintidle output, which is in milliseconds.
+fn parse_idle_time(xprintidle_output: &str) -> Option<Duration> {
+    let milliseconds = xprintidle_output.trim().parse().ok()?;
+    Some(Duration::from_millis(milliseconds))
+}
+
+//How long since the last keyboard or mouse input, from the X screensaver extension through xprintidle.
+//A focused window alone does not mean someone is at the screen.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn input_idle_time() -> Option<Duration> {
+    use std::process::Command;
+    use std::sync::Once;
+
+    static REPORT_MISSING: Once = Once::new();
+    match Command::new("xprintidle").output() {
+        Ok(output) => parse_idle_time(&String::from_utf8_lossy(&output.stdout)),
+        Err(err) => {
+            // Sampled every tick, so only reported once
+            REPORT_MISSING.call_once(|| {
+                eprintln!("Error getting the idle time with xprintidle: {}", err);
+            });
+            None
+        }
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn input_idle_time() -> Option<Duration> {
+    None
+}
+
+//Get the current active window and update the current app's time, under its alias if it has one.
+//Private windows are labelled or skipped first, so their names are never recorded.
+//Time in windows whose title matches a category rule is also added to categorized_times.
+pub fn update_current_app(
+    program_times: &mut HashMap<String, Duration>,
+    categorized_times: &mut CategorizedTimes,
+    privacy_rules: &PrivacyRules,
+    alias_rules: &AliasRules,
+    title_category_rules: &TitleCategoryRules,
+) -> FocusedWindow {
     match get_active_window() {
         Ok(active_window) => {
-            let app_name = &active_window.app_name;
-            if program_times.contains_key(app_name) {
-                let existing_time = match program_times.get(app_name) {
-                    Some(time) => time,
-                    None => {
-                        exit_with_error_notification("Error getting existing time");
-                    }
-                };
-                let new_time = *existing_time + Duration::from_secs(1);
-                program_times.insert(app_name.to_string(), new_time);
-            } else {
-                program_times.insert(app_name.to_string(), Duration::from_secs(1));
+            let app_name = match privacy_rules.apply(&active_window.app_name, &active_window.title)
+            {
+                Some(app_name) => alias_rules.resolve(app_name),
+                None => return FocusedWindow::Private,
+            };
+            *program_times.entry(app_name.to_string()).or_default() += Duration::from_secs(1);
+            // The title of a private window is not used for anything
+            let category = match app_name {
+                PRIVATE_APPLICATION => None,
+                app_name => title_category_rules.resolve(app_name, &active_window.title),
+            };
+            if let Some(category) = category {
+                *categorized_times
+                    .entry((app_name.to_string(), category.to_string()))
+                    .or_default() += Duration::from_secs(1);
             }
+            FocusedWindow::App(app_name.to_string())
         }
         Err(()) => {
             //Could happen when switching windows.
             println!("error occurred while getting the active window");
+            FocusedWindow::None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_xprintidle_output() {
+        assert_eq!(
+            parse_idle_time("61250\n"),
+            Some(Duration::from_millis(61250))
+        );
+        assert_eq!(parse_idle_time("couldn't open display"), None);
+    }
+}
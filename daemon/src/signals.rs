@@ -2,11 +2,11 @@ use std::sync::{atomic::AtomicBool, Arc};
 
 use signal_hook::consts::signal::*;
 use signal_hook::flag as signal_flag;
-use std::error::Error;
+use std::io;
 
-use crate::notification::exit_with_error_notification;
+use crate::error::DaemonError;
 
-fn attempt_to_register_signals(program_finished: &Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+fn attempt_to_register_signals(program_finished: &Arc<AtomicBool>) -> Result<(), io::Error> {
     signal_flag::register(SIGTERM, Arc::clone(program_finished))?;
     signal_flag::register(SIGINT, Arc::clone(program_finished))?;
     signal_flag::register(SIGUSR1, Arc::clone(program_finished))?;
@@ -14,10 +14,6 @@ fn attempt_to_register_signals(program_finished: &Arc<AtomicBool>) -> Result<(),
     Ok(())
 }
 
-pub fn register_os_signals(program_finished: &Arc<AtomicBool>) {
-    if let Err(err) = attempt_to_register_signals(&program_finished) {
-        exit_with_error_notification(
-            format!("Exiting: Error registering signals: {}", err).as_str(),
-        );
-    }
+pub fn register_os_signals(program_finished: &Arc<AtomicBool>) -> Result<(), DaemonError> {
+    attempt_to_register_signals(program_finished).map_err(DaemonError::Signals)
 }
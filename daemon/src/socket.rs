@@ -1,97 +1,148 @@
-use crate::csv_writer::{get_curr_path_to_csv, remove_old_data};
-use crate::notification::exit_with_error_notification;
-use crate::{ALERT_SCREEN_ENV_VAR, SCREEN_DATA_CSV_PATH};
+use crate::aliases::format_app_totals;
+use crate::backup::{BackupRequest, BackupRequestSender};
+use crate::csv_writer::{get_curr_path_to_csv, read_app_totals, remove_old_data};
+use crate::digest::{build_digest, format_digest, DigestPeriod};
+use crate::encryption::{Cipher, KeySource};
+use crate::error::DaemonError;
+use crate::export::{export_data, ExportRequest};
+use crate::focus::{
+    format_focus_sessions, format_focus_status, parse_focus_request, read_focus_sessions,
+    FocusState, FOCUS_SESSIONS_FILE_NAME,
+};
+use crate::goals::{format_goal_status, GoalStatus};
+use crate::limits::{day_of, format_limit_usage, LimitUsage};
+use crate::pause::{parse_pause_request, PauseState, PAUSED_APPLICATION};
+use crate::{ALERT_SCREEN_ENV_VAR, DAY_START_HOUR_ENV_VAR, SCREEN_DATA_CSV_PATH};
+use chrono::Local;
 use socket2::{Domain, Socket, Type};
+use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
 use std::io::{self, Read, Write};
 use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn create_socket(socket_addr: &String) -> (Socket, TcpListener) {
-    let socket = match Socket::new(Domain::IPV6, Type::STREAM, None) {
-        Ok(socket) => socket,
+// State shared between the run() loop and the socket listener.
+#[derive(Clone, Default)]
+pub struct SocketState {
+    // When true, the run() loop updates the csv
+    pub update_csv: Arc<AtomicBool>,
+    pub pause_state: PauseState,
+    pub focus_state: FocusState,
+    pub limit_usage: Arc<Mutex<Vec<LimitUsage>>>,
+    // Today's progress on each goal and its streaks
+    pub goal_status: Arc<Mutex<Vec<GoalStatus>>>,
+    // Time that has not been written to the csv yet
+    pub program_times: Arc<Mutex<HashMap<String, Duration>>>,
+    pub alert_screen_time: u64,
+    pub day_start_hour: u32,
+    // Set if the data file is encrypted
+    pub cipher: Option<Cipher>,
+    pub key_source: Option<KeySource>,
+    // Backups, restores and repairs are handed to the run() loop, so that nothing is written during them
+    pub backup_requests: Option<BackupRequestSender>,
+}
+
+// How long a client waits for the run() loop to take a backup or restore one.
+const BACKUP_REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Hand the request to the run() loop and wait for its reply.
+fn send_backup_request(socket_state: &SocketState, request: BackupRequest) -> String {
+    let Some(backup_requests) = &socket_state.backup_requests else {
+        eprintln!("Backups are not handled");
+        return "Failure".to_string();
+    };
+    let (reply_sender, reply_receiver) = mpsc::channel();
+    if let Err(err) = backup_requests.send((request, reply_sender)) {
+        eprintln!("Error sending backup request: {}", err);
+        return "Failure".to_string();
+    }
+    match reply_receiver.recv_timeout(BACKUP_REPLY_TIMEOUT) {
+        Ok(reply) => reply,
         Err(err) => {
-            let error_message = format!("Error creating socket: {}", err);
-            exit_with_error_notification(error_message.as_str());
+            eprintln!("Error waiting for backup: {}", err);
+            "Failure".to_string()
         }
-    };
+    }
+}
+
+pub fn create_socket(socket_addr: &String) -> Result<(Socket, TcpListener), DaemonError> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, None)
+        .map_err(|err| DaemonError::socket("Error creating socket", err))?;
 
     let address: SocketAddr = match socket_addr.parse() {
         Ok(address) => address,
         Err(err) => {
-            let error_message = format!("Error parsing socket address: {}", err);
-            exit_with_error_notification(error_message.as_str());
+            return Err(DaemonError::socket(
+                "Error parsing socket address",
+                io::Error::new(io::ErrorKind::InvalidInput, err),
+            ));
         }
     };
     let address = address.into();
     if let Err(err) = socket.bind(&address) {
-        let error_message = format!(
-            "Error binding socket to address: {}, err: {}",
-            &socket_addr, err
-        );
-        exit_with_error_notification(error_message.as_str());
-    }
-    if let Err(err) = socket.listen(128) {
-        let error_message = format!("Error listening on socket: {}", err);
-        exit_with_error_notification(error_message.as_str());
+        let context = format!("Error binding socket to address: {}", &socket_addr);
+        return Err(DaemonError::socket(&context, err));
     }
+    socket
+        .listen(128)
+        .map_err(|err| DaemonError::socket("Error listening on socket", err))?;
     println!("Listening on {}", &socket_addr);
 
-    let listener = match socket.try_clone() {
-        Ok(cloned_socket) => cloned_socket.into(),
-        Err(err) => {
-            let error_message = format!("Error cloning socket: {}", err);
-            exit_with_error_notification(error_message.as_str());
-        }
-    };
+    let listener = socket
+        .try_clone()
+        .map_err(|err| DaemonError::socket("Error cloning socket", err))?
+        .into();
 
-    (socket, listener)
+    Ok((socket, listener))
 }
 pub fn close_socket(socket: Socket) -> io::Result<()> {
     println!("Closing socket...");
     socket.shutdown(Shutdown::Both)
 }
 
-pub fn connect_to_socket(socket_addr: String) -> TcpStream {
-    let stream = match TcpStream::connect(socket_addr) {
-        Ok(stream) => stream,
-        Err(err) => {
-            let error_message = format!("Error connecting to socket: {}", err);
-            exit_with_error_notification(error_message.as_str());
-        }
-    };
-    stream
+pub fn connect_to_socket(socket_addr: String) -> Result<TcpStream, DaemonError> {
+    TcpStream::connect(socket_addr)
+        .map_err(|err| DaemonError::socket("Error connecting to socket", err))
+}
+
+// Send a message to the daemon's socket and return the response.
+pub fn send_message_to_socket(socket_addr: &str, message: &str) -> Result<String, Box<dyn Error>> {
+    let mut stream = TcpStream::connect(socket_addr)?;
+    stream.write_all(message.as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut received = String::new();
+    stream.read_to_string(&mut received)?;
+    Ok(received)
 }
 // Send the terminating stream to close socket connection
 // When the listen_for_connection loop iterates as there is another stream, it will encounter the changed child_program_finished and break the loop.
-pub fn send_terminating_mssg(socket_path: String) {
-    let mut stream = connect_to_socket(socket_path);
-    match stream.write_all(b"Terminating Stream") {
-        Ok(()) => {
-            println!("Terminating stream sent.");
-        }
-        Err(err) => {
-            let error_message = format!("Error sending terminating stream: {}", err);
-            exit_with_error_notification(error_message.as_str());
-        }
-    }
+pub fn send_terminating_mssg(socket_path: String) -> Result<(), DaemonError> {
+    let mut stream = connect_to_socket(socket_path)?;
+    stream
+        .write_all(b"Terminating Stream")
+        .map_err(|err| DaemonError::socket("Error sending terminating stream", err))?;
+    println!("Terminating stream sent.");
+    // The listener may have already closed the connection, so this is not fatal.
     match stream.shutdown(Shutdown::Both) {
         Ok(()) => {
             println!("Stream successfully shutdown.");
         }
         Err(err) => {
-            let error_message = format!("Error shutting down stream: {}", err);
-            exit_with_error_notification(error_message.as_str());
+            eprintln!("Error shutting down stream: {}", err);
         }
     }
+    Ok(())
 }
 pub fn listen_for_connections(
     listener: &TcpListener,
     terminating_arc: &Arc<AtomicBool>,
-    update_csv: &Arc<AtomicBool>,
-    alert_screen_time: u64,
-) -> Result<(), Box<dyn Error>> {
+    socket_state: &SocketState,
+) {
     for stream in listener.incoming() {
         if terminating_arc.load(Ordering::Relaxed) {
             break;
@@ -99,7 +150,10 @@ pub fn listen_for_connections(
         match stream {
             Ok(stream) => {
                 println!("new client!");
-                handle_client(stream, update_csv, alert_screen_time)?;
+                // A failing client should not stop the listener
+                if let Err(err) = handle_client(stream, socket_state) {
+                    eprintln!("{}", DaemonError::SocketClient(err.to_string()));
+                }
             }
             Err(err) => {
                 println!("Error in listen_for_connections: {}", err);
@@ -107,19 +161,35 @@ pub fn listen_for_connections(
             }
         }
     }
-    Ok(())
 }
-fn handle_client(
-    mut stream: TcpStream,
-    update_csv: &Arc<AtomicBool>,
-    alert_screen_time: u64,
-) -> Result<(), Box<dyn Error>> {
+// Tell the desktop app how to decrypt the data file, the passphrase itself is never sent.
+fn format_key_source(key_source: Option<&KeySource>) -> String {
+    match key_source {
+        None => "None".to_string(),
+        Some(KeySource::Keyfile(keyfile)) => {
+            let keyfile = fs::canonicalize(keyfile).unwrap_or(keyfile.clone());
+            format!("Keyfile {}", keyfile.display())
+        }
+        Some(KeySource::Passphrase(_)) => "Passphrase".to_string(),
+    }
+}
+
+fn handle_client(mut stream: TcpStream, socket_state: &SocketState) -> Result<(), Box<dyn Error>> {
     let mut received = String::new();
     stream.read_to_string(&mut received)?;
     let update_csv_str = String::from("UPDATE_CSV");
     let path_str = String::from("PATH");
     let health_check_str = String::from("HEALTH_CHECK");
+    let resume_str = String::from("RESUME");
+    let limits_str = String::from("LIMITS");
+    let goals_str = String::from("GOALS");
+    let goals_json_str = String::from("GOALS JSON");
+    let names_str = String::from("NAMES");
+    let encryption_str = String::from("ENCRYPTION");
+    let repair_str = String::from("REPAIR");
+    let backup_str = String::from("BACKUP");
     let alert_screen_env_var_str = ALERT_SCREEN_ENV_VAR.to_string();
+    let day_start_hour_env_var_str = DAY_START_HOUR_ENV_VAR.to_string();
     match received {
         s if s == health_check_str => {
             println!("Received HEALTH_CHECK request!");
@@ -128,20 +198,268 @@ fn handle_client(
         }
         s if s == update_csv_str => {
             println!("Received UPDATE_CSV request!");
-            update_csv.store(true, Ordering::Relaxed);
+            socket_state.update_csv.store(true, Ordering::Relaxed);
             stream.write_all(b"Success")?;
             Ok(())
         }
         s if s == path_str => {
             println!("Received PATH request!");
-            let curr_path = get_curr_path_to_csv(&SCREEN_DATA_CSV_PATH.to_string());
+            let curr_path = get_curr_path_to_csv(&SCREEN_DATA_CSV_PATH.to_string())?;
             stream.write_all(curr_path.as_bytes())?;
             println!("Sent path! - {}", curr_path);
             Ok(())
         }
         s if s == alert_screen_env_var_str => {
             println!("Received alert screen request!");
-            stream.write_all(alert_screen_time.to_string().as_bytes())?;
+            stream.write_all(socket_state.alert_screen_time.to_string().as_bytes())?;
+            Ok(())
+        }
+        s if s == day_start_hour_env_var_str => {
+            println!("Received day start hour request!");
+            stream.write_all(socket_state.day_start_hour.to_string().as_bytes())?;
+            Ok(())
+        }
+        s if s == resume_str => {
+            println!("Received RESUME request!");
+            socket_state.pause_state.resume();
+            stream.write_all(b"Success")?;
+            Ok(())
+        }
+        s if s == limits_str => {
+            println!("Received LIMITS request!");
+            let limit_usage = match socket_state.limit_usage.lock() {
+                Ok(limit_usage) => format_limit_usage(&limit_usage),
+                Err(err) => {
+                    eprintln!("Error locking limit usage: {}", err);
+                    stream.write_all(b"Failure")?;
+                    return Ok(());
+                }
+            };
+            stream.write_all(limit_usage.as_bytes())?;
+            Ok(())
+        }
+        s if s == goals_str || s == goals_json_str => {
+            println!("Received GOALS request!");
+            let goal_status = match socket_state.goal_status.lock() {
+                Ok(goal_status) => goal_status.clone(),
+                Err(err) => {
+                    eprintln!("Error locking goal status: {}", err);
+                    stream.write_all(b"Failure")?;
+                    return Ok(());
+                }
+            };
+            if s == goals_str {
+                stream.write_all(format_goal_status(&goal_status).as_bytes())?;
+                return Ok(());
+            }
+            match serde_json::to_string(&goal_status) {
+                Ok(goal_status) => stream.write_all(goal_status.as_bytes())?,
+                Err(err) => {
+                    eprintln!("Error serializing goal status: {}", err);
+                    stream.write_all(b"Failure")?;
+                }
+            }
+            Ok(())
+        }
+        s if s == names_str => {
+            println!("Received NAMES request!");
+            let mut app_totals = match read_app_totals(
+                &SCREEN_DATA_CSV_PATH.to_string(),
+                socket_state.cipher.as_ref(),
+                UNIX_EPOCH,
+                SystemTime::now() + Duration::from_secs(60),
+            ) {
+                Ok(app_totals) => app_totals,
+                Err(err) => {
+                    eprintln!("Error reading application names: {}", err);
+                    stream.write_all(b"Failure")?;
+                    return Ok(());
+                }
+            };
+            if let Ok(program_times) = socket_state.program_times.lock() {
+                for (program_name, duration) in program_times.iter() {
+                    if program_name != PAUSED_APPLICATION {
+                        *app_totals.entry(program_name.to_string()).or_default() += *duration;
+                    }
+                }
+            }
+            stream.write_all(format_app_totals(&app_totals).as_bytes())?;
+            Ok(())
+        }
+        s if s == encryption_str => {
+            println!("Received ENCRYPTION request!");
+            stream.write_all(format_key_source(socket_state.key_source.as_ref()).as_bytes())?;
+            Ok(())
+        }
+        s if s == repair_str => {
+            println!("Received REPAIR request!");
+            let reply = send_backup_request(socket_state, BackupRequest::Repair);
+            stream.write_all(reply.as_bytes())?;
+            Ok(())
+        }
+        s if s == backup_str => {
+            println!("Received BACKUP request!");
+            let reply = send_backup_request(socket_state, BackupRequest::Backup);
+            stream.write_all(reply.as_bytes())?;
+            Ok(())
+        }
+        s if s.starts_with("RESTORE ") => {
+            println!("Received RESTORE request!");
+            let snapshot = PathBuf::from(s["RESTORE ".len()..].trim());
+            let reply = send_backup_request(socket_state, BackupRequest::Restore(snapshot));
+            stream.write_all(reply.as_bytes())?;
+            Ok(())
+        }
+        s if s.starts_with("EXPORT") => {
+            println!("Received EXPORT request!");
+            let export_request = match ExportRequest::parse(&s["EXPORT".len()..]) {
+                Ok(export_request) => export_request,
+                Err(err) => {
+                    eprintln!("Error parsing export request: {}", err);
+                    stream.write_all(b"Failure")?;
+                    return Ok(());
+                }
+            };
+            let program_times = match socket_state.program_times.lock() {
+                Ok(program_times) => program_times.clone(),
+                Err(err) => {
+                    eprintln!("Error locking program times: {}", err);
+                    HashMap::new()
+                }
+            };
+            match export_data(
+                &export_request,
+                &SCREEN_DATA_CSV_PATH.to_string(),
+                socket_state.cipher.as_ref(),
+                &program_times,
+                socket_state.day_start_hour,
+            ) {
+                Ok(exported) => stream.write_all(exported.as_bytes())?,
+                Err(err) => {
+                    eprintln!("Error exporting data: {}", err);
+                    stream.write_all(b"Failure")?;
+                }
+            }
+            Ok(())
+        }
+        s if s.starts_with("FOCUS START ") => {
+            println!("Received FOCUS START request!");
+            let started = parse_focus_request(&s["FOCUS START ".len()..]).and_then(
+                |(duration, allowed_apps)| {
+                    socket_state
+                        .focus_state
+                        .start(duration, allowed_apps, SystemTime::now())
+                },
+            );
+            match started {
+                Ok(()) => stream.write_all(b"Success")?,
+                Err(err) => {
+                    eprintln!("Error starting focus session: {}", err);
+                    stream.write_all(b"Failure")?;
+                }
+            }
+            Ok(())
+        }
+        s if s == "FOCUS STOP" => {
+            println!("Received FOCUS STOP request!");
+            match socket_state.focus_state.stop() {
+                Ok(()) => stream.write_all(b"Success")?,
+                Err(err) => {
+                    eprintln!("Error stopping focus session: {}", err);
+                    stream.write_all(b"Failure")?;
+                }
+            }
+            Ok(())
+        }
+        s if s == "FOCUS" || s == "FOCUS JSON" => {
+            println!("Received FOCUS request!");
+            let focus_status = socket_state.focus_state.status(SystemTime::now());
+            if s == "FOCUS" {
+                stream.write_all(format_focus_status(focus_status.as_ref()).as_bytes())?;
+                return Ok(());
+            }
+            match serde_json::to_string(&focus_status) {
+                Ok(focus_status) => stream.write_all(focus_status.as_bytes())?,
+                Err(err) => {
+                    eprintln!("Error serializing focus status: {}", err);
+                    stream.write_all(b"Failure")?;
+                }
+            }
+            Ok(())
+        }
+        s if s == "FOCUS SESSIONS" || s == "FOCUS SESSIONS JSON" => {
+            println!("Received FOCUS SESSIONS request!");
+            let sessions = match read_focus_sessions(
+                &FOCUS_SESSIONS_FILE_NAME.to_string(),
+                socket_state.cipher.as_ref(),
+            ) {
+                Ok(sessions) => sessions,
+                Err(err) => {
+                    eprintln!("Error reading focus sessions: {}", err);
+                    stream.write_all(b"Failure")?;
+                    return Ok(());
+                }
+            };
+            if s == "FOCUS SESSIONS" {
+                stream.write_all(format_focus_sessions(&sessions).as_bytes())?;
+                return Ok(());
+            }
+            match serde_json::to_string(&sessions) {
+                Ok(sessions) => stream.write_all(sessions.as_bytes())?,
+                Err(err) => {
+                    eprintln!("Error serializing focus sessions: {}", err);
+                    stream.write_all(b"Failure")?;
+                }
+            }
+            Ok(())
+        }
+        s if s.starts_with("DIGEST") => {
+            println!("Received DIGEST request!");
+            let period = match s["DIGEST".len()..].trim() {
+                "" => DigestPeriod::Daily,
+                "WEEKLY" => DigestPeriod::Weekly,
+                _ => {
+                    eprintln!("Unknown digest period: {}", s);
+                    stream.write_all(b"Failure")?;
+                    return Ok(());
+                }
+            };
+            let program_times = match socket_state.program_times.lock() {
+                Ok(program_times) => program_times.clone(),
+                Err(err) => {
+                    eprintln!("Error locking program times: {}", err);
+                    stream.write_all(b"Failure")?;
+                    return Ok(());
+                }
+            };
+            match build_digest(
+                period,
+                &SCREEN_DATA_CSV_PATH.to_string(),
+                socket_state.cipher.as_ref(),
+                day_of(Local::now(), socket_state.day_start_hour),
+                socket_state.day_start_hour,
+                &program_times,
+            ) {
+                Ok(digest) => stream.write_all(format_digest(&digest).as_bytes())?,
+                Err(err) => {
+                    eprintln!("Error building digest: {}", err);
+                    stream.write_all(b"Failure")?;
+                }
+            }
+            Ok(())
+        }
+        s if s.starts_with("PAUSE") => {
+            println!("Received PAUSE request!");
+            match parse_pause_request(&s) {
+                Ok(duration) => {
+                    socket_state.pause_state.pause(duration);
+                    stream.write_all(b"Success")?;
+                }
+                Err(err) => {
+                    eprintln!("Error parsing pause request: {}", err);
+                    stream.write_all(b"Failure")?;
+                }
+            }
             Ok(())
         }
         s if (received.len() >= 7) && (&received[..6] == "DELETE") => {
@@ -155,7 +473,11 @@ fn handle_client(
                     return Ok(());
                 }
             };
-            match remove_old_data(months, &SCREEN_DATA_CSV_PATH.to_string()) {
+            match remove_old_data(
+                months,
+                &SCREEN_DATA_CSV_PATH.to_string(),
+                socket_state.cipher.as_ref(),
+            ) {
                 Ok(()) => {
                     stream.write_all(b"Success")?;
                     println!("Successfully removed old data!");
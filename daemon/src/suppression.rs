@@ -0,0 +1,332 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{Local, NaiveTime};
+
+use crate::notification::{Alert, AlertKind, Notifier};
+
+// A daily period without alerts, such as "22:00-07:00".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn parse(quiet_hours: &str) -> Result<QuietHours, &'static str> {
+        let (start, end) = match quiet_hours.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => return Err("Quiet hours should look like HH:MM-HH:MM"),
+        };
+        let start = NaiveTime::parse_from_str(start, "%H:%M")
+            .map_err(|_| "Quiet hours start should be a time such as 22:00")?;
+        let end = NaiveTime::parse_from_str(end, "%H:%M")
+            .map_err(|_| "Quiet hours end should be a time such as 07:00")?;
+        if start == end {
+            return Err("Quiet hours start and end should be different");
+        }
+        Ok(QuietHours { start, end })
+    }
+
+    //Quiet hours may run past midnight, in which case end is before start.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SuppressionRules {
+    pub quiet_hours: Option<QuietHours>,
+    // Suppress alerts while a fullscreen window is focused, e.g. a presentation
+    pub suppress_fullscreen: bool,
+}
+
+impl SuppressionRules {
+    pub fn is_enabled(&self) -> bool {
+        self.quiet_hours.is_some() || self.suppress_fullscreen
+    }
+
+    //is_fullscreen is only called when needed, as checking it runs a command.
+    pub fn is_suppressed(&self, time: NaiveTime, is_fullscreen: impl Fn() -> bool) -> bool {
+        if let Some(quiet_hours) = self.quiet_hours {
+            if quiet_hours.contains(time) {
+                return true;
+            }
+        }
+        self.suppress_fullscreen && is_fullscreen()
+    }
+}
+
+// Holds back alerts while they are suppressed and sends them on as one alert afterwards.
+// Error alerts are never held back, as a fatal error stops the daemon.
+pub struct SuppressingNotifier {
+    notifier: Arc<dyn Notifier>,
+    rules: SuppressionRules,
+    deferred: Mutex<Vec<Alert>>,
+}
+
+impl SuppressingNotifier {
+    pub fn new(notifier: Arc<dyn Notifier>, rules: SuppressionRules) -> SuppressingNotifier {
+        SuppressingNotifier {
+            notifier,
+            rules,
+            deferred: Mutex::new(Vec::new()),
+        }
+    }
+
+    //Called every tick, sends the deferred alerts once they are no longer suppressed.
+    pub fn release_deferred(&self) {
+        self.release_deferred_at(Local::now().time(), is_fullscreen_focused);
+    }
+
+    fn release_deferred_at(&self, time: NaiveTime, is_fullscreen: impl Fn() -> bool) {
+        let mut deferred = match self.deferred.lock() {
+            Ok(deferred) => deferred,
+            Err(err) => {
+                eprintln!("Error locking deferred alerts: {}", err);
+                return;
+            }
+        };
+        if deferred.is_empty() || self.rules.is_suppressed(time, is_fullscreen) {
+            return;
+        }
+        if let Some(alert) = coalesce_alerts(&deferred) {
+            self.notifier.notify(&alert);
+        }
+        deferred.clear();
+    }
+
+    fn notify_at(&self, alert: &Alert, time: NaiveTime, is_fullscreen: impl Fn() -> bool) {
+        if alert.kind == AlertKind::Error || !self.rules.is_suppressed(time, is_fullscreen) {
+            self.notifier.notify(alert);
+            return;
+        }
+        println!("Deferring alert: {}", alert.summary);
+        match self.deferred.lock() {
+            Ok(mut deferred) => {
+                // Only the latest break reminder is still relevant
+                if alert.kind == AlertKind::BreakReminder {
+                    deferred
+                        .retain(|deferred_alert| deferred_alert.kind != AlertKind::BreakReminder);
+                }
+                deferred.push(alert.clone());
+            }
+            Err(err) => eprintln!("Error locking deferred alerts: {}", err),
+        }
+    }
+}
+
+impl Notifier for SuppressingNotifier {
+    fn notify(&self, alert: &Alert) {
+        if !self.rules.is_enabled() {
+            self.notifier.notify(alert);
+            return;
+        }
+        self.notify_at(alert, Local::now().time(), is_fullscreen_focused);
+    }
+}
+
+// A single deferred alert is sent as it is, several are combined into one.
+fn coalesce_alerts(alerts: &[Alert]) -> Option<Alert> {
+    match alerts {
+        [] => None,
+        [alert] => Some(alert.clone()),
+        _ => Some(Alert {
+            kind: AlertKind::Summary,
+            summary: "Alerts While You Were Busy".to_string(),
+            body: alerts
+                .iter()
+                .map(|alert| alert.body.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n"),
+        }),
+    }
+}
+
+// Get the window id from xprop output such as "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007".
+fn parse_active_window_id(xprop_output: &str) -> Option<&str> {
+    let window_id = xprop_output.rsplit_once('#')?.1.trim();
+    match window_id {
+        "" | "0x0" => None,
+        window_id => Some(window_id),
+    }
+}
+
+//Uses the EWMH window state of the active window, so only works on X11.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn is_fullscreen_focused() -> bool {
+    use std::process::Command;
+
+    let active_window = match Command::new("xprop")
+        .args(["-root", "_NET_ACTIVE_WINDOW"])
+        .output()
+    {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+        Err(err) => {
+            eprintln!("Error getting the active window with xprop: {}", err);
+            return false;
+        }
+    };
+    let window_id = match parse_active_window_id(&active_window) {
+        Some(window_id) => window_id,
+        None => return false,
+    };
+    match Command::new("xprop")
+        .args(["-id", window_id, "_NET_WM_STATE"])
+        .output()
+    {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).contains("_NET_WM_STATE_FULLSCREEN"),
+        Err(err) => {
+            eprintln!("Error getting the window state with xprop: {}", err);
+            false
+        }
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn is_fullscreen_focused() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::LimitUsage;
+    use crate::notification::{
+        daily_limit_alert, error_alert, screen_time_alert, RecordingNotifier,
+    };
+    use std::time::Duration;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn quiet_notifier(rules: SuppressionRules) -> (Arc<RecordingNotifier>, SuppressingNotifier) {
+        let recording_notifier = Arc::new(RecordingNotifier::default());
+        let notifier = SuppressingNotifier::new(recording_notifier.clone(), rules);
+        (recording_notifier, notifier)
+    }
+
+    fn limit_alert(name: &str, threshold: u64) -> Alert {
+        let usage = LimitUsage {
+            name: name.to_string(),
+            used: Duration::from_secs(60 * 60),
+            minutes: 60,
+        };
+        daily_limit_alert(&usage, threshold)
+    }
+
+    #[test]
+    fn parse_quiet_hours() {
+        assert_eq!(
+            QuietHours::parse("22:00 - 07:30").unwrap(),
+            QuietHours {
+                start: time(22, 0),
+                end: time(7, 30)
+            }
+        );
+        assert!(QuietHours::parse("22:00").is_err());
+        assert!(QuietHours::parse("22:00-25:00").is_err());
+        assert!(QuietHours::parse("09:00-09:00").is_err());
+    }
+
+    #[test]
+    fn quiet_hours_can_run_past_midnight() {
+        let overnight = QuietHours::parse("22:00-07:00").unwrap();
+        assert!(overnight.contains(time(23, 0)));
+        assert!(overnight.contains(time(6, 59)));
+        assert!(!overnight.contains(time(7, 0)));
+        assert!(!overnight.contains(time(12, 0)));
+
+        let lunch = QuietHours::parse("12:00-13:00").unwrap();
+        assert!(lunch.contains(time(12, 30)));
+        assert!(!lunch.contains(time(13, 0)));
+    }
+
+    #[test]
+    fn fullscreen_is_only_checked_when_enabled() {
+        let rules = SuppressionRules::default();
+        assert!(!rules.is_suppressed(time(12, 0), || panic!("should not be checked")));
+        let rules = SuppressionRules {
+            quiet_hours: None,
+            suppress_fullscreen: true,
+        };
+        assert!(rules.is_suppressed(time(12, 0), || true));
+        assert!(!rules.is_suppressed(time(12, 0), || false));
+    }
+
+    #[test]
+    fn alerts_are_deferred_during_quiet_hours() {
+        let (recording_notifier, notifier) = quiet_notifier(SuppressionRules {
+            quiet_hours: Some(QuietHours::parse("22:00-07:00").unwrap()),
+            suppress_fullscreen: false,
+        });
+        let alert = screen_time_alert(Duration::from_secs(45 * 60), None);
+        notifier.notify_at(&alert, time(23, 0), || false);
+        notifier.release_deferred_at(time(6, 0), || false);
+        assert!(recording_notifier.alerts().is_empty());
+
+        notifier.release_deferred_at(time(7, 0), || false);
+        assert_eq!(recording_notifier.alerts(), vec![alert]);
+        //Released alerts are only sent once
+        notifier.release_deferred_at(time(8, 0), || false);
+        assert_eq!(recording_notifier.alerts().len(), 1);
+    }
+
+    #[test]
+    fn deferred_alerts_are_coalesced() {
+        let (recording_notifier, notifier) = quiet_notifier(SuppressionRules {
+            quiet_hours: None,
+            suppress_fullscreen: true,
+        });
+        notifier.notify_at(
+            &screen_time_alert(Duration::from_secs(45 * 60), None),
+            time(12, 0),
+            || true,
+        );
+        notifier.notify_at(&limit_alert("Slack", 100), time(12, 0), || true);
+        notifier.notify_at(
+            &screen_time_alert(Duration::from_secs(90 * 60), None),
+            time(12, 0),
+            || true,
+        );
+        notifier.release_deferred_at(time(12, 0), || false);
+
+        let alerts = recording_notifier.alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            alerts[0].body,
+            "You have used 100% of your daily limit for Slack (60 of 60 minutes)\n\
+             You have been on the screen for 90 minutes without a break"
+        );
+    }
+
+    #[test]
+    fn error_alerts_are_never_deferred() {
+        let (recording_notifier, notifier) = quiet_notifier(SuppressionRules {
+            quiet_hours: None,
+            suppress_fullscreen: true,
+        });
+        notifier.notify_at(&error_alert("Error writing to csv"), time(12, 0), || true);
+        assert_eq!(recording_notifier.alerts().len(), 1);
+    }
+
+    #[test]
+    fn parse_xprop_active_window() {
+        assert_eq!(
+            parse_active_window_id("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007\n"),
+            Some("0x3a00007")
+        );
+        assert_eq!(
+            parse_active_window_id("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x0"),
+            None
+        );
+        assert_eq!(
+            parse_active_window_id("_NET_ACTIVE_WINDOW:  not found."),
+            None
+        );
+    }
+}
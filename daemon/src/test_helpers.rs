@@ -1,20 +1,13 @@
 #[cfg(test)]
 pub mod tests {
-    use std::{env, fs::File, io::Write};
+    use std::{collections::HashMap, env, fs::File, io::Write, time::Duration};
 
+    use chrono::NaiveDate;
     use tempfile;
-    pub const CSV_NAME: &str = "screen_time_data.csv";
-    pub const SOCKET_NAME: &str = "screen-time-sock";
 
-    pub fn get_socket_path(temp_dir: &tempfile::TempDir) -> String {
-        String::from(
-            temp_dir
-                .path()
-                .join(&SOCKET_NAME.to_string())
-                .to_str()
-                .unwrap(),
-        )
-    }
+    use crate::csv_writer::write_data_to_csv;
+    use crate::limits::start_of_day;
+    pub const CSV_NAME: &str = "screen_time_data.csv";
 
     fn create_and_set_temp_dir() -> tempfile::TempDir {
         let temp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
@@ -37,4 +30,18 @@ pub mod tests {
 
         (temp_dir, actual_path_to_csv)
     }
+
+    pub fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    // Writes the minutes used per app as one flush at midday
+    pub fn write_day(day: NaiveDate, apps: &[(&str, u64)]) {
+        let program_times: HashMap<String, Duration> = apps
+            .iter()
+            .map(|(app_name, app_minutes)| (app_name.to_string(), minutes(*app_minutes)))
+            .collect();
+        let midday = start_of_day(day, 0) + Duration::from_secs(12 * 60 * 60);
+        write_data_to_csv(&program_times, &CSV_NAME.to_string(), None, midday).unwrap();
+    }
 }
@@ -1,100 +1,70 @@
-use crate::socket::{create_socket, listen_for_connections};
-use crate::{notification::exit_with_error_notification, screen_time_notification, socket};
+use crate::socket::{create_socket, listen_for_connections, SocketState};
+use crate::{error::DaemonError, socket};
 use std::{
-    error::Error,
-    io,
     sync::{atomic::AtomicBool, Arc},
     thread::{self, JoinHandle},
-    time,
 };
 
-pub fn create_alert_screen_thread(alert_screen_time: u64) -> io::Result<JoinHandle<()>> {
-    thread::Builder::new()
-        .name("alert_screen_thread".to_string())
-        .spawn(move || {
-            if alert_screen_time == 0 {
-                return;
-            }
-            loop {
-                thread::sleep(time::Duration::from_secs(alert_screen_time * 60));
-                screen_time_notification(alert_screen_time);
-            }
-        })
-}
 pub fn create_socket_listener_thread(
     child_program_finished: Arc<AtomicBool>,
-    child_update_csv: Arc<AtomicBool>,
-    alert_screen_time: u64,
+    child_socket_state: SocketState,
     socket_path: String,
-) -> Result<JoinHandle<()>, Box<dyn Error>> {
-    let socket_listener_thread = match thread::Builder::new()
+) -> Result<JoinHandle<Result<(), DaemonError>>, DaemonError> {
+    let socket_listener_thread = thread::Builder::new()
         .name("socket_listener_thread".to_string())
         .spawn(move || {
-            let (socket, tcp_listener) = create_socket(&socket_path);
-            if let Err(err) = listen_for_connections(
-                &tcp_listener,
-                &child_program_finished,
-                &child_update_csv,
-                alert_screen_time,
-            ) {
-                let error_message = format!("Error listening for connections: {}", err);
-                exit_with_error_notification(error_message.as_str());
-            }
+            let (socket, tcp_listener) = create_socket(&socket_path)?;
+            listen_for_connections(&tcp_listener, &child_program_finished, &child_socket_state);
             println!("Finished listening for connections.");
 
-            match socket::close_socket(socket) {
-                Ok(()) => {
-                    println!("Socket closed!");
-                }
-                Err(err) => {
-                    let error_message = format!("Error closing socket: {}", err);
-                    exit_with_error_notification(error_message.as_str());
-                }
-            }
-        }) {
-        Ok(thread) => thread,
-        Err(err) => {
-            let error_message = format!("Error creating socket listener thread: {}", err);
-            exit_with_error_notification(error_message.as_str());
-        }
-    };
+            socket::close_socket(socket)
+                .map_err(|err| DaemonError::socket("Error closing socket", err))?;
+            println!("Socket closed!");
+            Ok(())
+        })
+        .map_err(|err| {
+            DaemonError::Thread(format!("Error creating socket listener thread: {}", err))
+        })?;
     Ok(socket_listener_thread)
 }
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use crate::socket::{connect_to_socket, send_terminating_mssg};
-    use crate::test_helpers::tests::{get_socket_path, setup};
+    use crate::backup::BackupRequest;
+    use crate::limits::LimitUsage;
+    use crate::pause::PauseState;
+    use crate::socket::{connect_to_socket, send_message_to_socket, send_terminating_mssg};
+    use crate::test_helpers::tests::setup;
     use serial_test::serial;
     use std::io::{Read, Write};
     use std::net::Shutdown;
-    use std::path::Path;
     use std::sync::atomic::Ordering;
+    use std::sync::{mpsc, Mutex};
 
     #[test]
     #[serial]
     fn test_create_socket_listener_thread() {
-        let (temp_dir, _) = setup();
-        // let socket_path = get_socket_path(&temp_dir);
+        let (_temp_dir, _) = setup();
 
         let child_program_finished = Arc::new(AtomicBool::new(false));
-        let child_update_csv = Arc::new(AtomicBool::new(false));
-        let alert_screen_time = 45;
+        let socket_state = SocketState {
+            alert_screen_time: 45,
+            ..Default::default()
+        };
         let socket_addr = "[::1]:42345".to_string();
 
         //creates socket, listens for connections, and closes socket
         let socket_listener_thread = create_socket_listener_thread(
             child_program_finished.clone(),
-            child_update_csv,
-            alert_screen_time,
+            socket_state,
             socket_addr.clone(),
         )
         .unwrap();
         //Wait for socket_listener_thread to set up
         std::thread::sleep(std::time::Duration::from_secs(3));
 
-        let mut stream = connect_to_socket(socket_addr.clone());
+        let mut stream = connect_to_socket(socket_addr.clone()).unwrap();
         println!("Socket connected");
         stream.write_all(b"HEALTH_CHECK").unwrap();
         println!("Sent HEALTH_CHECK request");
@@ -107,12 +77,166 @@ mod tests {
 
         //terminate socket_listener_thread
         child_program_finished.store(true, Ordering::Relaxed);
-        send_terminating_mssg(socket_addr.clone());
+        send_terminating_mssg(socket_addr.clone()).unwrap();
 
         println!("program_finished set to true");
         assert_eq!(received, "Ok");
-        socket_listener_thread.join().unwrap();
+        socket_listener_thread.join().unwrap().unwrap();
 
         //if socket is closed, it does not exist
     }
+
+    #[test]
+    #[serial]
+    fn test_pause_and_resume_requests() {
+        let (_temp_dir, _) = setup();
+        let child_program_finished = Arc::new(AtomicBool::new(false));
+        let pause_state = PauseState::new();
+        let socket_addr = "[::1]:42346".to_string();
+
+        let socket_listener_thread = create_socket_listener_thread(
+            child_program_finished.clone(),
+            SocketState {
+                pause_state: pause_state.clone(),
+                ..Default::default()
+            },
+            socket_addr.clone(),
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(3));
+
+        let received = send_message_to_socket(&socket_addr, "PAUSE 10").unwrap();
+        assert_eq!(received, "Success");
+        assert!(pause_state.is_paused());
+
+        let received = send_message_to_socket(&socket_addr, "RESUME").unwrap();
+        assert_eq!(received, "Success");
+        assert!(!pause_state.is_paused());
+
+        let received = send_message_to_socket(&socket_addr, "PAUSE later").unwrap();
+        assert_eq!(received, "Failure");
+        assert!(!pause_state.is_paused());
+
+        child_program_finished.store(true, Ordering::Relaxed);
+        send_terminating_mssg(socket_addr.clone()).unwrap();
+        socket_listener_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_limits_request() {
+        let (_temp_dir, _) = setup();
+        let child_program_finished = Arc::new(AtomicBool::new(false));
+        let limit_usage = Arc::new(Mutex::new(vec![LimitUsage {
+            name: "Slack".to_string(),
+            used: std::time::Duration::from_secs(95 * 60),
+            minutes: 90,
+        }]));
+        let socket_addr = "[::1]:42347".to_string();
+
+        let socket_listener_thread = create_socket_listener_thread(
+            child_program_finished.clone(),
+            SocketState {
+                limit_usage: Arc::clone(&limit_usage),
+                ..Default::default()
+            },
+            socket_addr.clone(),
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(3));
+
+        let received = send_message_to_socket(&socket_addr, "LIMITS").unwrap();
+        assert_eq!(received, "Slack: 95/90 minutes (105%) - over limit");
+
+        child_program_finished.store(true, Ordering::Relaxed);
+        send_terminating_mssg(socket_addr.clone()).unwrap();
+        socket_listener_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_restore_request_waits_for_the_run_loop() {
+        let (_temp_dir, _) = setup();
+        let child_program_finished = Arc::new(AtomicBool::new(false));
+        let (backup_sender, backup_receiver) = mpsc::channel();
+        let socket_addr = "[::1]:42349".to_string();
+
+        let socket_listener_thread = create_socket_listener_thread(
+            child_program_finished.clone(),
+            SocketState {
+                backup_requests: Some(backup_sender),
+                ..Default::default()
+            },
+            socket_addr.clone(),
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(3));
+
+        let run_loop = std::thread::spawn(move || {
+            let (request, reply): (BackupRequest, mpsc::Sender<String>) =
+                backup_receiver.recv().unwrap();
+            reply.send("Restored".to_string()).unwrap();
+            request
+        });
+        let received =
+            send_message_to_socket(&socket_addr, "RESTORE /backups/snapshot.tar.gz").unwrap();
+        assert_eq!(received, "Restored");
+        assert_eq!(
+            run_loop.join().unwrap(),
+            BackupRequest::Restore("/backups/snapshot.tar.gz".into())
+        );
+
+        child_program_finished.store(true, Ordering::Relaxed);
+        send_terminating_mssg(socket_addr.clone()).unwrap();
+        socket_listener_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_digest_and_names_requests() {
+        let (_temp_dir, _) = setup();
+        let child_program_finished = Arc::new(AtomicBool::new(false));
+        let socket_state = SocketState::default();
+        socket_state
+            .program_times
+            .lock()
+            .unwrap()
+            .insert("Code".to_string(), std::time::Duration::from_secs(30 * 60));
+        let socket_addr = "[::1]:42348".to_string();
+
+        let socket_listener_thread = create_socket_listener_thread(
+            child_program_finished.clone(),
+            socket_state,
+            socket_addr.clone(),
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(3));
+
+        let received = send_message_to_socket(&socket_addr, "DIGEST").unwrap();
+        assert_eq!(
+            received,
+            "Today: 30m screen time, 30m more than yesterday\nTop apps: Code 30m"
+        );
+        let received = send_message_to_socket(&socket_addr, "DIGEST MONTHLY").unwrap();
+        assert_eq!(received, "Failure");
+        let received = send_message_to_socket(&socket_addr, "NAMES").unwrap();
+        assert_eq!(received, "Code: 30m");
+        let received = send_message_to_socket(&socket_addr, "GOALS JSON").unwrap();
+        assert_eq!(received, "[]");
+        let received = send_message_to_socket(&socket_addr, "FOCUS").unwrap();
+        assert_eq!(received, "No focus session is running");
+        let received = send_message_to_socket(&socket_addr, "DAY_START_HOUR").unwrap();
+        assert_eq!(received, "0");
+        let received = send_message_to_socket(&socket_addr, "ENCRYPTION").unwrap();
+        assert_eq!(received, "None");
+        //Backups and repairs are handled by the run() loop, which is not running
+        let received = send_message_to_socket(&socket_addr, "BACKUP").unwrap();
+        assert_eq!(received, "Failure");
+        let received = send_message_to_socket(&socket_addr, "REPAIR").unwrap();
+        assert_eq!(received, "Failure");
+
+        child_program_finished.store(true, Ordering::Relaxed);
+        send_terminating_mssg(socket_addr.clone()).unwrap();
+        socket_listener_thread.join().unwrap().unwrap();
+    }
 }
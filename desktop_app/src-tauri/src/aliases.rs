@@ -0,0 +1,48 @@
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// Alias rules file kept next to the screen time csv, shared with the daemon.
+pub const ALIASES_FILE_NAME: &str = "aliases.json";
+
+// Maps raw application names, such as "code" and "code-oss", to one name.
+// The file looks like {"aliases": {"Code": ["(?i)code", "code-oss"]}}, each pattern must match the whole raw name.
+#[derive(Debug, Default)]
+pub struct AliasRules {
+    rules: Vec<(Regex, String)>,
+}
+
+#[derive(Deserialize)]
+struct AliasesConfig {
+    aliases: HashMap<String, Vec<String>>,
+}
+
+impl AliasRules {
+    //No file means no aliases.
+    pub fn load(aliases_path: &Path) -> Result<AliasRules, Box<dyn Error>> {
+        if !aliases_path.exists() {
+            return Ok(AliasRules::default());
+        }
+        let aliases_json = fs::read_to_string(aliases_path)?;
+        let config: AliasesConfig = serde_json::from_str(&aliases_json)?;
+        let mut rules = Vec::new();
+        for (name, patterns) in config.aliases {
+            for pattern in patterns {
+                rules.push((Regex::new(&format!("^(?:{})$", pattern))?, name.clone()));
+            }
+        }
+        // Apply the rules in the same order as the daemon
+        rules.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        Ok(AliasRules { rules })
+    }
+
+    pub fn resolve<'a>(&'a self, app_name: &'a str) -> &'a str {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(app_name))
+            .map_or(app_name, |(_, name)| name.as_str())
+    }
+}
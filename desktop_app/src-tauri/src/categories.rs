@@ -0,0 +1,309 @@
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::data_analysis::Row;
+
+// Rules file kept next to the screen time csv.
+pub const CATEGORIES_FILE_NAME: &str = "categories.json";
+// Category of applications that do not match any rule.
+pub const UNCATEGORIZED: &str = "Uncategorized";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Productivity {
+    Productive,
+    Neutral,
+    Distracting,
+}
+
+// A rule matches if the application name matches the regex.
+// Rules that also match the window title are resolved by the daemon when recording,
+// as titles are never written to the csv, and rows carry the category they resolved to.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct CategoryRuleConfig {
+    app: String,
+    #[serde(default)]
+    title: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CategoryConfig {
+    name: String,
+    productivity: Productivity,
+    rules: Vec<CategoryRuleConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CategoriesConfig {
+    categories: Vec<CategoryConfig>,
+}
+
+struct CategoryRule {
+    category: String,
+    productivity: Productivity,
+    app: Regex,
+}
+
+// Maps applications to categories, the first matching rule wins.
+pub struct CategoryRules {
+    rules: Vec<CategoryRule>,
+    //Productivity of each category by name, for rows the daemon categorized by title
+    productivities: HashMap<String, Productivity>,
+}
+
+impl CategoryRules {
+    //Load the rules file, no file means every application is uncategorized.
+    pub fn load(rules_path: &Path) -> Result<CategoryRules, Box<dyn Error>> {
+        if !rules_path.exists() {
+            return Ok(CategoryRules {
+                rules: Vec::new(),
+                productivities: HashMap::new(),
+            });
+        }
+        let rules_json = fs::read_to_string(rules_path)?;
+        CategoryRules::parse(&rules_json)
+    }
+
+    pub fn parse(rules_json: &str) -> Result<CategoryRules, Box<dyn Error>> {
+        let config: CategoriesConfig = serde_json::from_str(rules_json)?;
+        let mut rules = Vec::new();
+        let mut productivities = HashMap::new();
+        for category in config.categories {
+            productivities
+                .entry(category.name.clone())
+                .or_insert(category.productivity);
+            for rule in category.rules {
+                let app = Regex::new(&rule.app)?;
+                //Title rules are applied by the daemon, but a broken one is still refused here
+                if let Some(title) = rule.title {
+                    Regex::new(&title)?;
+                    continue;
+                }
+                rules.push(CategoryRule {
+                    category: category.name.clone(),
+                    productivity: category.productivity,
+                    app,
+                });
+            }
+        }
+        Ok(CategoryRules {
+            rules,
+            productivities,
+        })
+    }
+
+    pub fn categorize(&self, application: &str) -> (&str, Productivity) {
+        match self
+            .rules
+            .iter()
+            .find(|rule| rule.app.is_match(application))
+        {
+            Some(rule) => (&rule.category, rule.productivity),
+            None => (UNCATEGORIZED, Productivity::Neutral),
+        }
+    }
+
+    // The category of a row, the one the daemon recorded from a title rule if any.
+    pub fn categorize_row<'a>(&'a self, row: &'a Row) -> (&'a str, Productivity) {
+        if row.category().is_empty() {
+            return self.categorize(row.application());
+        }
+        let productivity = self
+            .productivities
+            .get(row.category())
+            .copied()
+            .unwrap_or(Productivity::Neutral);
+        (row.category(), productivity)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CategoryTotal {
+    category: String,
+    productivity: Productivity,
+    //How long in seconds the category was active
+    duration: u64,
+}
+
+impl CategoryTotal {
+    pub fn category(&self) -> &str {
+        &self.category
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ProductivityScore {
+    //0 when all time was distracting, 100 when all time was productive
+    score: u64,
+    productive: u64,
+    neutral: u64,
+    distracting: u64,
+}
+
+pub fn category_totals(rows: &[Row], category_rules: &CategoryRules) -> Vec<CategoryTotal> {
+    let mut totals: HashMap<String, CategoryTotal> = HashMap::new();
+    for row in rows {
+        let (category, productivity) = category_rules.categorize_row(row);
+        totals
+            .entry(category.to_string())
+            .or_insert(CategoryTotal {
+                category: category.to_string(),
+                productivity,
+                duration: 0,
+            })
+            .duration += row.duration();
+    }
+    let mut totals: Vec<CategoryTotal> = totals.into_values().collect();
+    totals.sort_by_key(|total| Reverse(total.duration));
+    totals
+}
+
+// Neutral time counts half, so a day of only neutral time scores 50.
+pub fn productivity_score(category_totals: &[CategoryTotal]) -> ProductivityScore {
+    let mut score = ProductivityScore::default();
+    for total in category_totals {
+        match total.productivity {
+            Productivity::Productive => score.productive += total.duration,
+            Productivity::Neutral => score.neutral += total.duration,
+            Productivity::Distracting => score.distracting += total.duration,
+        }
+    }
+    let total = score.productive + score.neutral + score.distracting;
+    score.score = (score.productive * 100 + score.neutral * 50)
+        .checked_div(total)
+        .unwrap_or(0);
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES_JSON: &str = r#"{
+        "categories": [
+            {
+                "name": "Development",
+                "productivity": "productive",
+                "rules": [{ "app": "(?i)^code$" }, { "app": "^Alacritty$" }]
+            },
+            {
+                "name": "Editors",
+                "productivity": "neutral",
+                "rules": [{ "app": "(?i)code" }]
+            },
+            {
+                "name": "Entertainment",
+                "productivity": "distracting",
+                "rules": [{ "app": "(?i)^steam$" }]
+            }
+        ]
+    }"#;
+
+    fn total(productivity: Productivity, duration: u64) -> CategoryTotal {
+        CategoryTotal {
+            category: format!("{:?}", productivity),
+            productivity,
+            duration,
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let category_rules = CategoryRules::parse(RULES_JSON).unwrap();
+        assert_eq!(
+            category_rules.categorize("Code"),
+            ("Development", Productivity::Productive)
+        );
+        assert_eq!(
+            category_rules.categorize("Visual Studio Code"),
+            ("Editors", Productivity::Neutral)
+        );
+        assert_eq!(
+            category_rules.categorize("Steam"),
+            ("Entertainment", Productivity::Distracting)
+        );
+    }
+
+    #[test]
+    fn rules_are_case_sensitive_unless_they_say_otherwise() {
+        let category_rules = CategoryRules::parse(RULES_JSON).unwrap();
+        assert_eq!(
+            category_rules.categorize("CODE"),
+            ("Development", Productivity::Productive)
+        );
+        assert_eq!(
+            category_rules.categorize("alacritty"),
+            (UNCATEGORIZED, Productivity::Neutral)
+        );
+    }
+
+    #[test]
+    fn rows_keep_the_category_recorded_from_their_title() {
+        let rules_json = r#"{"categories": [{"name": "Development", "productivity": "productive",
+            "rules": [{ "app": "(?i)firefox", "title": "GitHub" }]}]}"#;
+        let category_rules = CategoryRules::parse(rules_json).unwrap();
+        //The title rule does not categorize the application on its own
+        assert_eq!(
+            category_rules.categorize("firefox"),
+            (UNCATEGORIZED, Productivity::Neutral)
+        );
+        let csv_text = "timestamp,application,duration,host,utc_offset,category\n\
+            1704700800,firefox,600,laptop,,Development\n\
+            1704700800,firefox,300,laptop,,\n";
+        let rows: Vec<Row> = csv::Reader::from_reader(csv_text.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let totals: Vec<(String, Productivity, u64)> = category_totals(&rows, &category_rules)
+            .into_iter()
+            .map(|total| (total.category, total.productivity, total.duration))
+            .collect();
+        assert_eq!(
+            totals,
+            vec![
+                ("Development".to_string(), Productivity::Productive, 600),
+                (UNCATEGORIZED.to_string(), Productivity::Neutral, 300),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_title_rules_are_refused() {
+        let rules_json = r#"{"categories": [{"name": "Development", "productivity": "productive",
+            "rules": [{ "app": "(?i)firefox", "title": "(" }]}]}"#;
+        assert!(CategoryRules::parse(rules_json).is_err());
+    }
+
+    #[test]
+    fn neutral_time_counts_half() {
+        let score = productivity_score(&[
+            total(Productivity::Productive, 600),
+            total(Productivity::Neutral, 200),
+            total(Productivity::Distracting, 200),
+        ]);
+        assert_eq!(score.score, 70);
+        assert_eq!(
+            (score.productive, score.neutral, score.distracting),
+            (600, 200, 200)
+        );
+        assert_eq!(
+            productivity_score(&[total(Productivity::Neutral, 300)]).score,
+            50
+        );
+        assert_eq!(
+            productivity_score(&[total(Productivity::Distracting, 300)]).score,
+            0
+        );
+        assert_eq!(productivity_score(&[]).score, 0);
+    }
+}
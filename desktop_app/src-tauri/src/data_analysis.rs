@@ -1,10 +1,20 @@
-use csv::ReaderBuilder;
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveDateTime, TimeDelta, Timelike};
+use csv::{Reader, ReaderBuilder};
 use serde_derive::{Deserialize, Serialize};
 use serde_with::serde_as;
 use serde_with::TimestampSeconds;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read};
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{error::Error, vec};
+use tz::{DateTime, TimeZoneRef};
+
+use crate::aliases::AliasRules;
+use crate::encryption::Cipher;
+
+// Application name the daemon uses for rows recording a paused period.
+const PAUSED_APPLICATION: &str = "Paused";
 
 #[serde_as]
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -14,6 +24,29 @@ pub struct Row {
     application: String,
     //How long in seconds the application was active
     duration: u64,
+    //Machine the row was recorded on, empty in files from before the daemon recorded it
+    #[serde(default)]
+    host: String,
+    //Seconds east of UTC of the local time the row was recorded in, absent before the daemon recorded it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    utc_offset: Option<i32>,
+    //Category the daemon resolved from the window title, empty when no title rule matched
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    category: String,
+}
+
+impl Row {
+    pub fn application(&self) -> &str {
+        &self.application
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+
+    pub fn category(&self) -> &str {
+        &self.category
+    }
 }
 #[serde_as]
 #[derive(Deserialize, Serialize, Debug)]
@@ -22,68 +55,807 @@ pub struct RowDetails {
     timestamp: SystemTime,
     //How long in seconds the application was active
     duration: u64,
+    utc_offset: Option<i32>,
 }
-pub fn week_screen_time(
+// Read the csv, decrypting it first if the daemon encrypts it.
+fn csv_reader(
+    csv_path: String,
+    cipher: Option<&Cipher>,
+) -> Result<Reader<Box<dyn Read>>, Box<dyn Error>> {
+    let source: Box<dyn Read> = match cipher {
+        Some(cipher) => Box::new(Cursor::new(cipher.decrypt_file(&csv_path)?)),
+        None => Box::new(File::open(csv_path)?),
+    };
+    // Lines starting with '#', such as the schema version, are skipped and unknown columns are ignored
+    Ok(ReaderBuilder::new().comment(Some(b'#')).from_reader(source))
+}
+
+// Skip malformed rows so that one bad row does not break every view, `screen_timed repair` quarantines them.
+// Only rows recorded on host are kept if it is given, otherwise every machine's rows are combined.
+// The rows are read one at a time, so a query does not hold the whole history.
+fn valid_rows<'a>(
+    rdr: &'a mut Reader<Box<dyn Read>>,
+    host: Option<&'a str>,
+) -> impl Iterator<Item = Row> + 'a {
+    rdr.deserialize::<Row>()
+        .filter_map(move |result| match result {
+            Ok(row) => host.is_none_or(|host| row.host == host).then_some(row),
+            Err(e) => {
+                println!("Skipping malformed row: {}", e);
+                None
+            }
+        })
+}
+
+// The machines with rows in the csv, as merged with `screen_timed merge`.
+pub fn hosts(csv_path: String, cipher: Option<&Cipher>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut rdr = csv_reader(csv_path, cipher)?;
+    let mut hosts: Vec<String> = valid_rows(&mut rdr, None)
+        .map(|row| row.host)
+        .filter(|host| !host.is_empty())
+        .collect();
+    hosts.sort();
+    hosts.dedup();
+    Ok(hosts)
+}
+
+// The length of each bucket of a range query.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bucket {
+    Day,
+    Week,
+    Month,
+}
+
+// Which rows a range query counts. Applications are matched under their aliases.
+#[derive(Debug, Default)]
+pub struct RowFilter<'a> {
+    // Every machine's rows are combined if no host is given
+    pub host: Option<&'a str>,
+    pub applications: Option<&'a [String]>,
+}
+
+// The IANA time zone, e.g. "Europe/Paris", or this machine's time zone if none is given.
+pub fn time_zone(name: Option<&str>) -> Result<TimeZoneRef<'static>, Box<dyn Error>> {
+    match name {
+        Some(name) => Ok(tzdb::tz_by_name(name).ok_or(format!("Unknown time zone {}", name))?),
+        None => Ok(tzdb::local_tz().ok_or("Could not find the local time zone")?),
+    }
+}
+
+// When days start: at hour in the time zone, whatever offset the rows were recorded in.
+// With an hour after midnight, the time before it counts towards the day before.
+#[derive(Debug, Clone, Copy)]
+pub struct DayStart<'a> {
+    pub time_zone: TimeZoneRef<'a>,
+    pub hour: u32,
+}
+
+// The start of the date in seconds.
+// If the clocks skipped the start the day starts when they changed, if they went back it starts the first time.
+fn start_of_day(date: NaiveDate, day_start: DayStart) -> Result<u64, Box<dyn Error>> {
+    let start = DateTime::find(
+        date.year(),
+        date.month() as u8,
+        date.day() as u8,
+        day_start.hour.try_into()?,
+        0,
+        0,
+        0,
+        day_start.time_zone,
+    )?
+    .earliest()
+    .ok_or(format!("{} has no start in the time zone", date))?;
+    Ok(start.unix_time().max(0) as u64)
+}
+
+// Where a range query starts and how it is split into buckets.
+#[derive(Debug, Clone, Copy)]
+pub struct DateRange<'a> {
+    pub start: NaiveDate,
+    pub bucket: Bucket,
+    pub buckets: u32,
+    pub day_start: DayStart<'a>,
+}
+
+// The start of each bucket, followed by the end of the last bucket.
+// Days follow the calendar, so a day is not always 24 hours long.
+fn bucket_boundaries(range: &DateRange) -> Result<Vec<u64>, Box<dyn Error>> {
+    let mut boundaries = Vec::new();
+    for index in 0..=range.buckets {
+        let date = match range.bucket {
+            Bucket::Day => range.start.checked_add_days(Days::new(index as u64)),
+            Bucket::Week => range.start.checked_add_days(Days::new(7 * index as u64)),
+            Bucket::Month => range.start.checked_add_months(Months::new(index)),
+        }
+        .ok_or("The range is out of bounds")?;
+        boundaries.push(start_of_day(date, range.day_start)?);
+    }
+    Ok(boundaries)
+}
+
+//Read the csv once and total the screen time per application in each bucket of the range.
+pub fn range_screen_time(
     csv_path: String,
-    start_of_week: u64,
+    range: &DateRange,
+    filter: &RowFilter,
+    alias_rules: &AliasRules,
+    cipher: Option<&Cipher>,
 ) -> Result<Vec<Vec<Row>>, Box<dyn Error>> {
-    let mut week_rows: Vec<Vec<Row>> = vec![Vec::new(); 7];
-    for day in 0..7 {
-        let day_start = start_of_week + (day * 24 * 60 * 60);
-        println!("Day start: {}", day_start);
-        let day_rows = date_screen_time(csv_path.clone(), day_start)?;
-        week_rows[day as usize] = day_rows;
+    let boundaries = bucket_boundaries(range)?;
+    let buckets = range.buckets;
+    let (range_start, range_end) = (boundaries[0], boundaries[boundaries.len() - 1]);
+    let mut bucket_totals: Vec<HashMap<String, RowDetails>> =
+        (0..buckets).map(|_| HashMap::new()).collect();
+    let mut rdr = csv_reader(csv_path, cipher)?;
+    for record in valid_rows(&mut rdr, filter.host) {
+        let timestamp = record.timestamp.duration_since(UNIX_EPOCH)?.as_secs();
+        if timestamp < range_start || timestamp >= range_end {
+            continue;
+        }
+        let Some(app_name) = counted_application(&record, filter, alias_rules) else {
+            continue;
+        };
+        // The bucket whose start is the last one at or before the row
+        let index = boundaries.partition_point(|boundary| *boundary <= timestamp) - 1;
+        let row_details = bucket_totals[index]
+            .entry(app_name.to_string())
+            .or_insert(RowDetails {
+                timestamp: record.timestamp,
+                duration: 0,
+                utc_offset: record.utc_offset,
+            });
+        row_details.duration += record.duration;
+        // The latest row of the application in the bucket
+        if record.timestamp >= row_details.timestamp {
+            row_details.timestamp = record.timestamp;
+            row_details.utc_offset = record.utc_offset;
+        }
     }
-    Ok(week_rows)
+    Ok(bucket_totals
+        .into_iter()
+        .map(|totals| {
+            totals
+                .into_iter()
+                .map(|(app_name, row_details)| Row {
+                    timestamp: row_details.timestamp,
+                    application: app_name,
+                    duration: row_details.duration,
+                    host: filter.host.unwrap_or_default().to_string(),
+                    utc_offset: row_details.utc_offset,
+                    category: String::new(),
+                })
+                .collect()
+        })
+        .collect())
+}
+
+pub fn week_screen_time(
+    csv_path: String,
+    start_of_week: NaiveDate,
+    day_start: DayStart,
+    alias_rules: &AliasRules,
+    cipher: Option<&Cipher>,
+    host: Option<&str>,
+) -> Result<Vec<Vec<Row>>, Box<dyn Error>> {
+    let range = DateRange {
+        start: start_of_week,
+        bucket: Bucket::Day,
+        buckets: 7,
+        day_start,
+    };
+    let filter = RowFilter {
+        host,
+        ..Default::default()
+    };
+    range_screen_time(csv_path, &range, &filter, alias_rules, cipher)
+}
+
+// The screen time of each day of the month the date is in.
+pub fn month_screen_time(
+    csv_path: String,
+    date: NaiveDate,
+    day_start: DayStart,
+    filter: &RowFilter,
+    alias_rules: &AliasRules,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<Vec<Row>>, Box<dyn Error>> {
+    let first_day = date.with_day(1).ok_or("Invalid month")?;
+    let next_month = first_day
+        .checked_add_months(Months::new(1))
+        .ok_or("The month is out of bounds")?;
+    let range = DateRange {
+        start: first_day,
+        bucket: Bucket::Day,
+        buckets: (next_month - first_day).num_days() as u32,
+        day_start,
+    };
+    range_screen_time(csv_path, &range, filter, alias_rules, cipher)
+}
+
+// The screen time of each month of the year the date is in.
+pub fn year_screen_time(
+    csv_path: String,
+    date: NaiveDate,
+    day_start: DayStart,
+    filter: &RowFilter,
+    alias_rules: &AliasRules,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<Vec<Row>>, Box<dyn Error>> {
+    let range = DateRange {
+        start: NaiveDate::from_ymd_opt(date.year(), 1, 1).ok_or("Invalid year")?,
+        bucket: Bucket::Month,
+        buckets: 12,
+        day_start,
+    };
+    range_screen_time(csv_path, &range, filter, alias_rules, cipher)
 }
+
 pub fn date_screen_time(
     csv_path: String,
-    start_of_date: u64,
+    date: NaiveDate,
+    day_start: DayStart,
+    alias_rules: &AliasRules,
+    cipher: Option<&Cipher>,
+    host: Option<&str>,
+) -> Result<vec::Vec<Row>, Box<dyn Error>> {
+    let range = DateRange {
+        start: date,
+        bucket: Bucket::Day,
+        buckets: 1,
+        day_start,
+    };
+    let filter = RowFilter {
+        host,
+        ..Default::default()
+    };
+    let mut days = range_screen_time(csv_path, &range, &filter, alias_rules, cipher)?;
+    Ok(days.remove(0))
+}
+
+// Rows recorded in the range under their aliases, without aggregating them by application.
+pub fn range_rows(
+    csv_path: String,
+    range: &DateRange,
+    filter: &RowFilter,
+    alias_rules: &AliasRules,
+    cipher: Option<&Cipher>,
 ) -> Result<vec::Vec<Row>, Box<dyn Error>> {
-    println!("csv_path: {}", csv_path);
-    let mut rdr = ReaderBuilder::new().from_path(csv_path)?;
-
-    let end_of_date = start_of_date + (24 * 60 * 60);
-    println!("Start of date: {}", start_of_date);
-    println!("End of date: {}", end_of_date);
-
-    let mut records_map: HashMap<String, RowDetails> = HashMap::new();
-    let mut records: Vec<Row> = Vec::new(); // Collect records into a vector
-    for result in rdr.deserialize() {
-        let record: Row = result?;
-        records.push(record); // Collect each record into the vector
-    }
-    for record in records.iter().rev() {
-        let record_timestamp = record.timestamp.duration_since(UNIX_EPOCH)?;
-        let is_date = record_timestamp >= std::time::Duration::from_secs(start_of_date)
-            && record_timestamp <= std::time::Duration::from_secs(end_of_date);
-        if is_date {
-            // println!("{:?}", &record);
-            let app_name = &record.application;
-            if records_map.contains_key(app_name) {
-                let row_details = records_map.get_mut(app_name).unwrap();
-                (row_details).duration += record.duration;
-            } else {
-                records_map.insert(
-                    app_name.to_string(),
-                    RowDetails {
-                        timestamp: record.timestamp,
-                        duration: record.duration,
-                    },
-                );
+    let boundaries = bucket_boundaries(range)?;
+    let (range_start, range_end) = (boundaries[0], boundaries[boundaries.len() - 1]);
+    let mut rdr = csv_reader(csv_path, cipher)?;
+
+    let mut rows: Vec<Row> = Vec::new();
+    for mut record in valid_rows(&mut rdr, filter.host) {
+        let record_timestamp = record.timestamp.duration_since(UNIX_EPOCH)?.as_secs();
+        if record_timestamp < range_start || record_timestamp >= range_end {
+            continue;
+        }
+        if let Some(app_name) = counted_application(&record, filter, alias_rules) {
+            record.application = app_name.to_string();
+            rows.push(record);
+        }
+    }
+    Ok(rows)
+}
+
+// Rows recorded on the date under their aliases, without aggregating them by application.
+pub fn date_rows(
+    csv_path: String,
+    date: NaiveDate,
+    day_start: DayStart,
+    alias_rules: &AliasRules,
+    cipher: Option<&Cipher>,
+    host: Option<&str>,
+) -> Result<vec::Vec<Row>, Box<dyn Error>> {
+    let range = DateRange {
+        start: date,
+        bucket: Bucket::Day,
+        buckets: 1,
+        day_start,
+    };
+    let filter = RowFilter {
+        host,
+        ..Default::default()
+    };
+    range_rows(csv_path, &range, &filter, alias_rules, cipher)
+}
+
+// The local time of the timestamp in the time zone.
+fn local_time(timestamp: u64, time_zone: TimeZoneRef) -> Result<NaiveDateTime, Box<dyn Error>> {
+    let utc_offset = time_zone
+        .find_local_time_type(timestamp as i64)?
+        .ut_offset();
+    Ok(
+        chrono::DateTime::from_timestamp(timestamp as i64 + utc_offset as i64, 0)
+            .ok_or(format!("Invalid timestamp {}", timestamp))?
+            .naive_utc(),
+    )
+}
+
+// The name a row is counted under, or None if the filter leaves it out.
+fn counted_application<'a>(
+    row: &'a Row,
+    filter: &RowFilter,
+    alias_rules: &'a AliasRules,
+) -> Option<&'a str> {
+    if row.application == PAUSED_APPLICATION {
+        return None;
+    }
+    let app_name = alias_rules.resolve(&row.application);
+    match filter.applications {
+        Some(applications) if !applications.iter().any(|app| app == app_name) => None,
+        _ => Some(app_name),
+    }
+}
+
+// The seconds a row covers, from start to end.
+struct Interval {
+    row: Row,
+    start: u64,
+    end: u64,
+}
+
+// Rows end when they were written and last their duration.
+// The rows of one flush share the same period and the order the applications were used in is not recorded,
+// so they are laid out one after the other, longest first, ending when they were written.
+fn row_intervals(mut rows: Vec<Row>) -> Result<Vec<Interval>, Box<dyn Error>> {
+    rows.sort_by(|a, b| {
+        (&a.host, a.timestamp)
+            .cmp(&(&b.host, b.timestamp))
+            .then_with(|| b.duration.cmp(&a.duration))
+            .then_with(|| a.application.cmp(&b.application))
+    });
+    let mut intervals = Vec::new();
+    for flush in rows.chunk_by(|a, b| a.host == b.host && a.timestamp == b.timestamp) {
+        let end = flush[0].timestamp.duration_since(UNIX_EPOCH)?.as_secs();
+        let mut start = end.saturating_sub(flush.iter().map(|row| row.duration).sum());
+        for row in flush {
+            intervals.push(Interval {
+                row: row.clone(),
+                start,
+                end: start + row.duration,
+            });
+            start += row.duration;
+        }
+    }
+    Ok(intervals)
+}
+
+// The rows whose time can fall between start and end: those written in it, and each machine's first flush after it.
+// A flush only covers the time since the previous one, so no later row can start before end.
+fn rows_covering(
+    rows: impl Iterator<Item = Row>,
+    start: u64,
+    end: u64,
+) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut covering = Vec::new();
+    let mut next_flushes: HashMap<String, Vec<Row>> = HashMap::new();
+    for row in rows {
+        let timestamp = row.timestamp.duration_since(UNIX_EPOCH)?.as_secs();
+        // Rows end when they were written
+        if timestamp <= start {
+            continue;
+        }
+        if timestamp < end {
+            covering.push(row);
+            continue;
+        }
+        let next_flush = next_flushes.entry(row.host.clone()).or_default();
+        match next_flush.first() {
+            Some(first) if first.timestamp < row.timestamp => {}
+            Some(first) if first.timestamp == row.timestamp => next_flush.push(row),
+            _ => *next_flush = vec![row],
+        }
+    }
+    covering.extend(next_flushes.into_values().flatten());
+    Ok(covering)
+}
+
+// Seconds of screen time in each hour of each weekday over the range, Sunday first like Date.getDay().
+// Hours before the day start hour count towards the weekday before.
+pub fn heatmap(
+    csv_path: String,
+    range: &DateRange,
+    filter: &RowFilter,
+    alias_rules: &AliasRules,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<Vec<u64>>, Box<dyn Error>> {
+    let boundaries = bucket_boundaries(range)?;
+    let (range_start, range_end) = (boundaries[0], boundaries[boundaries.len() - 1]);
+    let mut heatmap = vec![vec![0; 24]; 7];
+    let mut rdr = csv_reader(csv_path, cipher)?;
+    let rows = rows_covering(valid_rows(&mut rdr, filter.host), range_start, range_end)?;
+    for interval in row_intervals(rows)? {
+        if counted_application(&interval.row, filter, alias_rules).is_none() {
+            continue;
+        }
+        let mut start = interval.start.max(range_start);
+        let end = interval.end.min(range_end);
+        // Split the interval at each hour
+        while start < end {
+            let local = local_time(start, range.day_start.time_zone)?;
+            let hour_end =
+                (start + 60 * 60 - (local.minute() * 60 + local.second()) as u64).min(end);
+            let day = (local - TimeDelta::hours(range.day_start.hour as i64)).weekday();
+            heatmap[day.num_days_from_sunday() as usize][local.hour() as usize] += hour_end - start;
+            start = hour_end;
+        }
+    }
+    Ok(heatmap)
+}
+
+// A stretch of time spent in one application.
+#[derive(Serialize, Debug)]
+pub struct Segment {
+    application: String,
+    //Start and end in seconds since the epoch
+    start: u64,
+    end: u64,
+    host: String,
+}
+
+// What was used when on the date, in order, with back to back stretches of one application joined.
+pub fn date_timeline(
+    csv_path: String,
+    date: NaiveDate,
+    day_start: DayStart,
+    filter: &RowFilter,
+    alias_rules: &AliasRules,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<Segment>, Box<dyn Error>> {
+    let start_of_date = start_of_day(date, day_start)?;
+    let next_date = date.succ_opt().ok_or("The date is out of bounds")?;
+    let end_of_date = start_of_day(next_date, day_start)?;
+    let mut rdr = csv_reader(csv_path, cipher)?;
+    let rows = rows_covering(
+        valid_rows(&mut rdr, filter.host),
+        start_of_date,
+        end_of_date,
+    )?;
+    let mut intervals = row_intervals(rows)?;
+    intervals.sort_by_key(|interval| interval.start);
+
+    let mut segments: Vec<Segment> = Vec::new();
+    for interval in intervals {
+        let Some(app_name) = counted_application(&interval.row, filter, alias_rules) else {
+            continue;
+        };
+        let start = interval.start.max(start_of_date);
+        let end = interval.end.min(end_of_date);
+        if start >= end {
+            continue;
+        }
+        match segments.last_mut() {
+            Some(last)
+                if last.application == app_name
+                    && last.host == interval.row.host
+                    && start <= last.end =>
+            {
+                last.end = last.end.max(end);
             }
-        } else {
-            // println!("Not today");
+            _ => segments.push(Segment {
+                application: app_name.to_string(),
+                start,
+                end,
+                host: interval.row.host,
+            }),
+        }
+    }
+    Ok(segments)
+}
+
+#[derive(Serialize, Debug)]
+pub struct ApplicationTotal {
+    application: String,
+    //How long in seconds the application was active
+    duration: u64,
+}
+
+// Every raw application name in the csv with its total, longest first, to help write alias rules.
+pub fn raw_application_totals(
+    csv_path: String,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<ApplicationTotal>, Box<dyn Error>> {
+    let mut rdr = csv_reader(csv_path, cipher)?;
+    let mut totals: HashMap<String, u64> = HashMap::new();
+    for record in valid_rows(&mut rdr, None) {
+        if record.application != PAUSED_APPLICATION {
+            *totals.entry(record.application).or_default() += record.duration;
+        }
+    }
+    let mut totals: Vec<ApplicationTotal> = totals
+        .into_iter()
+        .map(|(application, duration)| ApplicationTotal {
+            application,
+            duration,
+        })
+        .collect();
+    totals.sort_by(|a, b| {
+        b.duration
+            .cmp(&a.duration)
+            .then_with(|| a.application.cmp(&b.application))
+    });
+    Ok(totals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use std::fs;
+
+    // Europe/London changes to BST at 01:00 on 31 March 2024 and back to GMT at 02:00 on 27 October
+    fn london(hour: u32) -> DayStart<'static> {
+        DayStart {
+            time_zone: time_zone(Some("Europe/London")).unwrap(),
+            hour,
         }
     }
 
-    println!("Finish read_csv method");
-    Ok(records_map
-        .iter()
-        .map(|(app_name, row_details)| Row {
-            timestamp: row_details.timestamp,
-            application: app_name.to_string(),
-            duration: row_details.duration,
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32) -> u64 {
+        Utc.with_ymd_and_hms(year, month, day, hour, 0, 0)
+            .unwrap()
+            .timestamp() as u64
+    }
+
+    // A csv in the temporary directory with the rows, (timestamp, application, duration).
+    fn write_csv(name: &str, rows: &[(u64, &str, u64)]) -> String {
+        let csv_path = std::env::temp_dir().join(format!("screen_time_test_{}.csv", name));
+        let mut contents = "timestamp,application,duration,host\n".to_string();
+        for (timestamp, application, duration) in rows {
+            contents += &format!("{},{},{},laptop\n", timestamp, application, duration);
+        }
+        fs::write(&csv_path, contents).unwrap();
+        csv_path.to_string_lossy().to_string()
+    }
+
+    fn durations(rows: &[Row]) -> Vec<(String, u64)> {
+        let mut durations: Vec<(String, u64)> = rows
+            .iter()
+            .map(|row| (row.application.clone(), row.duration))
+            .collect();
+        durations.sort();
+        durations
+    }
+
+    #[test]
+    fn days_change_length_with_the_clocks() {
+        let spring = DateRange {
+            start: date(2024, 3, 31),
+            bucket: Bucket::Day,
+            buckets: 1,
+            day_start: london(0),
+        };
+        assert_eq!(
+            bucket_boundaries(&spring).unwrap(),
+            vec![utc(2024, 3, 31, 0), utc(2024, 3, 31, 23)]
+        );
+        let autumn = DateRange {
+            start: date(2024, 10, 27),
+            ..spring
+        };
+        assert_eq!(
+            bucket_boundaries(&autumn).unwrap(),
+            vec![utc(2024, 10, 26, 23), utc(2024, 10, 28, 0)]
+        );
+    }
+
+    #[test]
+    fn a_skipped_day_start_starts_when_the_clocks_change() {
+        // 01:00 does not exist on 31 March, the clocks go from 00:59:59 GMT to 02:00 BST
+        assert_eq!(
+            start_of_day(date(2024, 3, 31), london(1)).unwrap(),
+            utc(2024, 3, 31, 1)
+        );
+        // 01:00 happens twice on 27 October, the day starts the first time
+        assert_eq!(
+            start_of_day(date(2024, 10, 27), london(1)).unwrap(),
+            utc(2024, 10, 27, 0)
+        );
+    }
+
+    #[test]
+    fn a_row_on_a_boundary_counts_towards_the_bucket_it_starts() {
+        let end_of_spring_day = utc(2024, 3, 31, 23);
+        let csv_path = write_csv(
+            "boundary",
+            &[
+                (utc(2024, 3, 31, 0), "Code", 60),
+                (end_of_spring_day - 1, "Code", 30),
+                (end_of_spring_day, "Firefox", 20),
+                (utc(2024, 4, 1, 23) - 1, "Firefox", 10),
+                // The end of the range belongs to the day after it
+                (utc(2024, 4, 1, 23), "Slack", 5),
+            ],
+        );
+        let range = DateRange {
+            start: date(2024, 3, 31),
+            bucket: Bucket::Day,
+            buckets: 2,
+            day_start: london(0),
+        };
+        let days = range_screen_time(
+            csv_path.clone(),
+            &range,
+            &RowFilter::default(),
+            &AliasRules::default(),
+            None,
+        )
+        .unwrap();
+        fs::remove_file(csv_path).unwrap();
+        assert_eq!(durations(&days[0]), vec![("Code".to_string(), 90)]);
+        assert_eq!(durations(&days[1]), vec![("Firefox".to_string(), 30)]);
+    }
+
+    fn utc_time(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> u64 {
+        utc(year, month, day, hour) + minute as u64 * 60
+    }
+
+    #[test]
+    fn a_flush_is_laid_out_longest_first() {
+        let written = utc(2024, 1, 8, 10);
+        let rows: Vec<Row> = [("Slack", 600), ("Firefox", 1200), ("Code", 600)]
+            .into_iter()
+            .map(|(application, duration)| Row {
+                timestamp: UNIX_EPOCH + std::time::Duration::from_secs(written),
+                application: application.to_string(),
+                duration,
+                host: "laptop".to_string(),
+                utc_offset: None,
+                category: String::new(),
+            })
+            .collect();
+        let intervals: Vec<(String, u64, u64)> = row_intervals(rows)
+            .unwrap()
+            .into_iter()
+            .map(|interval| (interval.row.application, interval.start, interval.end))
+            .collect();
+        assert_eq!(
+            intervals,
+            vec![
+                ("Firefox".to_string(), written - 2400, written - 1200),
+                ("Code".to_string(), written - 1200, written - 600),
+                ("Slack".to_string(), written - 600, written),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_first_flush_after_a_range_can_cover_it() {
+        let (start, end) = (utc(2024, 1, 8, 10), utc(2024, 1, 8, 11));
+        let rows: Vec<Row> = [
+            ("laptop", "Code", start - 300),
+            // Ends at the start of the range
+            ("laptop", "Code", start),
+            ("laptop", "Code", start + 300),
+            ("laptop", "Slack", end + 300),
+            ("laptop", "Code", end + 300),
+            ("laptop", "Code", end + 600),
+            ("desktop", "Steam", end + 900),
+        ]
+        .into_iter()
+        .map(|(host, application, written)| Row {
+            timestamp: UNIX_EPOCH + std::time::Duration::from_secs(written),
+            application: application.to_string(),
+            duration: 300,
+            host: host.to_string(),
+            utc_offset: None,
+            category: String::new(),
         })
-        .collect())
+        .collect();
+        let mut covering: Vec<(String, String, u64)> = rows_covering(rows.into_iter(), start, end)
+            .unwrap()
+            .into_iter()
+            .map(|row| {
+                let written = row.timestamp.duration_since(UNIX_EPOCH).unwrap().as_secs();
+                (row.host, row.application, written)
+            })
+            .collect();
+        covering.sort();
+        assert_eq!(
+            covering,
+            vec![
+                ("desktop".to_string(), "Steam".to_string(), end + 900),
+                ("laptop".to_string(), "Code".to_string(), start + 300),
+                ("laptop".to_string(), "Code".to_string(), end + 300),
+                ("laptop".to_string(), "Slack".to_string(), end + 300),
+            ]
+        );
+    }
+
+    #[test]
+    fn heatmap_splits_rows_at_hours_and_midnight() {
+        // London is on GMT in January, so local times are UTC
+        let csv_path = write_csv(
+            "heatmap",
+            &[
+                // Saturday 23:50 to Sunday 00:10
+                (utc_time(2024, 1, 7, 0, 10), "Code", 1200),
+                // Monday 09:45 to 10:15
+                (utc_time(2024, 1, 8, 10, 15), "Code", 1800),
+            ],
+        );
+        let range = DateRange {
+            start: date(2024, 1, 6),
+            bucket: Bucket::Week,
+            buckets: 1,
+            day_start: london(0),
+        };
+        let hours = heatmap(
+            csv_path.clone(),
+            &range,
+            &RowFilter::default(),
+            &AliasRules::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(hours[6][23], 600);
+        assert_eq!(hours[0][0], 600);
+        assert_eq!((hours[1][9], hours[1][10]), (900, 900));
+        assert_eq!(hours.iter().flatten().sum::<u64>(), 3000);
+
+        // With days starting at 4am, the hour after midnight still belongs to Saturday
+        let range = DateRange {
+            day_start: london(4),
+            ..range
+        };
+        let hours = heatmap(
+            csv_path.clone(),
+            &range,
+            &RowFilter::default(),
+            &AliasRules::default(),
+            None,
+        )
+        .unwrap();
+        fs::remove_file(csv_path).unwrap();
+        assert_eq!((hours[6][23], hours[6][0], hours[0][0]), (600, 600, 0));
+    }
+
+    #[test]
+    fn timeline_clips_rows_to_the_date_and_joins_stretches() {
+        let csv_path = write_csv(
+            "timeline",
+            &[
+                // Saturday 23:50 to Sunday 00:10
+                (utc_time(2024, 1, 7, 0, 10), "Code", 1200),
+                // Back to back with it, in the next flush
+                (utc_time(2024, 1, 7, 0, 20), "Code", 600),
+                (utc_time(2024, 1, 7, 0, 30), "Firefox", 600),
+            ],
+        );
+        let segments: Vec<(String, u64, u64)> = date_timeline(
+            csv_path.clone(),
+            date(2024, 1, 7),
+            london(0),
+            &RowFilter::default(),
+            &AliasRules::default(),
+            None,
+        )
+        .unwrap()
+        .into_iter()
+        .map(|segment| (segment.application, segment.start, segment.end))
+        .collect();
+        fs::remove_file(csv_path).unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                (
+                    "Code".to_string(),
+                    utc(2024, 1, 7, 0),
+                    utc_time(2024, 1, 7, 0, 20)
+                ),
+                (
+                    "Firefox".to_string(),
+                    utc_time(2024, 1, 7, 0, 20),
+                    utc_time(2024, 1, 7, 0, 30)
+                ),
+            ]
+        );
+    }
 }
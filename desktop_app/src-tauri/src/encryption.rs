@@ -0,0 +1,115 @@
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+// Same format as the daemon writes, see daemon/src/encryption.rs.
+const ENCRYPTED_FILE_MAGIC: &str = "#screen_timed-encrypted-v1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+// The daemon never sends the passphrase, so the app reads it from its own environment.
+const ENCRYPTION_PASSPHRASE_ENV_VAR: &str = "ENCRYPTION_PASSPHRASE";
+
+pub enum KeySource {
+    Keyfile(PathBuf),
+    Passphrase(String),
+}
+
+impl KeySource {
+    //Parse the daemon's reply to the ENCRYPTION request, None if the data is not encrypted.
+    pub fn from_message(message: &str) -> Result<Option<KeySource>, Box<dyn Error>> {
+        match message.trim() {
+            "None" => Ok(None),
+            "Passphrase" => match std::env::var(ENCRYPTION_PASSPHRASE_ENV_VAR) {
+                Ok(passphrase) => Ok(Some(KeySource::Passphrase(passphrase))),
+                Err(_) => Err(format!(
+                    "The data is encrypted with a passphrase, set {} to read it",
+                    ENCRYPTION_PASSPHRASE_ENV_VAR
+                )
+                .into()),
+            },
+            message => match message.strip_prefix("Keyfile ") {
+                Some(keyfile) => Ok(Some(KeySource::Keyfile(keyfile.into()))),
+                None => Err(format!("Unknown encryption: {}", message).into()),
+            },
+        }
+    }
+
+    fn secret(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            KeySource::Keyfile(path) => Ok(fs::read(path)?.trim_ascii().to_vec()),
+            KeySource::Passphrase(passphrase) => Ok(passphrase.as_bytes().to_vec()),
+        }
+    }
+}
+
+// Decrypts the data file written by the daemon.
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn open(csv_path: &str, key_source: &KeySource) -> Result<Cipher, Box<dyn Error>> {
+        let mut first_line = String::new();
+        BufReader::new(File::open(csv_path)?).read_line(&mut first_line)?;
+        let salt = match first_line.trim().strip_prefix(ENCRYPTED_FILE_MAGIC) {
+            Some(salt) => STANDARD.decode(salt.trim())?,
+            None => return Err(format!("{} is not encrypted", csv_path).into()),
+        };
+        if salt.len() != SALT_LEN {
+            return Err(format!("{} has an invalid salt", csv_path).into());
+        }
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(&key_source.secret()?, &salt, &mut key)
+            .map_err(|err| format!("Error deriving key: {}", err))?;
+        Ok(Cipher {
+            aead: XChaCha20Poly1305::new(&key.into()),
+        })
+    }
+
+    //Decrypt the data file back into csv.
+    //Records that cannot be decrypted, such as a line torn by a crash, are skipped like malformed rows.
+    pub fn decrypt_file(&self, csv_path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut csv = Vec::new();
+        let mut bad_records = 0;
+        // The first line holds the salt
+        for line in BufReader::new(File::open(csv_path)?).lines().skip(1) {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.decrypt_record(&line) {
+                Some(plaintext) => csv.extend(plaintext),
+                None => bad_records += 1,
+            }
+        }
+        // Nothing decrypting means the key is wrong rather than the data being damaged
+        if csv.is_empty() && bad_records > 0 {
+            return Err(format!("Error decrypting {}, the key is wrong", csv_path).into());
+        }
+        if bad_records > 0 {
+            eprintln!(
+                "Skipped {} records of {} that could not be decrypted",
+                bad_records, csv_path
+            );
+        }
+        Ok(csv)
+    }
+
+    fn decrypt_record(&self, line: &str) -> Option<Vec<u8>> {
+        let record = STANDARD.decode(line.trim()).ok()?;
+        if record.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = record.split_at(NONCE_LEN);
+        self.aead
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+}
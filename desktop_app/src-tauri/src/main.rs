@@ -1,10 +1,22 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod aliases;
+mod categories;
 mod data_analysis;
+mod encryption;
 mod socket_comm;
-use data_analysis::Row;
+mod trends;
+use aliases::{AliasRules, ALIASES_FILE_NAME};
+use categories::{CategoryRules, CategoryTotal, ProductivityScore, CATEGORIES_FILE_NAME};
+use chrono::{Days, NaiveDate};
+use data_analysis::{ApplicationTotal, Bucket, DateRange, DayStart, Row, RowFilter, Segment};
+use encryption::{Cipher, KeySource};
+use std::fs;
+use std::path::Path;
 use std::vec;
+use tauri::api::dialog::blocking::FileDialogBuilder;
+use trends::{Comparison, RollingAverage, LONGEST_ROLLING_WINDOW};
 
 fn get_csv_path() -> Result<String, String> {
     let csv_path = match socket_comm::get_path_message() {
@@ -17,11 +29,189 @@ fn get_csv_path() -> Result<String, String> {
     Ok(csv_path)
 }
 
+// The aliases file is kept next to the csv.
+fn get_alias_rules(csv_path: &str) -> Result<AliasRules, String> {
+    let aliases_path = Path::new(csv_path).with_file_name(ALIASES_FILE_NAME);
+    match AliasRules::load(&aliases_path) {
+        Ok(alias_rules) => Ok(alias_rules),
+        Err(e) => {
+            println!("Error while loading aliases: {}", e);
+            Err("Error while loading aliases".to_string())
+        }
+    }
+}
+
+// Ask the daemon whether the csv is encrypted, and with which key.
+fn get_cipher(csv_path: &str) -> Result<Option<Cipher>, String> {
+    let key_source = match socket_comm::get_encryption_message() {
+        Ok(message) => KeySource::from_message(&message),
+        Err(e) => {
+            println!("Error while getting encryption from socket: {}", e);
+            return Err("Error while getting encryption from socket".to_string());
+        }
+    };
+    let cipher = match key_source {
+        Ok(Some(key_source)) => Cipher::open(csv_path, &key_source).map(Some),
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+    };
+    match cipher {
+        Ok(cipher) => Ok(cipher),
+        Err(e) => {
+            println!("Error while opening encrypted csv: {}", e);
+            Err("Error while opening encrypted csv".to_string())
+        }
+    }
+}
+
+// Dates are calendar dates such as "2024-03-01".
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        Ok(date) => Ok(date),
+        Err(e) => {
+            println!("Error while parsing date {}: {}", date, e);
+            Err("Invalid date".to_string())
+        }
+    }
+}
+
+// Days start at the daemon's day start hour in the IANA time zone, e.g. "Europe/Paris",
+// or in this machine's time zone if none is given.
+fn get_day_start(time_zone: Option<&str>) -> Result<DayStart<'static>, String> {
+    let time_zone = match data_analysis::time_zone(time_zone) {
+        Ok(time_zone) => time_zone,
+        Err(e) => {
+            println!("Error while finding time zone: {}", e);
+            return Err("Invalid time zone".to_string());
+        }
+    };
+    match socket_comm::get_day_start_hour_message() {
+        Ok(hour) => Ok(DayStart { time_zone, hour }),
+        Err(e) => {
+            println!("Error while getting day start hour from socket: {}", e);
+            Err("Error while getting day start hour from socket".to_string())
+        }
+    }
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_week_screen_time(
+    date: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+) -> Result<vec::Vec<vec::Vec<Row>>, String> {
+    let date = parse_date(&date)?;
+    let day_start = get_day_start(time_zone.as_deref())?;
+    let csv_path = get_csv_path()?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+
+    match data_analysis::week_screen_time(
+        csv_path,
+        date,
+        day_start,
+        &alias_rules,
+        cipher.as_ref(),
+        host.as_deref(),
+    ) {
+        Ok(records) => Ok(records),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    }
+}
+// The screen time in each of the buckets of days, weeks or months from the date.
+#[tauri::command(rename_all = "snake_case")]
+fn get_range_screen_time(
+    date: String,
+    time_zone: Option<String>,
+    bucket: Bucket,
+    buckets: u32,
+    host: Option<String>,
+    applications: Option<Vec<String>>,
+) -> Result<vec::Vec<vec::Vec<Row>>, String> {
+    let range = DateRange {
+        start: parse_date(&date)?,
+        bucket,
+        buckets,
+        day_start: get_day_start(time_zone.as_deref())?,
+    };
+    let csv_path = get_csv_path()?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    let filter = RowFilter {
+        host: host.as_deref(),
+        applications: applications.as_deref(),
+    };
+    match data_analysis::range_screen_time(csv_path, &range, &filter, &alias_rules, cipher.as_ref())
+    {
+        Ok(records) => Ok(records),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    }
+}
+
+// The screen time of each day of the month the date is in, optionally only for some applications.
 #[tauri::command(rename_all = "snake_case")]
-fn get_week_screen_time(start_of_date: u64) -> Result<vec::Vec<vec::Vec<Row>>, String> {
+fn get_month_screen_time(
+    date: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+    applications: Option<Vec<String>>,
+) -> Result<vec::Vec<vec::Vec<Row>>, String> {
+    let date = parse_date(&date)?;
+    let day_start = get_day_start(time_zone.as_deref())?;
     let csv_path = get_csv_path()?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    let filter = RowFilter {
+        host: host.as_deref(),
+        applications: applications.as_deref(),
+    };
+    match data_analysis::month_screen_time(
+        csv_path,
+        date,
+        day_start,
+        &filter,
+        &alias_rules,
+        cipher.as_ref(),
+    ) {
+        Ok(records) => Ok(records),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    }
+}
 
-    match data_analysis::week_screen_time(csv_path, start_of_date) {
+// The screen time of each month of the year the date is in, optionally only for some applications.
+#[tauri::command(rename_all = "snake_case")]
+fn get_year_screen_time(
+    date: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+    applications: Option<Vec<String>>,
+) -> Result<vec::Vec<vec::Vec<Row>>, String> {
+    let date = parse_date(&date)?;
+    let day_start = get_day_start(time_zone.as_deref())?;
+    let csv_path = get_csv_path()?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    let filter = RowFilter {
+        host: host.as_deref(),
+        applications: applications.as_deref(),
+    };
+    match data_analysis::year_screen_time(
+        csv_path,
+        date,
+        day_start,
+        &filter,
+        &alias_rules,
+        cipher.as_ref(),
+    ) {
         Ok(records) => Ok(records),
         Err(e) => {
             println!("Error while reading csv: {}", e);
@@ -29,6 +219,71 @@ fn get_week_screen_time(start_of_date: u64) -> Result<vec::Vec<vec::Vec<Row>>, S
         }
     }
 }
+
+// Seconds of screen time in each hour of each weekday, Sunday first, over the weeks from the date.
+#[tauri::command(rename_all = "snake_case")]
+fn get_week_heatmap(
+    date: String,
+    time_zone: Option<String>,
+    weeks: Option<u32>,
+    host: Option<String>,
+    applications: Option<Vec<String>>,
+) -> Result<vec::Vec<vec::Vec<u64>>, String> {
+    let range = DateRange {
+        start: parse_date(&date)?,
+        bucket: Bucket::Week,
+        buckets: weeks.unwrap_or(1),
+        day_start: get_day_start(time_zone.as_deref())?,
+    };
+    let csv_path = get_csv_path()?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    let filter = RowFilter {
+        host: host.as_deref(),
+        applications: applications.as_deref(),
+    };
+    match data_analysis::heatmap(csv_path, &range, &filter, &alias_rules, cipher.as_ref()) {
+        Ok(heatmap) => Ok(heatmap),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    }
+}
+
+// The stretches of time spent in each application on the date, in order.
+#[tauri::command(rename_all = "snake_case")]
+fn get_date_timeline(
+    date: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+    applications: Option<Vec<String>>,
+) -> Result<vec::Vec<Segment>, String> {
+    let date = parse_date(&date)?;
+    let day_start = get_day_start(time_zone.as_deref())?;
+    let csv_path = get_csv_path()?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    let filter = RowFilter {
+        host: host.as_deref(),
+        applications: applications.as_deref(),
+    };
+    match data_analysis::date_timeline(
+        csv_path,
+        date,
+        day_start,
+        &filter,
+        &alias_rules,
+        cipher.as_ref(),
+    ) {
+        Ok(segments) => Ok(segments),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    }
+}
+
 #[tauri::command(rename_all = "snake_case")]
 fn send_get_health_check_message() -> Result<String, String> {
     match socket_comm::get_health_check_message() {
@@ -73,10 +328,25 @@ fn send_delete_months_data_message(months: u32) -> Result<(), String> {
 }
 
 #[tauri::command(rename_all = "snake_case")]
-fn get_date_screen_time(start_of_date: u64) -> Result<vec::Vec<Row>, String> {
+fn get_date_screen_time(
+    date: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+) -> Result<vec::Vec<Row>, String> {
+    println!("get_date_screen_time fn called with: {}", date);
+    let date = parse_date(&date)?;
+    let day_start = get_day_start(time_zone.as_deref())?;
     let csv_path = get_csv_path()?;
-    println!("get_date_screen_time fn called with: {}", start_of_date);
-    match data_analysis::date_screen_time(csv_path, start_of_date) {
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    match data_analysis::date_screen_time(
+        csv_path,
+        date,
+        day_start,
+        &alias_rules,
+        cipher.as_ref(),
+        host.as_deref(),
+    ) {
         Ok(records) => Ok(records),
         Err(e) => {
             println!("Error while reading csv: {}", e);
@@ -85,11 +355,350 @@ fn get_date_screen_time(start_of_date: u64) -> Result<vec::Vec<Row>, String> {
     }
 }
 
+// The categories file is kept next to the csv.
+fn get_category_rules(csv_path: &str) -> Result<CategoryRules, String> {
+    let rules_path = Path::new(csv_path).with_file_name(CATEGORIES_FILE_NAME);
+    match CategoryRules::load(&rules_path) {
+        Ok(category_rules) => Ok(category_rules),
+        Err(e) => {
+            println!("Error while loading categories: {}", e);
+            Err("Error while loading categories".to_string())
+        }
+    }
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_date_category_screen_time(
+    date: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+) -> Result<vec::Vec<CategoryTotal>, String> {
+    let date = parse_date(&date)?;
+    let day_start = get_day_start(time_zone.as_deref())?;
+    let csv_path = get_csv_path()?;
+    let category_rules = get_category_rules(&csv_path)?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    match data_analysis::date_rows(
+        csv_path,
+        date,
+        day_start,
+        &alias_rules,
+        cipher.as_ref(),
+        host.as_deref(),
+    ) {
+        Ok(rows) => Ok(categories::category_totals(&rows, &category_rules)),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    }
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_date_productivity_score(
+    date: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+) -> Result<ProductivityScore, String> {
+    let category_totals = get_date_category_screen_time(date, time_zone, host)?;
+    Ok(categories::productivity_score(&category_totals))
+}
+
+// The days from start to end, e.g. "2024-03-01" and "2024-03-31", inclusive.
+fn get_period(
+    start: &str,
+    end: &str,
+    day_start: DayStart<'static>,
+) -> Result<DateRange<'static>, String> {
+    match trends::period(parse_date(start)?, parse_date(end)?, day_start) {
+        Ok(period) => Ok(period),
+        Err(e) => {
+            println!("Error while reading period: {}", e);
+            Err("Invalid period".to_string())
+        }
+    }
+}
+
+// Each application's screen time in the current period against the previous one, the biggest movers first.
+#[tauri::command(rename_all = "snake_case")]
+fn compare_application_screen_time(
+    previous_start: String,
+    previous_end: String,
+    current_start: String,
+    current_end: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+    top: Option<usize>,
+) -> Result<vec::Vec<Comparison>, String> {
+    let day_start = get_day_start(time_zone.as_deref())?;
+    let previous_period = get_period(&previous_start, &previous_end, day_start)?;
+    let current_period = get_period(&current_start, &current_end, day_start)?;
+    let csv_path = get_csv_path()?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    let filter = RowFilter {
+        host: host.as_deref(),
+        ..Default::default()
+    };
+    let period_totals = |period: &DateRange| match data_analysis::range_rows(
+        csv_path.clone(),
+        period,
+        &filter,
+        &alias_rules,
+        cipher.as_ref(),
+    ) {
+        Ok(rows) => Ok(trends::application_totals(&rows)),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    };
+    let mut comparisons = trends::compare_totals(
+        &period_totals(&previous_period)?,
+        &period_totals(&current_period)?,
+    );
+    comparisons.truncate(top.unwrap_or(comparisons.len()));
+    Ok(comparisons)
+}
+
+// Each category's screen time in the current period against the previous one, the biggest movers first.
+#[tauri::command(rename_all = "snake_case")]
+fn compare_category_screen_time(
+    previous_start: String,
+    previous_end: String,
+    current_start: String,
+    current_end: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+    top: Option<usize>,
+) -> Result<vec::Vec<Comparison>, String> {
+    let day_start = get_day_start(time_zone.as_deref())?;
+    let previous_period = get_period(&previous_start, &previous_end, day_start)?;
+    let current_period = get_period(&current_start, &current_end, day_start)?;
+    let csv_path = get_csv_path()?;
+    let category_rules = get_category_rules(&csv_path)?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    let filter = RowFilter {
+        host: host.as_deref(),
+        ..Default::default()
+    };
+    let period_totals = |period: &DateRange| match data_analysis::range_rows(
+        csv_path.clone(),
+        period,
+        &filter,
+        &alias_rules,
+        cipher.as_ref(),
+    ) {
+        Ok(rows) => Ok(trends::category_durations(&categories::category_totals(
+            &rows,
+            &category_rules,
+        ))),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    };
+    let mut comparisons = trends::compare_totals(
+        &period_totals(&previous_period)?,
+        &period_totals(&current_period)?,
+    );
+    comparisons.truncate(top.unwrap_or(comparisons.len()));
+    Ok(comparisons)
+}
+
+// The screen time of each day from start to end with its rolling 7 and 28 day averages,
+// optionally only for some applications.
+#[tauri::command(rename_all = "snake_case")]
+fn get_rolling_averages(
+    start_date: String,
+    end_date: String,
+    time_zone: Option<String>,
+    host: Option<String>,
+    applications: Option<Vec<String>>,
+) -> Result<vec::Vec<RollingAverage>, String> {
+    let day_start = get_day_start(time_zone.as_deref())?;
+    let mut range = get_period(&start_date, &end_date, day_start)?;
+    let first_date = range.start;
+    // Read the days before the start too, so that the first days have full windows
+    range.start = match first_date.checked_sub_days(Days::new(LONGEST_ROLLING_WINDOW - 1)) {
+        Some(start) => start,
+        None => return Err("Invalid period".to_string()),
+    };
+    range.buckets += LONGEST_ROLLING_WINDOW as u32 - 1;
+    let csv_path = get_csv_path()?;
+    let alias_rules = get_alias_rules(&csv_path)?;
+    let cipher = get_cipher(&csv_path)?;
+    let filter = RowFilter {
+        host: host.as_deref(),
+        applications: applications.as_deref(),
+    };
+    match data_analysis::range_screen_time(csv_path, &range, &filter, &alias_rules, cipher.as_ref())
+    {
+        Ok(days) => {
+            let daily_totals: Vec<u64> = days
+                .iter()
+                .map(|day| day.iter().map(Row::duration).sum())
+                .collect();
+            Ok(trends::rolling_averages(first_date, &daily_totals))
+        }
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    }
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_raw_application_totals() -> Result<vec::Vec<ApplicationTotal>, String> {
+    let csv_path = get_csv_path()?;
+    let cipher = get_cipher(&csv_path)?;
+    match data_analysis::raw_application_totals(csv_path, cipher.as_ref()) {
+        Ok(totals) => Ok(totals),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    }
+}
+
+// Today's progress on each goal set in the daemon's GOALS, with its current and longest streaks.
+#[tauri::command(rename_all = "snake_case")]
+fn get_goals() -> Result<vec::Vec<socket_comm::GoalStatus>, String> {
+    match socket_comm::get_goals_message() {
+        Ok(goals) => Ok(goals),
+        Err(e) => {
+            println!("Error while getting goals from socket: {}", e);
+            Err("Error while getting goals from socket".to_string())
+        }
+    }
+}
+
+// Start a focus session of minutes in which only allowed_apps count as focused.
+#[tauri::command(rename_all = "snake_case")]
+fn start_focus_session(minutes: u64, allowed_apps: Vec<String>) -> Result<(), String> {
+    match socket_comm::start_focus_message(minutes, &allowed_apps) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            println!("Error while starting focus session: {}", e);
+            Err("Error while starting focus session".to_string())
+        }
+    }
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn stop_focus_session() -> Result<(), String> {
+    match socket_comm::stop_focus_message() {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            println!("Error while stopping focus session: {}", e);
+            Err("Error while stopping focus session".to_string())
+        }
+    }
+}
+
+// The running focus session, or None.
+#[tauri::command(rename_all = "snake_case")]
+fn get_focus_status() -> Result<Option<socket_comm::FocusStatus>, String> {
+    match socket_comm::get_focus_status_message() {
+        Ok(focus_status) => Ok(focus_status),
+        Err(e) => {
+            println!("Error while getting focus session from socket: {}", e);
+            Err("Error while getting focus session from socket".to_string())
+        }
+    }
+}
+
+// Every finished focus session, oldest first.
+#[tauri::command(rename_all = "snake_case")]
+fn get_focus_sessions() -> Result<vec::Vec<socket_comm::FocusSession>, String> {
+    match socket_comm::get_focus_sessions_message() {
+        Ok(sessions) => Ok(sessions),
+        Err(e) => {
+            println!("Error while getting focus sessions from socket: {}", e);
+            Err("Error while getting focus sessions from socket".to_string())
+        }
+    }
+}
+
+// The machines whose data has been merged into the csv, for the per-host views.
+#[tauri::command(rename_all = "snake_case")]
+fn get_hosts() -> Result<vec::Vec<String>, String> {
+    let csv_path = get_csv_path()?;
+    let cipher = get_cipher(&csv_path)?;
+    match data_analysis::hosts(csv_path, cipher.as_ref()) {
+        Ok(hosts) => Ok(hosts),
+        Err(e) => {
+            println!("Error while reading csv: {}", e);
+            Err("Error while reading csv".to_string())
+        }
+    }
+}
+
+// Export the data between two dates, e.g. "2024-03-01", and save it where the user chooses.
+// Returns the saved path, or None if the save dialog was cancelled.
+#[tauri::command(rename_all = "snake_case")]
+async fn export_data(
+    start_date: String,
+    end_date: String,
+    format: String,
+    aggregation: Option<String>,
+) -> Result<Option<String>, String> {
+    let mut export_request = format!("{} {} {}", start_date, end_date, format);
+    if let Some(aggregation) = &aggregation {
+        export_request = format!("{} {}", export_request, aggregation);
+    }
+    let exported = match socket_comm::export_data_message(export_request) {
+        Ok(exported) => exported,
+        Err(e) => {
+            println!("Error while sending message to socket: {}", e);
+            return Err("Error while exporting data".to_string());
+        }
+    };
+    // The blocking dialog is fine here as async commands do not run on the main thread
+    let save_path = FileDialogBuilder::new()
+        .set_file_name(&format!(
+            "screen_time_{}_{}.{}",
+            start_date, end_date, format
+        ))
+        .add_filter(&format.to_uppercase(), &[&format])
+        .save_file();
+    let Some(save_path) = save_path else {
+        return Ok(None);
+    };
+    match fs::write(&save_path, exported) {
+        Ok(_) => Ok(Some(save_path.display().to_string())),
+        Err(e) => {
+            println!("Error while saving export: {}", e);
+            Err("Error while saving export".to_string())
+        }
+    }
+}
+
 fn main() {
     tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
             get_date_screen_time,
+            get_date_category_screen_time,
+            get_date_productivity_score,
+            get_raw_application_totals,
+            get_hosts,
+            export_data,
             get_week_screen_time,
+            get_range_screen_time,
+            get_month_screen_time,
+            get_year_screen_time,
+            get_week_heatmap,
+            get_date_timeline,
+            compare_application_screen_time,
+            compare_category_screen_time,
+            get_rolling_averages,
+            get_goals,
+            start_focus_session,
+            stop_focus_session,
+            get_focus_status,
+            get_focus_sessions,
             send_update_socket_message,
             send_get_alert_screen_time_message,
             send_delete_months_data_message,
@@ -2,8 +2,11 @@ use std::error::Error;
 use std::io::prelude::*;
 use std::net::{Shutdown, TcpStream};
 
+use serde_derive::{Deserialize, Serialize};
+
 const SOCKET_ADDR: &str = "[::1]:12345";
 const ALERT_SCREEN_ENV_VAR: &str = "ALERT_SCREEN";
+const DAY_START_HOUR_ENV_VAR: &str = "DAY_START_HOUR";
 
 fn send_message_to_socket(message: String) -> Result<String, Box<dyn Error>> {
     let mut stream = TcpStream::connect(SOCKET_ADDR.to_string())?;
@@ -37,6 +40,12 @@ pub fn get_alert_screen_time_message() -> Result<u64, Box<dyn Error>> {
     let alert_screen_time: u64 = alert_screen_str.parse()?;
     Ok(alert_screen_time)
 }
+// The hour the daemon starts days at, 0 for midnight.
+pub fn get_day_start_hour_message() -> Result<u32, Box<dyn Error>> {
+    let day_start_hour_str = send_message_to_socket(DAY_START_HOUR_ENV_VAR.to_string())?;
+    let day_start_hour: u32 = day_start_hour_str.trim().parse()?;
+    Ok(day_start_hour)
+}
 pub fn delete_months_data_message(months: u32) -> Result<(), Box<dyn Error>> {
     let message = format!("DELETE {}", months);
     let response = send_message_to_socket(message)?;
@@ -49,3 +58,104 @@ pub fn delete_months_data_message(months: u32) -> Result<(), Box<dyn Error>> {
         Err("Unknown response".into())
     }
 }
+
+// Today's progress on a goal and its streaks, see the daemon's GOALS request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GoalStatus {
+    name: String,
+    //"at_most" or "at_least"
+    kind: String,
+    minutes: u64,
+    //Seconds used today
+    used_today: u64,
+    met_today: bool,
+    current_streak: u32,
+    longest_streak: u32,
+    at_risk: bool,
+}
+
+pub fn get_goals_message() -> Result<Vec<GoalStatus>, Box<dyn Error>> {
+    let received = send_message_to_socket("GOALS JSON".to_string())?;
+    if received.trim() == "Failure" {
+        return Err("Failed to get goals".into());
+    }
+    Ok(serde_json::from_str(&received)?)
+}
+
+// The running focus session, see the daemon's FOCUS request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FocusStatus {
+    allowed_apps: Vec<String>,
+    planned_minutes: u64,
+    //Seconds
+    remaining: u64,
+    focused: u64,
+    distracted: u64,
+    distractions: u32,
+}
+
+// A finished focus session, see the daemon's FOCUS SESSIONS request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FocusSession {
+    //Unix time in seconds
+    started: u64,
+    planned_minutes: u64,
+    //Joined with '+'
+    allowed_apps: String,
+    //Seconds
+    focused: u64,
+    distracted: u64,
+    distractions: u32,
+    completed: bool,
+}
+
+pub fn start_focus_message(minutes: u64, allowed_apps: &[String]) -> Result<(), Box<dyn Error>> {
+    let message = format!("FOCUS START {} {}", minutes, allowed_apps.join(","));
+    let response = send_message_to_socket(message)?;
+    if response.trim() == "Success" {
+        Ok(())
+    } else {
+        Err("Failed to start focus session".into())
+    }
+}
+
+pub fn stop_focus_message() -> Result<(), Box<dyn Error>> {
+    let response = send_message_to_socket("FOCUS STOP".to_string())?;
+    if response.trim() == "Success" {
+        Ok(())
+    } else {
+        Err("Failed to stop focus session".into())
+    }
+}
+
+pub fn get_focus_status_message() -> Result<Option<FocusStatus>, Box<dyn Error>> {
+    let received = send_message_to_socket("FOCUS JSON".to_string())?;
+    if received.trim() == "Failure" {
+        return Err("Failed to get focus session".into());
+    }
+    Ok(serde_json::from_str(&received)?)
+}
+
+pub fn get_focus_sessions_message() -> Result<Vec<FocusSession>, Box<dyn Error>> {
+    let received = send_message_to_socket("FOCUS SESSIONS JSON".to_string())?;
+    if received.trim() == "Failure" {
+        return Err("Failed to get focus sessions".into());
+    }
+    Ok(serde_json::from_str(&received)?)
+}
+
+// How the data file is encrypted: "None", "Keyfile <path>" or "Passphrase".
+pub fn get_encryption_message() -> Result<String, Box<dyn Error>> {
+    let received = send_message_to_socket("ENCRYPTION".to_string())?;
+    Ok(received)
+}
+
+// The data between two dates as csv, json or ndjson, see the daemon's EXPORT request.
+pub fn export_data_message(export_request: String) -> Result<String, Box<dyn Error>> {
+    let response = send_message_to_socket(format!("EXPORT {}", export_request))?;
+    if response.trim() == "Failure" {
+        Err("Failed to export data".into())
+    } else {
+        Ok(response)
+    }
+}
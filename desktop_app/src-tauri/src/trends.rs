@@ -0,0 +1,200 @@
+use chrono::{Days, NaiveDate};
+use serde_derive::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+
+use crate::categories::CategoryTotal;
+use crate::data_analysis::{Bucket, DateRange, DayStart, Row};
+
+// The longest rolling average, the days before a range that have to be read for it.
+pub const LONGEST_ROLLING_WINDOW: u64 = 28;
+
+// The change in the screen time of one application or category between two periods.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Comparison {
+    name: String,
+    //How long in seconds it was active in each period
+    previous: u64,
+    current: u64,
+    //Current minus previous, in seconds
+    change: i64,
+    //The change as a percentage of previous, absent if it was not used in the previous period
+    percentage_change: Option<f64>,
+}
+
+// Screen time on a date, with the daily average of the 7 and 28 days up to and including it.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RollingAverage {
+    date: String,
+    //Seconds
+    total: u64,
+    seven_day_average: u64,
+    twenty_eight_day_average: u64,
+}
+
+// The days from start to end, inclusive.
+pub fn period(
+    start: NaiveDate,
+    end: NaiveDate,
+    day_start: DayStart,
+) -> Result<DateRange, Box<dyn Error>> {
+    if end < start {
+        return Err(format!("The period ends on {} before it starts on {}", end, start).into());
+    }
+    Ok(DateRange {
+        start,
+        bucket: Bucket::Day,
+        buckets: (end - start).num_days() as u32 + 1,
+        day_start,
+    })
+}
+
+pub fn application_totals(rows: &[Row]) -> HashMap<String, u64> {
+    let mut totals = HashMap::new();
+    for row in rows {
+        *totals.entry(row.application().to_string()).or_default() += row.duration();
+    }
+    totals
+}
+
+pub fn category_durations(category_totals: &[CategoryTotal]) -> HashMap<String, u64> {
+    category_totals
+        .iter()
+        .map(|total| (total.category().to_string(), total.duration()))
+        .collect()
+}
+
+// Compare every name used in either period, the biggest movers first.
+pub fn compare_totals(
+    previous: &HashMap<String, u64>,
+    current: &HashMap<String, u64>,
+) -> Vec<Comparison> {
+    let names: BTreeSet<&String> = previous.keys().chain(current.keys()).collect();
+    let mut comparisons: Vec<Comparison> = names
+        .into_iter()
+        .map(|name| {
+            let previous = previous.get(name).copied().unwrap_or_default();
+            let current = current.get(name).copied().unwrap_or_default();
+            let change = current as i64 - previous as i64;
+            Comparison {
+                name: name.to_string(),
+                previous,
+                current,
+                change,
+                percentage_change: (previous > 0).then(|| change as f64 * 100.0 / previous as f64),
+            }
+        })
+        .collect();
+    // Names are already in order, so ties stay alphabetical
+    comparisons.sort_by_key(|comparison| Reverse(comparison.change.unsigned_abs()));
+    comparisons
+}
+
+// daily_totals starts LONGEST_ROLLING_WINDOW - 1 days before first_date, so that every date has a full window.
+pub fn rolling_averages(first_date: NaiveDate, daily_totals: &[u64]) -> Vec<RollingAverage> {
+    let average = |totals: &[u64]| totals.iter().sum::<u64>() / totals.len() as u64;
+    let first = LONGEST_ROLLING_WINDOW as usize - 1;
+    (first..daily_totals.len())
+        .map(|index| RollingAverage {
+            date: (first_date + Days::new((index - first) as u64)).to_string(),
+            total: daily_totals[index],
+            seven_day_average: average(&daily_totals[index - 6..=index]),
+            twenty_eight_day_average: average(&daily_totals[index - first..=index]),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn totals(totals: &[(&str, u64)]) -> HashMap<String, u64> {
+        totals
+            .iter()
+            .map(|(name, duration)| (name.to_string(), *duration))
+            .collect()
+    }
+
+    #[test]
+    fn biggest_movers_come_first() {
+        let comparisons = compare_totals(
+            &totals(&[("Code", 3600), ("Slack", 1800), ("Steam", 600)]),
+            &totals(&[("Code", 3000), ("Slack", 3600), ("Firefox", 900)]),
+        );
+        let changes: Vec<(&str, i64)> = comparisons
+            .iter()
+            .map(|comparison| (comparison.name.as_str(), comparison.change))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("Slack", 1800),
+                ("Firefox", 900),
+                // Ties stay alphabetical
+                ("Code", -600),
+                ("Steam", -600),
+            ]
+        );
+        assert_eq!(comparisons[0].percentage_change, Some(100.0));
+    }
+
+    #[test]
+    fn names_used_in_only_one_period() {
+        let comparisons = compare_totals(&totals(&[("Steam", 600)]), &totals(&[("Code", 300)]));
+        assert_eq!(
+            comparisons,
+            vec![
+                Comparison {
+                    name: "Steam".to_string(),
+                    previous: 600,
+                    current: 0,
+                    change: -600,
+                    percentage_change: Some(-100.0),
+                },
+                // No percentage from nothing
+                Comparison {
+                    name: "Code".to_string(),
+                    previous: 0,
+                    current: 300,
+                    change: 300,
+                    percentage_change: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn windows_count_the_days_before_the_first_recorded_one() {
+        let first_date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        // Nothing was recorded in the 27 days before the range
+        let mut daily_totals = vec![0; LONGEST_ROLLING_WINDOW as usize - 1];
+        daily_totals.extend([2800, 0, 700]);
+        let averages = rolling_averages(first_date, &daily_totals);
+        assert_eq!(
+            averages,
+            vec![
+                RollingAverage {
+                    date: "2024-03-01".to_string(),
+                    total: 2800,
+                    seven_day_average: 400,
+                    twenty_eight_day_average: 100,
+                },
+                RollingAverage {
+                    date: "2024-03-02".to_string(),
+                    total: 0,
+                    seven_day_average: 400,
+                    twenty_eight_day_average: 100,
+                },
+                RollingAverage {
+                    date: "2024-03-03".to_string(),
+                    total: 700,
+                    seven_day_average: 500,
+                    twenty_eight_day_average: 125,
+                },
+            ]
+        );
+        // Too few days for a window
+        assert!(rolling_averages(first_date, &[100; 27]).is_empty());
+    }
+}
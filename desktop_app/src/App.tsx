@@ -29,6 +29,15 @@ export interface Row {
   timestamp: number;
   application: string;
   duration: number;
+  host: string;
+  utc_offset?: number;
+}
+interface ProductivityScore {
+  //0 when all time was distracting, 100 when all time was productive
+  score: number;
+  productive: number;
+  neutral: number;
+  distracting: number;
 }
 interface Chart {
   labels: string[];
@@ -59,6 +68,13 @@ function formatDate(date: Date | undefined) {
   const year: number = date.getFullYear();
   return `${month} ${day}, ${year}`;
 }
+// The calendar date, e.g. "2024-03-01", which the backend splits into days in timeZone.
+function toCalendarDate(date: Date) {
+  const month = String(date.getMonth() + 1).padStart(2, "0");
+  const day = String(date.getDate()).padStart(2, "0");
+  return `${date.getFullYear()}-${month}-${day}`;
+}
+const timeZone = Intl.DateTimeFormat().resolvedOptions().timeZone;
 function pickerValueToDate(pickerValue: PickerValue) {
   const date = pickerValue.value.selectedDateObject;
   if (date === undefined) return;
@@ -159,6 +175,8 @@ function App() {
   const [deleteConfirm, setDeleteConfirm] = createSignal<boolean>(false);
   const [chartSet, setChartSet] = createSignal<boolean>(false);
   const [records, setRecords] = createSignal<Row[]>([]);
+  const [productivityScore, setProductivityScore] =
+    createSignal<ProductivityScore>();
   //An array of size 7, first element is Sunday
   const [weekRecords, setWeekRecords] = createSignal<Row[][]>([[]]);
   const [alertScreenTime, setAlertScreenTime] = createSignal<number>(0);
@@ -307,15 +325,27 @@ function App() {
     }
   }
 
+  // Machines merged into the data file, an empty host shows them all combined
+  const [hosts, setHosts] = createSignal<string[]>([]);
+  const [host, setHost] = createSignal<string>("");
+  async function getHosts() {
+    try {
+      setHosts(await invoke("get_hosts"));
+    } catch (e) {
+      console.log("Error fetching hosts");
+      console.log(e);
+    }
+  }
+
   async function getWeekScreenTime() {
     const start_of_week = weekDate();
     if (start_of_week === undefined) return;
-    start_of_week.setHours(0, 0, 0, 0);
-    const start_of_date_secs = Math.floor(start_of_week.getTime() / 1000);
     try {
       setWeekRecords(
         await invoke("get_week_screen_time", {
-          start_of_date: start_of_date_secs,
+          date: toCalendarDate(start_of_week),
+          time_zone: timeZone,
+          host: host() || null,
         }),
       );
       setWeekChartData();
@@ -337,13 +367,13 @@ function App() {
   async function getDateScreenTime() {
     const start_of_date = pickerValueToDate(date());
     if (start_of_date === undefined) return;
-    start_of_date.setHours(0, 0, 0, 0);
-    const start_of_date_secs = Math.floor(start_of_date.getTime() / 1000);
 
     try {
       setRecords(
         await invoke("get_date_screen_time", {
-          start_of_date: start_of_date_secs,
+          date: toCalendarDate(start_of_date),
+          time_zone: timeZone,
+          host: host() || null,
         }),
       );
       setChartData();
@@ -353,6 +383,23 @@ function App() {
       console.log(e);
     }
   }
+  async function getDateProductivityScore() {
+    const start_of_date = pickerValueToDate(date());
+    if (start_of_date === undefined) return;
+
+    try {
+      setProductivityScore(
+        await invoke("get_date_productivity_score", {
+          date: toCalendarDate(start_of_date),
+          time_zone: timeZone,
+          host: host() || null,
+        }),
+      );
+    } catch (e) {
+      console.log("Error fetching productivity score");
+      console.log(e);
+    }
+  }
   const handleChange = (e: any) => {
     const input = e.target.value;
     setDeleteMonths(input);
@@ -383,6 +430,11 @@ function App() {
       await getDateScreenTime();
     }
   });
+  createEffect(async () => {
+    if (updated()) {
+      await getDateProductivityScore();
+    }
+  });
   createEffect(async () => {
     await getAlertScreenTime();
   });
@@ -390,6 +442,7 @@ function App() {
   onMount(async () => {
     await getHealthCheckStatus();
     await updateScreenTime();
+    await getHosts();
     setUpdated(true);
   });
   return (
@@ -399,6 +452,18 @@ function App() {
         <TabsTrigger value="week">Week</TabsTrigger>
         <TabsTrigger value="other">Other</TabsTrigger>
       </TabsList>
+      {hosts().length > 1 && (
+        <select
+          class="rounded-md border bg-background px-3 py-2 text-sm"
+          value={host()}
+          onChange={(e) => setHost(e.currentTarget.value)}
+        >
+          <option value="">All machines</option>
+          {hosts().map((hostName) => (
+            <option value={hostName}>{hostName}</option>
+          ))}
+        </select>
+      )}
 
       <TabsContent value="day" class="space-y-4">
         <div class="grid gap-4 md:grid-cols-2 lg:grid-cols-4">
@@ -430,6 +495,21 @@ function App() {
               </div>
             </CardContent>
           </Card>
+          <Card>
+            <CardHeader class="flex flex-row items-center justify-between space-y-0 pb-2">
+              <CardTitle class="text-sm font-medium">
+                Productivity Score
+              </CardTitle>
+            </CardHeader>
+            <CardContent>
+              <div class="text-2xl font-bold">
+                {productivityScore()?.score ?? 0}
+              </div>
+              <p class="text-xs text-muted-foreground">
+                {formatMinutes(productivityScore()?.productive ?? 0)} productive
+              </p>
+            </CardContent>
+          </Card>
         </div>
         <Grid colsMd={2} colsLg={7} class="gap-4">
           <Card class="col-span-4">